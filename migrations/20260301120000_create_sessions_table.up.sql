@@ -0,0 +1,16 @@
+create table if not exists "sessions" (
+    "token_hash" text not null primary key,
+    "id" uuid not null unique,
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "ip" text,
+    "user_agent" text,
+    "auth_method" text not null,
+    "kind" text not null,
+    "created_at" timestamp with time zone not null,
+    "last_seen_at" timestamp with time zone not null,
+    "expires_at" timestamp with time zone not null,
+    "idle_expires_at" timestamp with time zone not null
+);
+
+create index if not exists "sessions_user_id_idx" on "sessions" ("user_id");
+create index if not exists "sessions_idle_expires_at_idx" on "sessions" ("idle_expires_at");
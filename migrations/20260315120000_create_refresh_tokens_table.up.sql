@@ -0,0 +1,8 @@
+create table if not exists "refresh_tokens" (
+    "token_hash" text not null primary key,
+    "session_token_hash" text not null references "sessions" ("token_hash") on delete cascade,
+    "created_at" timestamp with time zone not null default now(),
+    "used_at" timestamp with time zone
+);
+
+create index if not exists "refresh_tokens_session_token_hash_idx" on "refresh_tokens" ("session_token_hash");
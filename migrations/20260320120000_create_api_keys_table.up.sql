@@ -0,0 +1,13 @@
+create table if not exists "api_keys" (
+    "id" uuid not null primary key,
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "name" text not null,
+    "prefix" text not null unique,
+    "key_hash" text not null unique,
+    "scopes" text[] not null default '{}',
+    "expires_at" timestamp with time zone,
+    "last_used_at" timestamp with time zone,
+    "created_at" timestamp with time zone not null default now()
+);
+
+create index if not exists "api_keys_user_id_idx" on "api_keys" ("user_id");
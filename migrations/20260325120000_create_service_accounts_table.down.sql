@@ -0,0 +1 @@
+drop table if exists "service_accounts";
\ No newline at end of file
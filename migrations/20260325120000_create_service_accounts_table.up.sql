@@ -0,0 +1,8 @@
+create table if not exists "service_accounts" (
+    "id" uuid not null primary key,
+    "client_id" text not null unique,
+    "secret_hash" text not null,
+    "name" text not null,
+    "scopes" text[] not null default '{}',
+    "created_at" timestamp with time zone not null default now()
+);
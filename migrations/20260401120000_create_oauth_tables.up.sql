@@ -0,0 +1,33 @@
+create table if not exists "oauth_clients" (
+    "id" uuid not null primary key,
+    "client_id" text not null unique,
+    -- null for public clients, which authenticate with PKCE alone
+    "secret_hash" text,
+    "name" text not null,
+    "redirect_uris" text[] not null,
+    "scopes" text[] not null default '{}',
+    "created_at" timestamp with time zone not null default now()
+);
+
+create table if not exists "oauth_consents" (
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "client_id" text not null references "oauth_clients" ("client_id") on delete cascade,
+    "scopes" text[] not null default '{}',
+    "granted_at" timestamp with time zone not null default now(),
+    primary key ("user_id", "client_id")
+);
+
+create table if not exists "oauth_authorization_codes" (
+    "code_hash" text not null primary key,
+    "client_id" text not null references "oauth_clients" ("client_id") on delete cascade,
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "session_id" uuid not null,
+    "redirect_uri" text not null,
+    "scopes" text[] not null default '{}',
+    "code_challenge" text not null,
+    "expires_at" timestamp with time zone not null,
+    "used_at" timestamp with time zone
+);
+
+create index if not exists "oauth_authorization_codes_expires_at_idx"
+    on "oauth_authorization_codes" ("expires_at");
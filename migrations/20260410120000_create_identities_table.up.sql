@@ -0,0 +1,10 @@
+create table if not exists "identities" (
+    "id" uuid not null primary key,
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "provider" text not null,
+    "subject" text not null,
+    "email" text not null,
+    "created_at" timestamp with time zone not null default now(),
+    unique ("provider", "subject"),
+    unique ("user_id", "provider")
+);
@@ -0,0 +1,13 @@
+create table if not exists "email_tokens" (
+    "token_hash" text not null primary key,
+    "purpose" text not null,
+    "user_id" uuid not null references "users" ("id") on delete cascade,
+    "email" text not null,
+    "browser_secret_hash" text,
+    "expires_at" timestamp with time zone not null,
+    "used_at" timestamp with time zone,
+    "created_at" timestamp with time zone not null default now()
+);
+
+create index if not exists "email_tokens_user_id_purpose_idx" on "email_tokens" ("user_id", "purpose", "created_at");
+create index if not exists "email_tokens_expires_at_idx" on "email_tokens" ("expires_at");
@@ -0,0 +1,5 @@
+alter table "users" add column if not exists "email_verified_at" timestamp with time zone;
+
+-- Accounts created before verification existed are trusted as they are,
+-- the "block" policy would lock every one of them out otherwise.
+update "users" set "email_verified_at" = "created_at" where "email_verified_at" is null;
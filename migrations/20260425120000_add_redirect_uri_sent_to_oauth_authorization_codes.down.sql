@@ -0,0 +1 @@
+alter table "oauth_authorization_codes" drop column if exists "redirect_uri_sent";
@@ -0,0 +1,2 @@
+-- whether the authorization request named the redirect URI, the token request must then repeat it
+alter table "oauth_authorization_codes" add column if not exists "redirect_uri_sent" boolean not null default true;
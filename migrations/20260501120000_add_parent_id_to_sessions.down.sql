@@ -0,0 +1,3 @@
+drop index if exists "sessions_parent_id_idx";
+
+alter table "sessions" drop column if exists "parent_id";
@@ -0,0 +1,3 @@
+alter table "sessions" add column if not exists "parent_id" uuid references "sessions" ("id") on delete cascade;
+
+create index if not exists "sessions_parent_id_idx" on "sessions" ("parent_id");
@@ -0,0 +1 @@
+alter table "sessions" add column if not exists "client_id" text;
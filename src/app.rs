@@ -1,31 +1,71 @@
-use crate::config::AppConfig;
-use crate::delivery_grpc::users_delivery::UsersDeliveryGRPC;
+use crate::config::{AppConfig, SessionBackend};
+use crate::delivery_grpc::service_auth::ServiceAuthInterceptor;
 use crate::delivery_grpc::users_delivery::auth::users_provider_server::UsersProviderServer;
-use crate::delivery_http::dto::{LoginRequest, RegisterRequest, UpdateUserRequest};
-use crate::delivery_http::users_delivery::{IUsersRepo, UsersDelivery};
+use crate::delivery_grpc::users_delivery::{IUserIDGetter, UsersDeliveryGRPC};
+use crate::delivery_http::bearer::BearerToken;
+use crate::delivery_http::calling_service::CallingService;
+use crate::delivery_http::client_auth::BasicCredentials;
+use crate::delivery_http::client_info::ClientInfo;
+use crate::delivery_http::dto::{
+    AuthorizeForm, AuthorizeRequest, ChangeEmailRequest, ChangePasswordRequest,
+    CreateApiKeyRequest, EmailRevertToken, ExternalLoginCallback, ExternalLoginRequest,
+    ForgotPasswordRequest, LoginRequest, MagicLinkRequest, MagicLinkToken, OAuthTokenActionRequest,
+    OAuthTokenRequest, RefreshTokenRequest, RegisterRequest, ResendVerificationRequest,
+    ResetPasswordRequest, UpdateUserRequest, VerifyEmailRequest,
+};
+use crate::delivery_http::email_verification_delivery::{
+    EMAIL_REVERT_PATH, EmailVerificationDelivery,
+};
+use crate::delivery_http::external_login_delivery::ExternalLoginDelivery;
+use crate::delivery_http::magic_link_delivery::{MAGIC_LINK_PATH, MagicLinkDelivery};
+use crate::delivery_http::oauth_delivery::OAuthDelivery;
+use crate::delivery_http::password_reset_delivery::PasswordResetDelivery;
+use crate::delivery_http::session_cookie::SessionCookieCodec;
+use crate::delivery_http::tokens_delivery::TokensDelivery;
+use crate::delivery_http::users_delivery::{ISessionStore, IUsersRepo, UsersDelivery};
 use crate::errors::ApiError;
 use crate::handlers::{
-    create_user, delete_user, get_user, get_user_from_cookie, login, logout, update_user,
+    calling_service, change_password, confirm_email_change, confirm_magic_link, create_api_key,
+    create_user, delete_api_key, delete_session, delete_user, external_login,
+    external_login_callback, forgot_password, get_user, get_user_from_cookie, jwks, list_api_keys,
+    list_oauth_consents, list_sessions, login, logout, logout_all, oauth_authorize,
+    oauth_authorize_submit, oauth_introspect, oauth_revoke, oauth_token, openid_configuration,
+    refresh_token, request_email_change, request_magic_link, resend_email_verification,
+    reset_password, revert_email, revert_email_page, revoke_oauth_consent, update_user, userinfo,
+    verify_email, verify_magic_link,
 };
+use crate::infra::mailer::{IMailer, LogMailer, SmtpMailer};
 use crate::infra::postgres::PGPool;
 use crate::infra::redis::RedisPool;
+use crate::jwt::AccessTokenIssuer;
+use crate::repo::api_keys_repo::ApiKeysRepo;
+use crate::repo::email_tokens_repo::EmailTokensRepo;
+use crate::repo::identities_repo::IdentitiesRepo;
+use crate::repo::memory_sessions::MemorySessionsRepo;
+use crate::repo::oauth_repo::OAuthRepo;
+use crate::repo::pg_sessions::PgSessionsRepo;
+use crate::repo::service_accounts_repo::ServiceAccountsRepo;
 use crate::repo::sessions::SessionsRepo;
 use crate::repo::users_repo::UsersRepo;
+use crate::tokens::TokenHasher;
+use crate::usecase::email_links::{EmailLinkUrls, EmailLinks};
 use crate::usecase::users_usecase::{IUsersRepository, UserUsecase};
 use async_trait::async_trait;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
 use axum::http::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ORIGIN};
 use axum::http::{HeaderName, HeaderValue, Method};
 use axum::response::Response;
 use axum::routing::{delete, get, post, put};
-use axum::{Json, Router};
+use axum::{Form, Json, Router};
 use axum_extra::extract::CookieJar;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::process;
 use std::sync::Arc;
+use std::time::Duration;
 use tonic::transport::Server;
 use tonic::transport::server::Router as grpc_router;
 use tower_http::cors::CorsLayer;
+use url::Url;
 use uuid::Uuid;
 
 #[async_trait]
@@ -33,6 +73,8 @@ pub trait IUsersDelivery: Send + Sync {
     async fn create_user(
         &self,
         jar: CookieJar,
+        bearer: BearerToken,
+        client: ClientInfo,
         payload: Json<RegisterRequest>,
     ) -> Result<Response, ApiError>;
     async fn get_user(&self, payload: Path<Uuid>) -> Result<Response, ApiError>;
@@ -45,14 +87,170 @@ pub trait IUsersDelivery: Send + Sync {
     async fn login(
         &self,
         jar: CookieJar,
+        bearer: BearerToken,
+        client: ClientInfo,
         payload: Json<LoginRequest>,
     ) -> Result<Response, ApiError>;
-    async fn logout(&self, jar: CookieJar) -> Result<Response, ApiError>;
-    async fn get_user_from_cookie(&self, jar: CookieJar) -> Result<Response, ApiError>;
+    async fn logout(&self, jar: CookieJar, bearer: BearerToken) -> Result<Response, ApiError>;
+    async fn logout_all(&self, jar: CookieJar, bearer: BearerToken) -> Result<Response, ApiError>;
+    async fn get_user_from_cookie(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError>;
+    async fn change_password(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        payload: Json<ChangePasswordRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn request_email_change(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        payload: Json<ChangeEmailRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn list_sessions(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError>;
+    async fn delete_session(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        session_id: Path<Uuid>,
+    ) -> Result<Response, ApiError>;
+    async fn create_api_key(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        payload: Json<CreateApiKeyRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn list_api_keys(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError>;
+    async fn delete_api_key(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        key_id: Path<Uuid>,
+    ) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait ITokensDelivery: Send + Sync {
+    async fn jwks(&self) -> Result<Response, ApiError>;
+    async fn refresh(&self, payload: Json<RefreshTokenRequest>) -> Result<Response, ApiError>;
+    async fn token(
+        &self,
+        basic: BasicCredentials,
+        payload: Form<OAuthTokenRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn introspect(
+        &self,
+        basic: BasicCredentials,
+        payload: Form<OAuthTokenActionRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn revoke(
+        &self,
+        basic: BasicCredentials,
+        payload: Form<OAuthTokenActionRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn openid_configuration(&self) -> Result<Response, ApiError>;
+    async fn userinfo(&self, bearer: BearerToken) -> Result<Response, ApiError>;
+    async fn calling_service(&self, caller: CallingService) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait IOAuthDelivery: Send + Sync {
+    async fn authorize(
+        &self,
+        jar: CookieJar,
+        request: Query<AuthorizeRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn authorize_submit(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        form: Form<AuthorizeForm>,
+    ) -> Result<Response, ApiError>;
+    async fn list_consents(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError>;
+    async fn revoke_consent(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        client_id: Path<String>,
+    ) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait IExternalLoginDelivery: Send + Sync {
+    async fn start(
+        &self,
+        jar: CookieJar,
+        provider: Path<String>,
+        request: Query<ExternalLoginRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn callback(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        provider: Path<String>,
+        callback: Query<ExternalLoginCallback>,
+    ) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait IMagicLinkDelivery: Send + Sync {
+    async fn request(
+        &self,
+        jar: CookieJar,
+        payload: Json<MagicLinkRequest>,
+    ) -> Result<Response, ApiError>;
+    async fn confirm(&self, link: Query<MagicLinkToken>) -> Result<Response, ApiError>;
+    async fn verify(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        link: Form<MagicLinkToken>,
+    ) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait IEmailVerificationDelivery: Send + Sync {
+    async fn verify(&self, payload: Json<VerifyEmailRequest>) -> Result<Response, ApiError>;
+    async fn resend(&self, payload: Json<ResendVerificationRequest>) -> Result<Response, ApiError>;
+    async fn confirm_change(&self, payload: Json<VerifyEmailRequest>)
+    -> Result<Response, ApiError>;
+    async fn revert_page(&self, link: Query<EmailRevertToken>) -> Result<Response, ApiError>;
+    async fn revert(&self, link: Form<EmailRevertToken>) -> Result<Response, ApiError>;
+}
+
+#[async_trait]
+pub trait IPasswordResetDelivery: Send + Sync {
+    async fn forgot(&self, payload: Json<ForgotPasswordRequest>) -> Result<Response, ApiError>;
+    async fn reset(&self, payload: Json<ResetPasswordRequest>) -> Result<Response, ApiError>;
 }
 
 pub struct AuthApp {
     pub http_delivery: Arc<dyn IUsersDelivery>,
+    pub tokens_delivery: Arc<dyn ITokensDelivery>,
+    pub oauth_delivery: Arc<dyn IOAuthDelivery>,
+    pub external_login_delivery: Arc<dyn IExternalLoginDelivery>,
+    pub magic_link_delivery: Arc<dyn IMagicLinkDelivery>,
+    pub email_verification_delivery: Arc<dyn IEmailVerificationDelivery>,
+    pub password_reset_delivery: Arc<dyn IPasswordResetDelivery>,
+    /// Peers [`ClientInfo`] takes the client address from proxy headers of.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Verifies service tokens for [`CallingService`].
+    pub access_tokens: Option<Arc<AccessTokenIssuer>>,
 }
 
 impl AuthApp {
@@ -65,46 +263,235 @@ impl AuthApp {
             }
         };
 
-        let redis_pool = match RedisPool::new(config.redis_conn_string) {
-            Ok(pool) => pool,
-            Err(e) => {
-                eprintln!("error getting redis pool {e}");
-                process::exit(1);
+        let hasher = TokenHasher::new(config.session_token_key);
+        let api_keys = Arc::new(ApiKeysRepo::new(pool.clone(), hasher.clone()));
+        let service_accounts = Arc::new(ServiceAccountsRepo::new(pool.clone(), hasher.clone()));
+        let oauth = Arc::new(OAuthRepo::new(pool.clone(), hasher.clone()));
+        let identities = Arc::new(IdentitiesRepo::new(pool.clone()));
+        let email_tokens = Arc::new(EmailTokensRepo::new(pool.clone(), hasher.clone()));
+
+        let mailer: Arc<dyn IMailer> = match &config.mail.smtp_url {
+            Some(url) => match SmtpMailer::new(url, &config.mail.from) {
+                Ok(mailer) => Arc::new(mailer),
+                Err(e) => {
+                    eprintln!("error configuring smtp: {e}");
+                    process::exit(1);
+                }
+            },
+            None => {
+                println!(
+                    "mail log only mode, emails are printed to stdout, do not use in production"
+                );
+                Arc::new(LogMailer)
             }
         };
 
-        let session_repo = Arc::new(SessionsRepo::new(redis_pool));
+        let (session_store, session_getter) = match config.session_backend {
+            SessionBackend::Redis => {
+                let redis_url = config.redis_conn_string.unwrap_or_default();
+                let redis_pool = match RedisPool::new(redis_url) {
+                    Ok(pool) => pool,
+                    Err(e) => {
+                        eprintln!("error getting redis pool {e}");
+                        process::exit(1);
+                    }
+                };
+
+                let session_repo = Arc::new(SessionsRepo::new(
+                    redis_pool,
+                    config.session,
+                    hasher,
+                    config.session_key_prefix,
+                ));
+
+                if config.session_migrate_keys {
+                    match session_repo.migrate_legacy_keys().await {
+                        Ok(migrated) => {
+                            println!("migrated {migrated} sessions to the prefixed key layout")
+                        }
+                        Err(e) => {
+                            eprintln!("error migrating session keys: {e}");
+                            process::exit(1);
+                        }
+                    }
+                }
+
+                session_backend(session_repo)
+            }
+            SessionBackend::Postgres => {
+                let session_repo =
+                    Arc::new(PgSessionsRepo::new(pool.clone(), config.session, hasher));
+                session_repo
+                    .clone()
+                    .spawn_cleanup(Duration::from_secs(config.session_cleanup_interval));
 
-        let sessions_for_grpc = session_repo.clone();
-        let session_for_id_getter = session_repo.clone();
+                session_backend(session_repo)
+            }
+            SessionBackend::Memory => {
+                println!("using in-memory session store, sessions are lost on restart");
+                session_backend(Arc::new(MemorySessionsRepo::new(config.session, hasher)))
+            }
+        };
 
         let repo = Arc::new(UsersRepo::new(pool));
 
         let repo_for_usecase: Arc<dyn IUsersRepository> = repo.clone();
         let repo_for_delivery: Arc<dyn IUsersRepo> = repo.clone();
 
-        let usecase = UserUsecase::new(repo_for_usecase);
+        let service_url = |path: String| match Url::parse(&format!("{}{path}", config.public_url)) {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("error parsing public url {}: {e}", config.public_url);
+                process::exit(1);
+            }
+        };
+        let link_urls = EmailLinkUrls {
+            verification: config.email_verification_url,
+            email_change: config.email_change_url,
+            email_revert: service_url(EMAIL_REVERT_PATH.to_string()),
+            password_reset: config.password_reset_url,
+            magic_link: service_url(format!("{MAGIC_LINK_PATH}/verify")),
+        };
+
+        let links = Arc::new(EmailLinks::new(
+            email_tokens.clone(),
+            repo.clone(),
+            mailer,
+            link_urls,
+        ));
+
+        let usecase = Arc::new(UserUsecase::new(
+            repo_for_usecase,
+            links.clone(),
+            config.email_verification,
+        ));
+
+        let email_verification_delivery = Arc::new(EmailVerificationDelivery::new(
+            email_tokens.clone(),
+            repo.clone(),
+            usecase.clone(),
+            session_store.clone(),
+            api_keys.clone(),
+            links.clone(),
+        ));
+
+        let mut cookies = SessionCookieCodec::new(
+            config.cookie_key.as_bytes(),
+            config.cookie_key_previous.as_deref().map(str::as_bytes),
+        );
+        if config.session_migrate_keys {
+            cookies = cookies.accept_legacy_values();
+        }
+
+        let access_tokens = config.jwt.map(|jwt| {
+            match AccessTokenIssuer::from_pem(
+                &jwt.signing_key,
+                jwt.signing_key_previous.as_deref(),
+                jwt.id_token_signing_key.as_deref(),
+                jwt.issuer,
+                jwt.access_token_ttl,
+            ) {
+                Ok(issuer) => Arc::new(issuer),
+                Err(e) => {
+                    eprintln!("error loading jwt signing key: {e}");
+                    process::exit(1);
+                }
+            }
+        });
 
         let delivery = Arc::new(UsersDelivery::new(
             repo_for_delivery,
-            Arc::new(usecase),
-            session_repo,
-            session_for_id_getter,
+            usecase.clone(),
+            session_store.clone(),
+            session_getter.clone(),
+            api_keys.clone(),
+            cookies.clone(),
+            access_tokens.clone(),
+        ));
+
+        let tokens_delivery = Arc::new(TokensDelivery::new(
+            repo.clone(),
+            session_store.clone(),
+            api_keys.clone(),
+            service_accounts,
+            oauth.clone(),
+            access_tokens.clone(),
+        ));
+
+        let password_reset_delivery = Arc::new(PasswordResetDelivery::new(
+            email_tokens.clone(),
+            repo.clone(),
+            usecase.clone(),
+            session_store.clone(),
+            links.clone(),
+        ));
+
+        let magic_link_delivery = Arc::new(MagicLinkDelivery::new(
+            email_tokens,
+            repo.clone(),
+            session_store.clone(),
+            links,
+            cookies.clone(),
+            config.login_redirect_url,
+        ));
+
+        let oauth_delivery = Arc::new(OAuthDelivery::new(
+            oauth,
+            usecase.clone(),
+            session_store.clone(),
+            session_getter.clone(),
+            cookies.clone(),
         ));
 
-        let grpc_auth = UsersDeliveryGRPC::new(sessions_for_grpc);
+        let external_login_delivery = match ExternalLoginDelivery::new(
+            config.external_login.unwrap_or_default(),
+            identities,
+            usecase,
+            session_store,
+            session_getter.clone(),
+            cookies.clone(),
+        ) {
+            Ok(delivery) => Arc::new(delivery),
+            Err(e) => {
+                eprintln!("error parsing identity provider url: {e}");
+                process::exit(1);
+            }
+        };
 
-        let grpc_router = Server::builder().add_service(UsersProviderServer::new(grpc_auth));
+        let grpc_auth = UsersDeliveryGRPC::new(session_getter, api_keys, cookies);
+
+        let service_auth = ServiceAuthInterceptor::new(access_tokens.clone());
+
+        let grpc_router = Server::builder().add_service(UsersProviderServer::with_interceptor(
+            grpc_auth,
+            service_auth,
+        ));
 
         (
             AuthApp {
                 http_delivery: delivery,
+                tokens_delivery,
+                oauth_delivery,
+                external_login_delivery,
+                magic_link_delivery,
+                email_verification_delivery,
+                password_reset_delivery,
+                trusted_proxies: config.trusted_proxies,
+                access_tokens,
             },
             grpc_router,
         )
     }
 }
 
+/// Exposes a single session backend through both traits it implements.
+fn session_backend<S>(store: Arc<S>) -> (Arc<dyn ISessionStore>, Arc<dyn IUserIDGetter>)
+where
+    S: ISessionStore + IUserIDGetter + 'static,
+{
+    (store.clone(), store)
+}
+
 pub fn init_router(state: Arc<AuthApp>) -> Router {
     let origins = [
         "http://localhost:3000".parse::<HeaderValue>().unwrap(),
@@ -153,8 +540,56 @@ pub fn init_router(state: Arc<AuthApp>) -> Router {
         .route("/api/v1/users/{id}", put(update_user))
         .route("/api/v1/users/{id}", delete(delete_user))
         .route("/api/v1/users/profile", get(get_user_from_cookie))
+        .route("/api/v1/users/me/password", put(change_password))
+        .route("/api/v1/users/me/email", post(request_email_change))
         .route("/api/v1/login", post(login))
+        .route("/api/v1/login/magic-link", post(request_magic_link))
+        .route(
+            "/api/v1/login/magic-link/verify",
+            get(confirm_magic_link).post(verify_magic_link),
+        )
+        .route("/api/v1/login/{provider}", get(external_login))
+        .route(
+            "/api/v1/login/{provider}/callback",
+            get(external_login_callback),
+        )
+        .route("/api/v1/email/verify", post(verify_email))
+        .route(
+            "/api/v1/email/verify/resend",
+            post(resend_email_verification),
+        )
+        .route("/api/v1/email/change/confirm", post(confirm_email_change))
+        .route(
+            "/api/v1/email/revert",
+            get(revert_email_page).post(revert_email),
+        )
+        .route("/api/v1/password/forgot", post(forgot_password))
+        .route("/api/v1/password/reset", post(reset_password))
         .route("/api/v1/logout", post(logout))
+        .route("/api/v1/logout/all", post(logout_all))
+        .route("/api/v1/sessions", get(list_sessions))
+        .route("/api/v1/sessions/{id}", delete(delete_session))
+        .route("/api/v1/api-keys", post(create_api_key))
+        .route("/api/v1/api-keys", get(list_api_keys))
+        .route("/api/v1/api-keys/{id}", delete(delete_api_key))
+        .route("/api/v1/token/refresh", post(refresh_token))
+        .route("/api/v1/service/me", get(calling_service))
+        .route("/api/v1/oauth/consents", get(list_oauth_consents))
+        .route(
+            "/api/v1/oauth/consents/{client_id}",
+            delete(revoke_oauth_consent),
+        )
+        .route("/oauth/authorize", get(oauth_authorize))
+        .route("/oauth/authorize", post(oauth_authorize_submit))
+        .route("/oauth/token", post(oauth_token))
+        .route("/oauth/introspect", post(oauth_introspect))
+        .route("/oauth/revoke", post(oauth_revoke))
+        .route("/userinfo", get(userinfo).post(userinfo))
+        .route("/.well-known/jwks.json", get(jwks))
+        .route(
+            "/.well-known/openid-configuration",
+            get(openid_configuration),
+        )
         .with_state(state)
         .layer(cors)
 }
@@ -169,7 +604,10 @@ pub async fn serve(
     let addr = format!("{}:{}", host, port);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
 
-    let http_future = axum::serve(listener, router);
+    let http_future = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    );
     let grpc_future = grpc_router.serve(grpc_addr);
 
     match tokio::join!(http_future, grpc_future) {
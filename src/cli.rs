@@ -0,0 +1,123 @@
+use crate::config::AppConfig;
+use crate::infra::postgres::PGPool;
+use crate::repo::oauth_repo::OAuthRepo;
+use crate::repo::service_accounts_repo::ServiceAccountsRepo;
+use crate::tokens::TokenHasher;
+use std::process;
+use url::Url;
+
+const USAGE: &str = "usage:
+  auth create-service-account <name> [scope...]
+  auth create-oauth-client [--public] [--scope <scope>]... <name> <redirect-uri>...";
+
+/// One-off admin commands, run instead of the servers when arguments are given.
+pub async fn run(config: AppConfig, args: Vec<String>) {
+    match args.first().map(String::as_str) {
+        Some("create-service-account") if args.len() >= 2 => {
+            create_service_account(config, args[1].clone(), args[2..].to_vec()).await
+        }
+        Some("create-oauth-client") => create_oauth_client(config, &args[1..]).await,
+        _ => usage(),
+    }
+}
+
+async fn connect(config: &AppConfig) -> PGPool {
+    match PGPool::new(config.postgres_conn_string.clone()).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("error getting pg pool: {e}");
+            process::exit(1)
+        }
+    }
+}
+
+async fn create_service_account(config: AppConfig, name: String, scopes: Vec<String>) {
+    let pool = connect(&config).await;
+
+    let repo = ServiceAccountsRepo::new(pool, TokenHasher::new(config.session_token_key));
+
+    match repo.create_service_account(name, scopes).await {
+        Ok(issued) => {
+            println!(
+                "created service account {} ({})",
+                issued.account.name, issued.account.id
+            );
+            println!("client_id: {}", issued.account.client_id);
+            println!("client_secret: {}", issued.client_secret);
+            println!("the secret is not stored and cannot be shown again");
+        }
+        Err(e) => {
+            eprintln!("error creating service account: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+async fn create_oauth_client(config: AppConfig, args: &[String]) {
+    let mut confidential = true;
+    let mut scopes = vec![];
+    let mut positional = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--public" => confidential = false,
+            "--scope" => match args.next() {
+                Some(scope) => scopes.push(scope.clone()),
+                None => usage(),
+            },
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let Some((name, redirect_uris)) = positional.split_first() else {
+        usage()
+    };
+
+    if redirect_uris.is_empty() {
+        usage();
+    }
+
+    // Redirect URIs are compared verbatim, fragments are forbidden by RFC 6749.
+    for uri in redirect_uris {
+        if Url::parse(uri).map_or(true, |url| url.fragment().is_some()) {
+            eprintln!("invalid redirect uri {uri}, expected an absolute URL without a fragment");
+            process::exit(2);
+        }
+    }
+
+    let pool = connect(&config).await;
+    let repo = OAuthRepo::new(pool, TokenHasher::new(config.session_token_key));
+
+    match repo
+        .create_client(name.clone(), redirect_uris.to_vec(), scopes, confidential)
+        .await
+    {
+        Ok(issued) => {
+            println!(
+                "created oauth client {} ({})",
+                issued.client.name, issued.client.id
+            );
+            println!("client_id: {}", issued.client.client_id);
+            println!("redirect_uris: {}", issued.client.redirect_uris.join(" "));
+            if issued.client.confidential {
+                println!(
+                    "client_secret: {}",
+                    issued.client_secret.unwrap_or_default()
+                );
+                println!("the secret is not stored and cannot be shown again");
+            } else {
+                println!("public client, authenticates with PKCE only");
+            }
+        }
+        Err(e) => {
+            eprintln!("error creating oauth client: {e}");
+            process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
+}
@@ -1 +1,2 @@
+pub mod service_auth;
 pub mod users_delivery;
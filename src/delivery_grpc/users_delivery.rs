@@ -1,9 +1,16 @@
-use crate::delivery_grpc::users_delivery::auth::{GetUserRequest, GetUserResponse};
+use crate::delivery_grpc::service_auth::{GET_USER_SCOPE, RESOLVE_API_KEY_SCOPE, require_scope};
+use crate::delivery_grpc::users_delivery::auth::{
+    GetUserRequest, GetUserResponse, ResolveApiKeyRequest, ResolveApiKeyResponse, SessionInfo,
+};
+use crate::delivery_http::bearer::parse_bearer;
+use crate::delivery_http::session_cookie::SessionCookieCodec;
 use crate::errors::DBError;
+use crate::model::{ApiKey, Session};
 use async_trait::async_trait;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
-use uuid::Uuid;
+
+const AUTHORIZATION_METADATA: &str = "authorization";
 
 pub mod auth {
     include!("../gen/auth.rs");
@@ -13,16 +20,61 @@ use auth::users_provider_server::UsersProvider;
 
 #[async_trait]
 pub trait IUserIDGetter: Send + Sync {
-    async fn get_user(&self, session_id: Uuid) -> Result<Option<Uuid>, DBError>;
+    /// Resolves a raw session token, implementations hash it before the lookup.
+    async fn get_user(&self, token: &str) -> Result<Option<Session>, DBError>;
+}
+
+#[async_trait]
+pub trait IApiKeyResolver: Send + Sync {
+    /// Resolves a raw API key into the live key it belongs to, expired keys yield `None`.
+    async fn resolve_api_key(&self, key: &str) -> Result<Option<ApiKey>, DBError>;
 }
 
 pub struct UsersDeliveryGRPC {
     user_id_getter: Arc<dyn IUserIDGetter>,
+    api_keys: Arc<dyn IApiKeyResolver>,
+    cookies: SessionCookieCodec,
 }
 
 impl UsersDeliveryGRPC {
-    pub fn new(user_id_getter: Arc<dyn IUserIDGetter>) -> Self {
-        UsersDeliveryGRPC { user_id_getter }
+    pub fn new(
+        user_id_getter: Arc<dyn IUserIDGetter>,
+        api_keys: Arc<dyn IApiKeyResolver>,
+        cookies: SessionCookieCodec,
+    ) -> Self {
+        UsersDeliveryGRPC {
+            user_id_getter,
+            api_keys,
+            cookies,
+        }
+    }
+}
+
+impl From<Session> for SessionInfo {
+    fn from(value: Session) -> Self {
+        SessionInfo {
+            session_id: value.id.to_string(),
+            ip: value.ip.unwrap_or_default(),
+            user_agent: value.user_agent.unwrap_or_default(),
+            auth_method: value.auth_method.as_str().to_string(),
+            created_at: value.created_at.timestamp(),
+            last_seen_at: value.last_seen_at.timestamp(),
+            expires_at: value.expires_at.timestamp(),
+        }
+    }
+}
+
+impl From<ApiKey> for ResolveApiKeyResponse {
+    fn from(value: ApiKey) -> Self {
+        ResolveApiKeyResponse {
+            user_id: value.user_id.to_string(),
+            key_id: value.id.to_string(),
+            name: value.name,
+            scopes: value.scopes,
+            expires_at: value
+                .expires_at
+                .map_or(0, |expires_at| expires_at.timestamp()),
+        }
     }
 }
 
@@ -32,20 +84,38 @@ impl UsersProvider for UsersDeliveryGRPC {
         &self,
         request: Request<GetUserRequest>,
     ) -> Result<Response<GetUserResponse>, Status> {
-        let raw_session_id = request.into_inner().session_id;
-
-        let session_id = if let Ok(id) = Uuid::parse_str(raw_session_id.as_str()) {
-            id
-        } else {
-            return Err(Status::invalid_argument(
-                "failed to parse session_id to uuid",
-            ));
+        require_scope(&request, GET_USER_SCOPE)?;
+
+        // Callers serving bearer clients forward their Authorization header as metadata.
+        let bearer = request
+            .metadata()
+            .get(AUTHORIZATION_METADATA)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_bearer)
+            .map(str::to_string);
+
+        let token = match bearer {
+            Some(token) => token,
+            None => {
+                let cookie_value = request.into_inner().session_id;
+
+                if cookie_value.is_empty() {
+                    return Err(Status::invalid_argument("session_id is empty"));
+                }
+
+                let Some(token) = self.cookies.open_value(&cookie_value) else {
+                    return Err(Status::unauthenticated("invalid session cookie"));
+                };
+
+                token
+            }
         };
 
-        match self.user_id_getter.get_user(session_id).await {
-            Ok(Some(user_id)) => {
+        match self.user_id_getter.get_user(&token).await {
+            Ok(Some(session)) => {
                 let message = GetUserResponse {
-                    user_id: user_id.to_string(),
+                    user_id: session.user_id.to_string(),
+                    session: Some(session.into()),
                 };
 
                 Ok(Response::new(message))
@@ -55,4 +125,23 @@ impl UsersProvider for UsersDeliveryGRPC {
             Err(e) => Err(Status::internal(e.to_string())),
         }
     }
+
+    async fn resolve_api_key(
+        &self,
+        request: Request<ResolveApiKeyRequest>,
+    ) -> Result<Response<ResolveApiKeyResponse>, Status> {
+        require_scope(&request, RESOLVE_API_KEY_SCOPE)?;
+
+        let api_key = request.into_inner().api_key;
+
+        if api_key.is_empty() {
+            return Err(Status::invalid_argument("api_key is empty"));
+        }
+
+        match self.api_keys.resolve_api_key(&api_key).await {
+            Ok(Some(api_key)) => Ok(Response::new(api_key.into())),
+            Ok(None) => Err(Status::not_found("api key not found")),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
 }
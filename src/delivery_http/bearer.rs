@@ -0,0 +1,50 @@
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+
+const BEARER_SCHEME: &str = "bearer";
+
+/// Session token sent as `Authorization: Bearer <token>` by clients without cookies.
+///
+/// Any other scheme or a malformed header counts as no token,
+/// authentication then falls back to the session cookie.
+#[derive(Debug, Clone, Default)]
+pub struct BearerToken(pub Option<String>);
+
+/// Extracts the credentials of a `Bearer` authorization value, the scheme is case-insensitive.
+pub fn parse_bearer(value: &str) -> Option<&str> {
+    let (scheme, token) = value.trim().split_once(' ')?;
+    let token = token.trim();
+
+    (scheme.eq_ignore_ascii_case(BEARER_SCHEME) && !token.is_empty()).then_some(token)
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for BearerToken {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_bearer)
+            .map(str::to_string);
+
+        Ok(BearerToken(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bearer() {
+        assert_eq!(parse_bearer("Bearer abc"), Some("abc"));
+        assert_eq!(parse_bearer("bearer  abc "), Some("abc"));
+        assert_eq!(parse_bearer("Basic abc"), None);
+        assert_eq!(parse_bearer("Bearer "), None);
+        assert_eq!(parse_bearer("Bearer"), None);
+    }
+}
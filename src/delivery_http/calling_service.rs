@@ -0,0 +1,40 @@
+use crate::app::AuthApp;
+use crate::errors::{ApiError, TokenError};
+use crate::model::ServiceIdentity;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use std::sync::Arc;
+
+/// Header carrying a service access token, separate from `Authorization`
+/// which stays with the end user on calls made on their behalf.
+pub const SERVICE_TOKEN_HEADER: &str = "x-service-token";
+
+/// The service behind the request, if it sent a service access token.
+///
+/// A token that is present but does not verify rejects the request
+/// instead of silently downgrading it to an anonymous one.
+#[derive(Debug, Clone, Default)]
+pub struct CallingService(pub Option<ServiceIdentity>);
+
+impl FromRequestParts<Arc<AuthApp>> for CallingService {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app: &Arc<AuthApp>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = parts
+            .headers
+            .get(SERVICE_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Ok(CallingService(None));
+        };
+
+        let Some(issuer) = &app.access_tokens else {
+            return Err(TokenError::AccessTokensDisabled.into());
+        };
+
+        Ok(CallingService(Some(issuer.verify_service(token)?)))
+    }
+}
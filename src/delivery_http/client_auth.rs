@@ -0,0 +1,55 @@
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::convert::Infallible;
+
+const BASIC_SCHEME: &str = "basic";
+
+/// OAuth client credentials sent as `Authorization: Basic` (RFC 6749 section 2.3.1).
+#[derive(Debug, Clone, Default)]
+pub struct BasicCredentials(pub Option<(String, String)>);
+
+fn parse_basic(value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case(BASIC_SCHEME) {
+        return None;
+    }
+
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for BasicCredentials {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let credentials = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_basic);
+
+        Ok(BasicCredentials(credentials))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_basic() {
+        let encoded = STANDARD.encode("svc_1:secret:with:colons");
+
+        assert_eq!(
+            parse_basic(&format!("Basic {encoded}")),
+            Some(("svc_1".to_string(), "secret:with:colons".to_string()))
+        );
+        assert_eq!(parse_basic(&format!("Bearer {encoded}")), None);
+        assert_eq!(parse_basic("Basic not-base64!"), None);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::app::AuthApp;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::HeaderMap;
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// Client address and User-Agent of the incoming request.
+///
+/// Proxy headers are only believed when the TCP peer is one of the configured
+/// trusted proxies, anyone else could put any address in them.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+/// The address of the client behind `peer`.
+///
+/// `X-Forwarded-For` is read from the right, every proxy appends the address it
+/// got the request from, so the first hop that is not a trusted proxy is the client.
+pub fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[IpAddr]) -> Option<IpAddr> {
+    let Some(peer) = peer.filter(|peer| trusted.contains(peer)) else {
+        return peer;
+    };
+
+    let forwarded: Vec<IpAddr> = ClientInfo::header(headers, X_FORWARDED_FOR)
+        .map(|value| {
+            value
+                .split(',')
+                .filter_map(|hop| hop.trim().parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if let Some(client) = forwarded.iter().rev().find(|hop| !trusted.contains(hop)) {
+        return Some(*client);
+    }
+
+    forwarded
+        .first()
+        .copied()
+        .or_else(|| ClientInfo::header(headers, X_REAL_IP).and_then(|ip| ip.parse().ok()))
+        .or(Some(peer))
+}
+
+impl FromRequestParts<Arc<AuthApp>> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app: &Arc<AuthApp>,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        Ok(ClientInfo {
+            ip: client_ip(&parts.headers, peer, &app.trusted_proxies).map(|ip| ip.to_string()),
+            user_agent: Self::header(&parts.headers, USER_AGENT.as_str()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let trusted = [ip("10.0.0.1"), ip("10.0.0.2")];
+        let forged = headers(&[(X_FORWARDED_FOR, "1.1.1.1"), (X_REAL_IP, "2.2.2.2")]);
+
+        // Untrusted peers cannot forge their address.
+        assert_eq!(
+            client_ip(&forged, Some(ip("3.3.3.3")), &trusted),
+            Some(ip("3.3.3.3"))
+        );
+        assert_eq!(
+            client_ip(&forged, Some(ip("3.3.3.3")), &[]),
+            Some(ip("3.3.3.3"))
+        );
+
+        // Behind trusted proxies the address the first of them saw wins,
+        // whatever the client prepended itself.
+        let chain = headers(&[(X_FORWARDED_FOR, "1.1.1.1, 4.4.4.4, 10.0.0.2")]);
+        assert_eq!(
+            client_ip(&chain, Some(ip("10.0.0.1")), &trusted),
+            Some(ip("4.4.4.4"))
+        );
+
+        let real_ip = headers(&[(X_REAL_IP, "2.2.2.2")]);
+        assert_eq!(
+            client_ip(&real_ip, Some(ip("10.0.0.1")), &trusted),
+            Some(ip("2.2.2.2"))
+        );
+        assert_eq!(
+            client_ip(&HeaderMap::new(), Some(ip("10.0.0.1")), &trusted),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(client_ip(&HeaderMap::new(), None, &trusted), None);
+    }
+}
@@ -1,15 +1,24 @@
-use crate::model::User;
-use chrono::{DateTime, Local};
+use crate::jwt::AccessToken;
+use crate::model::{
+    ApiKey, AuthMethod, IssuedApiKey, NewApiKey, OAuthConsent, ServiceIdentity, Session,
+    SessionKind, User,
+};
+use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
 const USER_NOT_FOUND_MSG: &str = "user not found";
+const MAX_API_KEY_SCOPES: usize = 32;
+const MAX_SCOPE_LEN: usize = 64;
 
 #[derive(Clone, Deserialize, Validate)]
 pub struct RegisterRequest {
     #[validate(
         email(message = "Invalid email format"),
-        custom(function = "custom_validate_email", message = "Email must include a valid domain (e.g. .com)")
+        custom(
+            function = "custom_validate_email",
+            message = "Email must include a valid domain (e.g. .com)"
+        )
     )]
     pub email: String,
     #[validate(length(min = 1, message = "Username should not be empty"))]
@@ -28,10 +37,157 @@ fn custom_validate_email(email: &str) -> Result<(), ValidationError> {
     Err(ValidationError::new("invalid email"))
 }
 
+#[derive(Clone, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[validate(length(min = 1, max = 64, message = "Name must be 1 to 64 characters"))]
+    pub name: String,
+    #[serde(default)]
+    #[validate(custom(
+        function = "validate_scopes",
+        message = "Scopes must be up to 64 characters of a-z, 0-9, ':', '.', '_' or '-'"
+    ))]
+    pub scopes: Vec<String>,
+    /// Keys without expiry live until revoked.
+    #[validate(custom(
+        function = "validate_in_future",
+        message = "Expiry must be in the future"
+    ))]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+fn validate_scopes(scopes: &[String]) -> Result<(), ValidationError> {
+    let valid_scope = |scope: &String| {
+        !scope.is_empty()
+            && scope.len() <= MAX_SCOPE_LEN
+            && scope
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b":._-".contains(&b))
+    };
+
+    if scopes.len() <= MAX_API_KEY_SCOPES && scopes.iter().all(valid_scope) {
+        return Ok(());
+    }
+
+    Err(ValidationError::new("invalid scopes"))
+}
+
+fn validate_in_future(at: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *at > Utc::now() {
+        return Ok(());
+    }
+
+    Err(ValidationError::new("in the past"))
+}
+
+impl From<CreateApiKeyRequest> for NewApiKey {
+    fn from(value: CreateApiKeyRequest) -> Self {
+        NewApiKey {
+            name: value.name,
+            scopes: value.scopes,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    #[serde(default)]
+    pub remember_me: bool,
+    /// Also return a signed access token for calls to other services.
+    #[serde(default)]
+    pub issue_access_token: bool,
+    /// Return the session token in the body instead of setting a cookie,
+    /// for clients that authenticate with `Authorization: Bearer`.
+    #[serde(default)]
+    pub cookieless: bool,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct MagicLinkRequest {
+    #[validate(
+        email(message = "Invalid email format"),
+        custom(
+            function = "custom_validate_email",
+            message = "Email must include a valid domain (e.g. .com)"
+        )
+    )]
+    pub email: String,
+    #[serde(default)]
+    pub remember_me: bool,
+}
+
+/// The token of a magic link, from its URL or the confirmation form.
+#[derive(Clone, Deserialize)]
+pub struct MagicLinkToken {
+    pub token: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct ResendVerificationRequest {
+    #[validate(
+        email(message = "Invalid email format"),
+        custom(
+            function = "custom_validate_email",
+            message = "Email must include a valid domain (e.g. .com)"
+        )
+    )]
+    pub email: String,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct ForgotPasswordRequest {
+    #[validate(
+        email(message = "Invalid email format"),
+        custom(
+            function = "custom_validate_email",
+            message = "Email must include a valid domain (e.g. .com)"
+        )
+    )]
+    pub email: String,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    #[validate(length(min = 6, message = "Password must be at least 6 characters"))]
+    pub password: String,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    #[validate(length(min = 6, message = "Password must be at least 6 characters"))]
+    pub new_password: String,
+    /// Return a new access token and refresh token for the rotated session,
+    /// the refresh tokens of the old one stop working along with it.
+    #[serde(default)]
+    pub issue_access_token: bool,
+}
+
+#[derive(Clone, Deserialize, Validate)]
+pub struct ChangeEmailRequest {
+    #[validate(
+        email(message = "Invalid email format"),
+        custom(
+            function = "custom_validate_email",
+            message = "Email must include a valid domain (e.g. .com)"
+        )
+    )]
+    pub new_email: String,
+    pub current_password: String,
+}
+
+/// The token of a link undoing an email change, from its URL or the confirmation form.
+#[derive(Clone, Deserialize)]
+pub struct EmailRevertToken {
+    pub token: String,
 }
 
 #[derive(Clone, Deserialize)]
@@ -57,6 +213,7 @@ pub struct UserResponse {
     pub id: uuid::Uuid,
     pub email: String,
     pub username: String,
+    pub email_verified: bool,
     pub created_at: DateTime<Local>,
     pub updated_at: DateTime<Local>,
 }
@@ -66,8 +223,266 @@ impl From<User> for UserResponse {
             id: value.id,
             email: value.email,
             username: value.username,
+            email_verified: value.email_verified_at.is_some(),
             created_at: value.created_at,
             updated_at: value.updated_at,
         }
     }
 }
+
+/// Form body of the OAuth token endpoint.
+#[derive(Clone, Deserialize)]
+pub struct OAuthTokenRequest {
+    pub grant_type: String,
+    /// Client credentials may come in the body instead of the Basic header.
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    /// Space-separated, defaults to every scope the client holds.
+    pub scope: Option<String>,
+    /// Authorization code grant only.
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Present when the `openid` scope was granted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+impl OAuthTokenResponse {
+    pub fn new(access_token: AccessToken, scope: Option<String>) -> Self {
+        OAuthTokenResponse {
+            access_token: access_token.token,
+            token_type: "Bearer",
+            expires_in: access_token.expires_in,
+            scope,
+            id_token: None,
+        }
+    }
+}
+
+/// Query of the authorization endpoint, carried through the login and consent forms.
+#[derive(Clone, Deserialize)]
+pub struct AuthorizeRequest {
+    pub client_id: String,
+    pub response_type: Option<String>,
+    /// May be left out when the client has a single registered redirect URI.
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// OpenID Connect only, returned unchanged in the id_token.
+    pub nonce: Option<String>,
+}
+
+/// Submitted login or consent form of the authorization endpoint.
+#[derive(Clone, Deserialize)]
+pub struct AuthorizeForm {
+    #[serde(flatten)]
+    pub request: AuthorizeRequest,
+    pub csrf_token: String,
+    /// Only sent by the login form, when there is no session yet.
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub remember_me: Option<String>,
+    /// `allow` or `deny`, only sent by the consent form.
+    pub consent: Option<String>,
+}
+
+/// Query of the external sign-in endpoint.
+#[derive(Clone, Deserialize)]
+pub struct ExternalLoginRequest {
+    #[serde(default)]
+    pub remember_me: bool,
+}
+
+/// Query the identity provider sends the browser back with.
+#[derive(Clone, Deserialize)]
+pub struct ExternalLoginCallback {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of `code` when the user declined or the provider failed.
+    pub error: Option<String>,
+    /// VK ID only, has to be sent back with the code.
+    pub device_id: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct OAuthConsentResponse {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    pub granted_at: DateTime<Utc>,
+}
+
+impl From<OAuthConsent> for OAuthConsentResponse {
+    fn from(consent: OAuthConsent) -> Self {
+        OAuthConsentResponse {
+            client_id: consent.client_id,
+            client_name: consent.client_name,
+            scopes: consent.scopes,
+            granted_at: consent.granted_at,
+        }
+    }
+}
+
+/// Form body of the introspection and revocation endpoints.
+#[derive(Clone, Deserialize)]
+pub struct OAuthTokenActionRequest {
+    pub token: String,
+    /// `access_token`, `refresh_token`, `session_token` or `api_key`, only decides lookup order.
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// RFC 7662 introspection response, inactive tokens carry nothing but `active`.
+#[derive(Clone, Default, Serialize)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<uuid::Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AccessTokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    /// Single-use, exchanged for a new pair at the refresh endpoint.
+    pub refresh_token: String,
+}
+
+impl AccessTokenResponse {
+    pub fn new(access_token: AccessToken, refresh_token: String) -> Self {
+        AccessTokenResponse {
+            access_token: access_token.token,
+            token_type: "Bearer",
+            expires_in: access_token.expires_in,
+            refresh_token,
+        }
+    }
+}
+
+/// The user as returned elsewhere, with the access token fields alongside when one was requested.
+#[derive(Clone, Serialize)]
+pub struct LoginResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    #[serde(flatten)]
+    pub access_token: Option<AccessTokenResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SessionResponse {
+    pub id: uuid::Uuid,
+    pub current: bool,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub auth_method: AuthMethod,
+    pub kind: SessionKind,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl SessionResponse {
+    pub fn new(session: Session, current: bool) -> Self {
+        SessionResponse {
+            id: session.id,
+            current,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            auth_method: session.auth_method,
+            kind: session.kind,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(value: ApiKey) -> Self {
+        ApiKeyResponse {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            expires_at: value.expires_at,
+            last_used_at: value.last_used_at,
+            created_at: value.created_at,
+        }
+    }
+}
+
+/// Returned once on creation, the key cannot be retrieved afterwards.
+#[derive(Clone, Serialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+    pub key: String,
+}
+
+impl From<IssuedApiKey> for CreatedApiKeyResponse {
+    fn from(value: IssuedApiKey) -> Self {
+        CreatedApiKeyResponse {
+            api_key: value.api_key.into(),
+            key: value.key,
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct ServiceIdentityResponse {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+impl From<ServiceIdentity> for ServiceIdentityResponse {
+    fn from(value: ServiceIdentity) -> Self {
+        ServiceIdentityResponse {
+            client_id: value.client_id,
+            scopes: value.scopes,
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::app::IEmailVerificationDelivery;
+use crate::delivery_http::dto::{EmailRevertToken, ResendVerificationRequest, VerifyEmailRequest};
+use crate::delivery_http::pages::{email_revert_page, message_page, page};
+use crate::delivery_http::users_delivery::{IApiKeysRepo, ISessionStore, IUsersCreatorUsecase};
+use crate::errors::{ApiError, DBError};
+use crate::model::EmailTokenPurpose;
+use crate::usecase::email_links::{EmailLinks, IEmailTokensRepo};
+use crate::usecase::users_usecase::IUsersRepository;
+use async_trait::async_trait;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use std::sync::Arc;
+use validator::Validate;
+
+pub const EMAIL_REVERT_PATH: &str = "/api/v1/email/revert";
+
+pub struct EmailVerificationDelivery {
+    email_tokens: Arc<dyn IEmailTokensRepo>,
+    users: Arc<dyn IUsersRepository>,
+    usecase: Arc<dyn IUsersCreatorUsecase>,
+    session_store: Arc<dyn ISessionStore>,
+    api_keys: Arc<dyn IApiKeysRepo>,
+    links: Arc<EmailLinks>,
+}
+
+impl EmailVerificationDelivery {
+    pub fn new(
+        email_tokens: Arc<dyn IEmailTokensRepo>,
+        users: Arc<dyn IUsersRepository>,
+        usecase: Arc<dyn IUsersCreatorUsecase>,
+        session_store: Arc<dyn ISessionStore>,
+        api_keys: Arc<dyn IApiKeysRepo>,
+        links: Arc<EmailLinks>,
+    ) -> Self {
+        EmailVerificationDelivery {
+            email_tokens,
+            users,
+            usecase,
+            session_store,
+            api_keys,
+            links,
+        }
+    }
+}
+
+#[async_trait]
+impl IEmailVerificationDelivery for EmailVerificationDelivery {
+    async fn verify(&self, Json(payload): Json<VerifyEmailRequest>) -> Result<Response, ApiError> {
+        let Some(redeemed) = self
+            .email_tokens
+            .redeem_email_token(EmailTokenPurpose::EmailVerification, &payload.token, None)
+            .await?
+        else {
+            return Err(DBError::InvalidEmailToken.into());
+        };
+
+        // A link sent before the address changed proves nothing about the new one,
+        // the repository only verifies the address the token was sent to.
+        self.users
+            .mark_email_verified(redeemed.user_id, &redeemed.email)
+            .await?;
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    async fn resend(
+        &self,
+        Json(payload): Json<ResendVerificationRequest>,
+    ) -> Result<Response, ApiError> {
+        payload.validate()?;
+
+        self.links.resend_verification(payload.email);
+
+        Ok(StatusCode::ACCEPTED.into_response())
+    }
+
+    async fn confirm_change(
+        &self,
+        Json(payload): Json<VerifyEmailRequest>,
+    ) -> Result<Response, ApiError> {
+        let Some(redeemed) = self
+            .email_tokens
+            .redeem_email_token(EmailTokenPurpose::EmailChange, &payload.token, None)
+            .await?
+        else {
+            return Err(DBError::InvalidEmailToken.into());
+        };
+
+        let Some(previous_email) = self
+            .users
+            .change_email(redeemed.user_id, &redeemed.email)
+            .await?
+        else {
+            return Err(DBError::InvalidEmailToken.into());
+        };
+
+        // The first confirmed link wins, links to other requested addresses stop working.
+        // So do sign-in links mailed to the previous address.
+        for purpose in [EmailTokenPurpose::EmailChange, EmailTokenPurpose::MagicLink] {
+            self.email_tokens
+                .revoke_email_tokens(redeemed.user_id, purpose)
+                .await?;
+        }
+
+        if previous_email != redeemed.email {
+            self.links
+                .send_changed_notice(redeemed.user_id, previous_email, redeemed.email);
+        }
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    async fn revert_page(
+        &self,
+        Query(link): Query<EmailRevertToken>,
+    ) -> Result<Response, ApiError> {
+        Ok(page(StatusCode::OK, email_revert_page(&link.token)))
+    }
+
+    async fn revert(&self, Form(link): Form<EmailRevertToken>) -> Result<Response, ApiError> {
+        let Some(redeemed) = self
+            .email_tokens
+            .redeem_email_token(EmailTokenPurpose::EmailRevert, &link.token, None)
+            .await?
+        else {
+            return Ok(page(
+                StatusCode::BAD_REQUEST,
+                message_page(
+                    "Link expired",
+                    "This link is invalid, has expired or was already used.",
+                ),
+            ));
+        };
+
+        match self
+            .users
+            .change_email(redeemed.user_id, &redeemed.email)
+            .await
+        {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Ok(page(
+                    StatusCode::NOT_FOUND,
+                    message_page("Account not found", "The account no longer exists."),
+                ));
+            }
+            Err(DBError::UserAlreadyExists) => {
+                return Ok(page(
+                    StatusCode::CONFLICT,
+                    message_page(
+                        "Email is taken",
+                        "Another account uses this email by now, please contact support.",
+                    ),
+                ));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Whoever made the change knew the password, so it goes along with every
+        // credential they could have picked up. Reset links die with the password,
+        // a new one can only go to the restored address.
+        self.usecase
+            .lock_password(redeemed.user_id, &redeemed.email)
+            .await?;
+        for purpose in [EmailTokenPurpose::EmailChange, EmailTokenPurpose::MagicLink] {
+            self.email_tokens
+                .revoke_email_tokens(redeemed.user_id, purpose)
+                .await?;
+        }
+        self.session_store
+            .revoke_all_sessions(redeemed.user_id)
+            .await?;
+        self.api_keys.revoke_api_keys(redeemed.user_id).await?;
+
+        Ok(page(
+            StatusCode::OK,
+            message_page(
+                "Email restored",
+                &format!(
+                    "Your account uses {} again, was signed out everywhere and its \
+                     password and API keys no longer work. Reset your password to sign in.",
+                    redeemed.email
+                ),
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EmailVerificationPolicy, SessionConfig};
+    use crate::delivery_http::users_delivery::MockIApiKeysRepo;
+    use crate::infra::mailer::LogMailer;
+    use crate::model::{AuthMethod, EmailToken, NewEmailToken, SessionKind, SessionMeta};
+    use crate::repo::memory_sessions::MemorySessionsRepo;
+    use crate::tokens::TokenHasher;
+    use crate::usecase::email_links::EmailLinkUrls;
+    use crate::usecase::users_usecase::{MockIEmailVerifier, MockIUsersRepository, UserUsecase};
+    use std::sync::Mutex;
+    use url::Url;
+    use uuid::Uuid;
+
+    /// Redeems each of `tokens` once and remembers what was revoked.
+    #[derive(Default)]
+    struct FakeEmailTokens {
+        tokens: Mutex<Vec<(EmailTokenPurpose, &'static str, EmailToken)>>,
+        revoked: Mutex<Vec<EmailTokenPurpose>>,
+    }
+
+    #[async_trait]
+    impl IEmailTokensRepo for FakeEmailTokens {
+        async fn issue_email_token(
+            &self,
+            _: &NewEmailToken,
+            _: u64,
+        ) -> Result<Option<String>, DBError> {
+            Ok(None)
+        }
+
+        async fn redeem_email_token(
+            &self,
+            purpose: EmailTokenPurpose,
+            token: &str,
+            _: Option<&str>,
+        ) -> Result<Option<EmailToken>, DBError> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let found = tokens
+                .iter()
+                .position(|(p, t, _)| *p == purpose && *t == token);
+
+            Ok(found.map(|i| tokens.remove(i).2))
+        }
+
+        async fn revoke_email_tokens(
+            &self,
+            _: Uuid,
+            purpose: EmailTokenPurpose,
+        ) -> Result<(), DBError> {
+            self.revoked.lock().unwrap().push(purpose);
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        delivery: EmailVerificationDelivery,
+        email_tokens: Arc<FakeEmailTokens>,
+        sessions: Arc<MemorySessionsRepo>,
+    }
+
+    fn fixture(
+        users: MockIUsersRepository,
+        api_keys: MockIApiKeysRepo,
+        token: (EmailTokenPurpose, EmailToken),
+    ) -> Fixture {
+        let users = Arc::new(users);
+        let email_tokens = Arc::new(FakeEmailTokens::default());
+        email_tokens
+            .tokens
+            .lock()
+            .unwrap()
+            .push((token.0, "t0k3n", token.1));
+        let sessions = Arc::new(MemorySessionsRepo::new(
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+        ));
+
+        let url = |path: &str| Url::parse(&format!("https://auth.example.com{path}")).unwrap();
+        let links = EmailLinks::new(
+            email_tokens.clone(),
+            users.clone(),
+            Arc::new(LogMailer),
+            EmailLinkUrls {
+                verification: url("/verify-email"),
+                email_change: url("/confirm-email"),
+                email_revert: url(EMAIL_REVERT_PATH),
+                password_reset: url("/reset-password"),
+                magic_link: url("/api/v1/login/magic-link/verify"),
+            },
+        );
+        let usecase = UserUsecase::new(
+            users.clone(),
+            Arc::new(MockIEmailVerifier::new()),
+            EmailVerificationPolicy::Restrict,
+        );
+
+        Fixture {
+            delivery: EmailVerificationDelivery::new(
+                email_tokens.clone(),
+                users,
+                Arc::new(usecase),
+                sessions.clone(),
+                Arc::new(api_keys),
+                Arc::new(links),
+            ),
+            email_tokens,
+            sessions,
+        }
+    }
+
+    fn email_token(user_id: Uuid, email: &str) -> EmailToken {
+        EmailToken {
+            user_id,
+            email: email.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confirm_change() {
+        let user_id = Uuid::new_v4();
+        let mut users = MockIUsersRepository::new();
+        users
+            .expect_change_email()
+            .times(1)
+            .withf(move |id, email| *id == user_id && email == "new@example.com")
+            .returning(|_, _| Ok(Some("old@example.com".to_string())));
+
+        let fixture = fixture(
+            users,
+            MockIApiKeysRepo::new(),
+            (
+                EmailTokenPurpose::EmailChange,
+                email_token(user_id, "new@example.com"),
+            ),
+        );
+        let confirm = || {
+            fixture.delivery.confirm_change(Json(VerifyEmailRequest {
+                token: "t0k3n".to_string(),
+            }))
+        };
+
+        assert_eq!(confirm().await.unwrap().status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            *fixture.email_tokens.revoked.lock().unwrap(),
+            [EmailTokenPurpose::EmailChange, EmailTokenPurpose::MagicLink]
+        );
+        assert!(matches!(
+            confirm().await,
+            Err(ApiError::DataBaseError(DBError::InvalidEmailToken))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_revert_locks_the_account() {
+        let user_id = Uuid::new_v4();
+        let mut users = MockIUsersRepository::new();
+        users
+            .expect_change_email()
+            .times(1)
+            .withf(move |id, email| *id == user_id && email == "old@example.com")
+            .returning(|_, _| Ok(Some("attacker@example.com".to_string())));
+        users
+            .expect_update_password()
+            .times(1)
+            .withf(move |id, email, _| *id == user_id && email == "old@example.com")
+            .returning(|_, _, _| Ok(true));
+
+        let mut api_keys = MockIApiKeysRepo::new();
+        api_keys
+            .expect_revoke_api_keys()
+            .times(1)
+            .withf(move |id| *id == user_id)
+            .returning(|_| Ok(1));
+
+        let fixture = fixture(
+            users,
+            api_keys,
+            (
+                EmailTokenPurpose::EmailRevert,
+                email_token(user_id, "old@example.com"),
+            ),
+        );
+        for _ in 0..2 {
+            let meta = SessionMeta {
+                ip: None,
+                user_agent: None,
+                auth_method: AuthMethod::Password,
+                kind: SessionKind::Transient,
+                parent_id: None,
+                client_id: None,
+            };
+            fixture
+                .sessions
+                .create_session(user_id, meta)
+                .await
+                .unwrap();
+        }
+        let revert = || {
+            fixture.delivery.revert(Form(EmailRevertToken {
+                token: "t0k3n".to_string(),
+            }))
+        };
+
+        assert_eq!(revert().await.unwrap().status(), StatusCode::OK);
+        assert!(
+            fixture
+                .sessions
+                .list_sessions(user_id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            *fixture.email_tokens.revoked.lock().unwrap(),
+            [EmailTokenPurpose::EmailChange, EmailTokenPurpose::MagicLink]
+        );
+
+        assert_eq!(revert().await.unwrap().status(), StatusCode::BAD_REQUEST);
+    }
+}
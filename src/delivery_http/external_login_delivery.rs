@@ -0,0 +1,370 @@
+use crate::app::IExternalLoginDelivery;
+use crate::config::ExternalLoginConfig;
+use crate::delivery_grpc::users_delivery::IUserIDGetter;
+use crate::delivery_http::client_info::ClientInfo;
+use crate::delivery_http::dto::{ExternalLoginCallback, ExternalLoginRequest};
+use crate::delivery_http::pages::{error_page, page};
+use crate::delivery_http::session_cookie::{SESSION_COOKIE, SessionCookieCodec};
+use crate::delivery_http::users_delivery::{ISessionStore, IUsersCreatorUsecase};
+use crate::errors::{ApiError, DBError, ExternalIdpError, UsecaseError};
+use crate::external_idp::{ExternalIdentity, IdentityProvider};
+use crate::model::{AuthMethod, Session, SessionKind, SessionMeta};
+use crate::tokens::{generate_token, pkce_challenge};
+use async_trait::async_trait;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Keeps the state and PKCE verifier of a sign-in in progress, which binds the
+/// callback to the browser that started it. `SameSite=Lax` still sends it on the
+/// top-level redirect back from the provider.
+const PENDING_LOGIN_COOKIE: &str = "external_login";
+const LOGIN_PATH: &str = "/api/v1/login";
+const PENDING_LOGIN_TTL: time::Duration = time::Duration::minutes(10);
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[async_trait]
+pub trait IIdentitiesRepo: Send + Sync {
+    async fn find_user(&self, provider: &str, subject: &str) -> Result<Option<Uuid>, DBError>;
+    /// A provider account belongs to one user and a user has at most one account
+    /// per provider, either conflict fails with [`DBError::IdentityAlreadyLinked`].
+    async fn link_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<(), DBError>;
+}
+
+/// Contents of [`PENDING_LOGIN_COOKIE`], sealed like the session cookie.
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    provider: String,
+    state: String,
+    code_verifier: String,
+    remember_me: bool,
+    /// Set when the user was already signed in, the provider account is then
+    /// linked to them instead of signing in.
+    link_to: Option<Uuid>,
+}
+
+pub struct ExternalLoginDelivery {
+    providers: Vec<IdentityProvider>,
+    public_url: String,
+    redirect_url: String,
+    identities: Arc<dyn IIdentitiesRepo>,
+    usecase: Arc<dyn IUsersCreatorUsecase>,
+    session_store: Arc<dyn ISessionStore>,
+    user_id_getter: Arc<dyn IUserIDGetter>,
+    cookies: SessionCookieCodec,
+}
+
+impl ExternalLoginDelivery {
+    pub fn new(
+        config: ExternalLoginConfig,
+        identities: Arc<dyn IIdentitiesRepo>,
+        usecase: Arc<dyn IUsersCreatorUsecase>,
+        session_store: Arc<dyn ISessionStore>,
+        user_id_getter: Arc<dyn IUserIDGetter>,
+        cookies: SessionCookieCodec,
+    ) -> Result<Self, url::ParseError> {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("auth/", env!("CARGO_PKG_VERSION")))
+            .timeout(PROVIDER_TIMEOUT)
+            .build()
+            .expect("http client has a static configuration");
+
+        let providers = config
+            .providers
+            .into_iter()
+            .map(|provider| IdentityProvider::new(provider, http.clone()))
+            .collect::<Result<_, _>>()?;
+
+        Ok(ExternalLoginDelivery {
+            providers,
+            public_url: config.public_url,
+            redirect_url: config.redirect_url,
+            identities,
+            usecase,
+            session_store,
+            user_id_getter,
+            cookies,
+        })
+    }
+
+    fn provider(&self, name: &str) -> Option<&IdentityProvider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name() == name)
+    }
+
+    /// Has to be registered at the provider exactly like this.
+    fn callback_url(&self, provider: &IdentityProvider) -> String {
+        format!(
+            "{}{LOGIN_PATH}/{}/callback",
+            self.public_url,
+            provider.name()
+        )
+    }
+
+    /// Only the session cookie counts: the flow runs in the browser.
+    async fn authenticate(&self, jar: &CookieJar) -> Result<Option<Session>, ApiError> {
+        let Some(token) = jar
+            .get(SESSION_COOKIE)
+            .and_then(|cookie| self.cookies.open(cookie))
+        else {
+            return Ok(None);
+        };
+
+        Ok(self.user_id_getter.get_user(&token).await?)
+    }
+
+    fn pending_login(&self, jar: &CookieJar) -> Option<PendingLogin> {
+        let value = self.cookies.open(jar.get(PENDING_LOGIN_COOKIE)?)?;
+        serde_json::from_str(&value).ok()
+    }
+
+    /// Signs in the owner of the provider account, creating the user on first sign-in.
+    async fn sign_in(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        provider: &str,
+        identity: ExternalIdentity,
+        remember_me: bool,
+    ) -> Result<Response, ApiError> {
+        let user_id = match self
+            .identities
+            .find_user(provider, &identity.subject)
+            .await?
+        {
+            Some(user_id) => user_id,
+            None => match self.usecase.create_external_user(provider, &identity).await {
+                Ok(user) => user.id,
+                // Taking over an existing account just because the provider reports
+                // the same email would hand it to whoever controls that provider account.
+                Err(UsecaseError::DBDerivedError(DBError::UserAlreadyExists)) => {
+                    return Ok(failed(
+                        StatusCode::CONFLICT,
+                        &format!(
+                            "An account with this email already exists. Sign in with your \
+                             password first, then connect {provider} to it."
+                        ),
+                    ));
+                }
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        let kind = if remember_me {
+            SessionKind::Persistent
+        } else {
+            SessionKind::Transient
+        };
+
+        let issued = self
+            .session_store
+            .create_session(
+                user_id,
+                SessionMeta {
+                    ip: client.ip,
+                    user_agent: client.user_agent,
+                    auth_method: AuthMethod::External,
+                    kind,
+                    parent_id: None,
+                    client_id: None,
+                },
+            )
+            .await?;
+
+        Ok((
+            jar.add(self.cookies.session_cookie(&issued)),
+            Redirect::to(&self.redirect_url),
+        )
+            .into_response())
+    }
+
+    /// Connects the provider account to the signed-in user.
+    async fn link(
+        &self,
+        jar: CookieJar,
+        user_id: Uuid,
+        provider: &str,
+        identity: ExternalIdentity,
+    ) -> Result<Response, ApiError> {
+        if self
+            .authenticate(&jar)
+            .await?
+            .map(|session| session.user_id)
+            != Some(user_id)
+        {
+            return Ok(expired());
+        }
+
+        let linked = match self
+            .identities
+            .find_user(provider, &identity.subject)
+            .await?
+        {
+            Some(owner) => owner == user_id,
+            None => match self
+                .identities
+                .link_identity(user_id, provider, &identity)
+                .await
+            {
+                Ok(()) => true,
+                Err(DBError::IdentityAlreadyLinked) => false,
+                Err(e) => return Err(e.into()),
+            },
+        };
+
+        if !linked {
+            return Ok(failed(
+                StatusCode::CONFLICT,
+                &format!("This {provider} account cannot be connected to your account."),
+            ));
+        }
+
+        Ok((jar, Redirect::to(&self.redirect_url)).into_response())
+    }
+}
+
+fn pending_login_cookie(value: String) -> Cookie<'static> {
+    Cookie::build((PENDING_LOGIN_COOKIE, value))
+        .path(LOGIN_PATH)
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Lax)
+        .max_age(PENDING_LOGIN_TTL)
+        .build()
+}
+
+fn failed(status: StatusCode, message: &str) -> Response {
+    page(status, error_page(message))
+}
+
+fn expired() -> Response {
+    failed(
+        StatusCode::BAD_REQUEST,
+        "The sign-in has expired, please try again.",
+    )
+}
+
+#[async_trait]
+impl IExternalLoginDelivery for ExternalLoginDelivery {
+    async fn start(
+        &self,
+        jar: CookieJar,
+        Path(provider): Path<String>,
+        Query(request): Query<ExternalLoginRequest>,
+    ) -> Result<Response, ApiError> {
+        let Some(idp) = self.provider(&provider) else {
+            return Ok(failed(StatusCode::NOT_FOUND, "Unknown sign-in provider."));
+        };
+
+        let pending = PendingLogin {
+            provider: provider.clone(),
+            state: generate_token(),
+            code_verifier: generate_token(),
+            remember_me: request.remember_me,
+            link_to: self
+                .authenticate(&jar)
+                .await?
+                .map(|session| session.user_id),
+        };
+
+        let url = idp.authorization_url(
+            &self.callback_url(idp),
+            &pending.state,
+            &pkce_challenge(&pending.code_verifier),
+        );
+
+        let value = serde_json::to_string(&pending).map_err(DBError::FailedToSerializeSession)?;
+        let jar = jar.add(self.cookies.seal(pending_login_cookie(value)));
+
+        Ok((jar, Redirect::to(url.as_str())).into_response())
+    }
+
+    async fn callback(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        Path(provider): Path<String>,
+        Query(callback): Query<ExternalLoginCallback>,
+    ) -> Result<Response, ApiError> {
+        let Some(idp) = self.provider(&provider) else {
+            return Ok(failed(StatusCode::NOT_FOUND, "Unknown sign-in provider."));
+        };
+
+        // Single use: whatever happens next, the attempt is over.
+        let pending = self.pending_login(&jar);
+        let jar = jar.remove(Cookie::build(PENDING_LOGIN_COOKIE).path(LOGIN_PATH));
+
+        let Some(pending) = pending.filter(|pending| {
+            pending.provider == provider && callback.state.as_deref() == Some(&pending.state)
+        }) else {
+            return Ok((jar, expired()).into_response());
+        };
+
+        if callback.error.is_some() {
+            return Ok((
+                jar,
+                failed(
+                    StatusCode::FORBIDDEN,
+                    &format!("Signing in with {provider} was cancelled."),
+                ),
+            )
+                .into_response());
+        }
+
+        let Some(code) = callback.code else {
+            return Ok((jar, expired()).into_response());
+        };
+
+        let identity = match idp
+            .identify(
+                &code,
+                &pending.code_verifier,
+                &self.callback_url(idp),
+                callback.device_id.as_deref(),
+            )
+            .await
+        {
+            Ok(identity) => identity,
+            Err(e) => {
+                let (status, message) = match e {
+                    ExternalIdpError::EmailNotVerified => (
+                        StatusCode::FORBIDDEN,
+                        format!("Verify your email with {provider} first."),
+                    ),
+                    ExternalIdpError::MissingClaim(claim) => (
+                        StatusCode::FORBIDDEN,
+                        format!("{provider} did not share the {claim} of your account."),
+                    ),
+                    ExternalIdpError::RequestFailed(e) => {
+                        eprintln!("external login with {provider} failed: {e}");
+                        (
+                            StatusCode::BAD_GATEWAY,
+                            format!("Could not complete signing in with {provider}."),
+                        )
+                    }
+                };
+
+                return Ok((jar, failed(status, &message)).into_response());
+            }
+        };
+
+        match pending.link_to {
+            Some(user_id) => self.link(jar, user_id, &provider, identity).await,
+            None => {
+                self.sign_in(jar, client, &provider, identity, pending.remember_me)
+                    .await
+            }
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use crate::app::IMagicLinkDelivery;
+use crate::delivery_http::client_info::ClientInfo;
+use crate::delivery_http::dto::{MagicLinkRequest, MagicLinkToken};
+use crate::delivery_http::pages::{error_page, magic_link_page, page};
+use crate::delivery_http::session_cookie::{SESSION_COOKIE, SessionCookieCodec};
+use crate::delivery_http::users_delivery::ISessionStore;
+use crate::errors::{ApiError, DBError};
+use crate::model::{AuthMethod, EmailTokenPurpose, SessionKind, SessionMeta};
+use crate::tokens::generate_token;
+use crate::usecase::email_links::{EmailLinks, IEmailTokensRepo, MAGIC_LINK_TTL};
+use crate::usecase::users_usecase::IUsersRepository;
+use async_trait::async_trait;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Form, Json};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use validator::Validate;
+
+/// Holds the secret a magic link is bound to, a link opened in any other
+/// browser is refused.
+const MAGIC_LINK_COOKIE: &str = "magic_link";
+pub const MAGIC_LINK_PATH: &str = "/api/v1/login/magic-link";
+
+/// Contents of [`MAGIC_LINK_COOKIE`], sealed like the session cookie.
+#[derive(Serialize, Deserialize)]
+struct PendingMagicLink {
+    secret: String,
+    remember_me: bool,
+}
+
+pub struct MagicLinkDelivery {
+    email_tokens: Arc<dyn IEmailTokensRepo>,
+    users: Arc<dyn IUsersRepository>,
+    session_store: Arc<dyn ISessionStore>,
+    links: Arc<EmailLinks>,
+    cookies: SessionCookieCodec,
+    redirect_url: String,
+}
+
+impl MagicLinkDelivery {
+    pub fn new(
+        email_tokens: Arc<dyn IEmailTokensRepo>,
+        users: Arc<dyn IUsersRepository>,
+        session_store: Arc<dyn ISessionStore>,
+        links: Arc<EmailLinks>,
+        cookies: SessionCookieCodec,
+        redirect_url: String,
+    ) -> Self {
+        MagicLinkDelivery {
+            email_tokens,
+            users,
+            session_store,
+            links,
+            cookies,
+            redirect_url,
+        }
+    }
+
+    fn pending(&self, jar: &CookieJar) -> Option<PendingMagicLink> {
+        let value = self.cookies.open(jar.get(MAGIC_LINK_COOKIE)?)?;
+        serde_json::from_str(&value).ok()
+    }
+}
+
+fn magic_link_cookie(value: String) -> Cookie<'static> {
+    Cookie::build((MAGIC_LINK_COOKIE, value))
+        .path(MAGIC_LINK_PATH)
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::None)
+        .max_age(time::Duration::seconds(MAGIC_LINK_TTL as i64))
+        .build()
+}
+
+#[async_trait]
+impl IMagicLinkDelivery for MagicLinkDelivery {
+    async fn request(
+        &self,
+        jar: CookieJar,
+        Json(payload): Json<MagicLinkRequest>,
+    ) -> Result<Response, ApiError> {
+        payload.validate()?;
+
+        // The browser keeps its secret across requests. Whether a new link went out
+        // is unknown here, a throttled request must not orphan the link sent before.
+        let pending = PendingMagicLink {
+            secret: self
+                .pending(&jar)
+                .map(|pending| pending.secret)
+                .unwrap_or_else(generate_token),
+            remember_me: payload.remember_me,
+        };
+
+        self.links
+            .send_magic_link(payload.email, pending.secret.clone());
+
+        let value = serde_json::to_string(&pending).map_err(DBError::FailedToSerializeSession)?;
+        let jar = jar.add(self.cookies.seal(magic_link_cookie(value)));
+
+        Ok((jar, StatusCode::ACCEPTED).into_response())
+    }
+
+    async fn confirm(&self, Query(link): Query<MagicLinkToken>) -> Result<Response, ApiError> {
+        Ok(page(StatusCode::OK, magic_link_page(&link.token)))
+    }
+
+    async fn verify(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        Form(link): Form<MagicLinkToken>,
+    ) -> Result<Response, ApiError> {
+        let invalid = || {
+            page(
+                StatusCode::BAD_REQUEST,
+                error_page(
+                    "This sign-in link is invalid or has expired. Links only work once, \
+                     and only in the browser they were requested from.",
+                ),
+            )
+        };
+
+        let Some(pending) = self.pending(&jar) else {
+            return Ok(invalid());
+        };
+
+        let Some(redeemed) = self
+            .email_tokens
+            .redeem_email_token(
+                EmailTokenPurpose::MagicLink,
+                &link.token,
+                Some(&pending.secret),
+            )
+            .await?
+        else {
+            return Ok(invalid());
+        };
+
+        // Opening the link proves the address as well as a verification email would.
+        self.users
+            .mark_email_verified(redeemed.user_id, &redeemed.email)
+            .await?;
+
+        // Same as a password login: whatever session the browser had is replaced.
+        if let Some(token) = jar
+            .get(SESSION_COOKIE)
+            .and_then(|cookie| self.cookies.open(cookie))
+        {
+            self.session_store.remove_session(&token).await?;
+        }
+
+        let kind = if pending.remember_me {
+            SessionKind::Persistent
+        } else {
+            SessionKind::Transient
+        };
+
+        let issued = self
+            .session_store
+            .create_session(
+                redeemed.user_id,
+                SessionMeta {
+                    ip: client.ip,
+                    user_agent: client.user_agent,
+                    auth_method: AuthMethod::MagicLink,
+                    kind,
+                    parent_id: None,
+                    client_id: None,
+                },
+            )
+            .await?;
+
+        let jar = jar
+            .remove(Cookie::build(MAGIC_LINK_COOKIE).path(MAGIC_LINK_PATH))
+            .add(self.cookies.session_cookie(&issued));
+
+        Ok((jar, Redirect::to(&self.redirect_url)).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SessionConfig;
+    use crate::infra::mailer::LogMailer;
+    use crate::model::{EmailToken, NewEmailToken, User};
+    use crate::repo::memory_sessions::MemorySessionsRepo;
+    use crate::tokens::TokenHasher;
+    use crate::usecase::email_links::EmailLinkUrls;
+    use crate::usecase::users_usecase::MockIUsersRepository;
+    use axum::http::header::SET_COOKIE;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use url::Url;
+    use uuid::Uuid;
+
+    /// Keeps every issued token, redeems them the way the trait promises.
+    #[derive(Default)]
+    struct FakeEmailTokens {
+        issued: Mutex<Vec<(NewEmailToken, bool)>>,
+    }
+
+    impl FakeEmailTokens {
+        fn issued(&self) -> Vec<NewEmailToken> {
+            let issued = self.issued.lock().unwrap();
+            issued.iter().map(|(token, _)| token.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl IEmailTokensRepo for FakeEmailTokens {
+        async fn issue_email_token(
+            &self,
+            new_token: &NewEmailToken,
+            _: u64,
+        ) -> Result<Option<String>, DBError> {
+            let mut issued = self.issued.lock().unwrap();
+            issued.push((new_token.clone(), false));
+
+            Ok(Some(format!("t0k3n{}", issued.len() - 1)))
+        }
+
+        async fn redeem_email_token(
+            &self,
+            purpose: EmailTokenPurpose,
+            token: &str,
+            browser_secret: Option<&str>,
+        ) -> Result<Option<EmailToken>, DBError> {
+            let mut issued = self.issued.lock().unwrap();
+            let found = token
+                .strip_prefix("t0k3n")
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| issued.get_mut(i))
+                .filter(|(new_token, used)| {
+                    !used
+                        && new_token.purpose == purpose
+                        && new_token.browser_secret.as_deref() == browser_secret
+                });
+
+            Ok(found.map(|(new_token, used)| {
+                *used = true;
+                EmailToken {
+                    user_id: new_token.user_id,
+                    email: new_token.email.clone(),
+                }
+            }))
+        }
+
+        async fn revoke_email_tokens(
+            &self,
+            user_id: Uuid,
+            purpose: EmailTokenPurpose,
+        ) -> Result<(), DBError> {
+            for (new_token, used) in self.issued.lock().unwrap().iter_mut() {
+                if new_token.user_id == user_id && new_token.purpose == purpose {
+                    *used = true;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Fixture {
+        delivery: MagicLinkDelivery,
+        email_tokens: Arc<FakeEmailTokens>,
+        sessions: Arc<MemorySessionsRepo>,
+    }
+
+    fn fixture(users: MockIUsersRepository) -> Fixture {
+        let users = Arc::new(users);
+        let email_tokens = Arc::new(FakeEmailTokens::default());
+        let sessions = Arc::new(MemorySessionsRepo::new(
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+        ));
+
+        let url = |path: &str| Url::parse(&format!("https://auth.example.com{path}")).unwrap();
+        let links = EmailLinks::new(
+            email_tokens.clone(),
+            users.clone(),
+            Arc::new(LogMailer),
+            EmailLinkUrls {
+                verification: url("/verify-email"),
+                email_change: url("/confirm-email"),
+                email_revert: url("/api/v1/email/revert"),
+                password_reset: url("/reset-password"),
+                magic_link: url("/api/v1/login/magic-link/verify"),
+            },
+        );
+
+        Fixture {
+            delivery: MagicLinkDelivery::new(
+                email_tokens.clone(),
+                users,
+                sessions.clone(),
+                Arc::new(links),
+                SessionCookieCodec::new(&[7; 64], None),
+                "https://app.example.com/".to_string(),
+            ),
+            email_tokens,
+            sessions,
+        }
+    }
+
+    fn users(user: User) -> MockIUsersRepository {
+        let mut users = MockIUsersRepository::new();
+        users
+            .expect_login()
+            .returning(move |_| Ok(Some(user.clone())));
+        users
+    }
+
+    fn user() -> User {
+        User {
+            id: Uuid::new_v4(),
+            email: "writer@example.com".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Requests a link from the browser holding `jar`, returns the browser's cookies
+    /// once the link went out.
+    async fn request(fixture: &Fixture, jar: CookieJar, remember_me: bool) -> CookieJar {
+        let sent = fixture.email_tokens.issued().len();
+        let response = fixture
+            .delivery
+            .request(
+                jar.clone(),
+                Json(MagicLinkRequest {
+                    email: "writer@example.com".to_string(),
+                    remember_me,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        // The link is mailed in the background.
+        while fixture.email_tokens.issued().len() == sent {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|header| Cookie::parse_encoded(header.to_str().unwrap().to_string()).unwrap())
+            .fold(jar, |jar, cookie| jar.add(cookie))
+    }
+
+    fn verify(
+        fixture: &Fixture,
+        jar: CookieJar,
+        token: &str,
+    ) -> impl Future<Output = Result<Response, ApiError>> {
+        fixture.delivery.verify(
+            jar,
+            ClientInfo {
+                ip: None,
+                user_agent: None,
+            },
+            Form(MagicLinkToken {
+                token: token.to_string(),
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_link_works_once_and_only_in_the_requesting_browser() {
+        let user = user();
+        let mut users = users(user.clone());
+        users
+            .expect_mark_email_verified()
+            .times(1)
+            .returning(|_, _| Ok(true));
+        let fixture = fixture(users);
+
+        let browser = request(&fixture, CookieJar::new(), true).await;
+        let other_browser = request(&fixture, CookieJar::new(), true).await;
+
+        let status = |response: Result<Response, ApiError>| response.unwrap().status();
+
+        assert_eq!(
+            status(verify(&fixture, CookieJar::new(), "t0k3n0").await),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(verify(&fixture, other_browser, "t0k3n0").await),
+            StatusCode::BAD_REQUEST
+        );
+
+        let response = verify(&fixture, browser.clone(), "t0k3n0").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert!(
+            response
+                .headers()
+                .get_all(SET_COOKIE)
+                .iter()
+                .any(|header| header.to_str().unwrap().starts_with(SESSION_COOKIE))
+        );
+
+        let sessions = fixture.sessions.list_sessions(user.id).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].auth_method, AuthMethod::MagicLink);
+        assert_eq!(sessions[0].kind, SessionKind::Persistent);
+
+        assert_eq!(
+            status(verify(&fixture, browser, "t0k3n0").await),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_browser_keeps_its_secret_across_requests() {
+        let fixture = fixture(users(user()));
+
+        let browser = request(&fixture, CookieJar::new(), false).await;
+        let _ = request(&fixture, browser, false).await;
+        let _ = request(&fixture, CookieJar::new(), false).await;
+
+        // A throttled second request leaves the first link usable.
+        let secrets: Vec<_> = fixture
+            .email_tokens
+            .issued()
+            .into_iter()
+            .map(|new_token| new_token.browser_secret.unwrap())
+            .collect();
+        assert_eq!(secrets[0], secrets[1]);
+        assert_ne!(secrets[0], secrets[2]);
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_sent_to_unknown_addresses() {
+        let mut users = MockIUsersRepository::new();
+        users.expect_login().times(1).returning(|_| Ok(None));
+        let fixture = fixture(users);
+
+        let response = fixture
+            .delivery
+            .request(
+                CookieJar::new(),
+                Json(MagicLinkRequest {
+                    email: "nobody@example.com".to_string(),
+                    remember_me: false,
+                }),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(fixture.email_tokens.issued().is_empty());
+    }
+}
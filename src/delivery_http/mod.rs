@@ -1,2 +1,14 @@
+pub mod bearer;
+pub mod calling_service;
+pub mod client_auth;
+pub mod client_info;
 pub mod dto;
+pub mod email_verification_delivery;
+pub mod external_login_delivery;
+pub mod magic_link_delivery;
+pub mod oauth_delivery;
+pub mod pages;
+pub mod password_reset_delivery;
+pub mod session_cookie;
+pub mod tokens_delivery;
 pub mod users_delivery;
@@ -0,0 +1,581 @@
+use crate::app::IOAuthDelivery;
+use crate::delivery_grpc::users_delivery::IUserIDGetter;
+use crate::delivery_http::bearer::BearerToken;
+use crate::delivery_http::client_info::ClientInfo;
+use crate::delivery_http::dto::{
+    AuthorizeForm, AuthorizeRequest, LoginRequest, OAuthConsentResponse,
+};
+use crate::delivery_http::pages::{consent_page, error_page, login_page, page};
+use crate::delivery_http::session_cookie::{SESSION_COOKIE, SessionCookieCodec};
+use crate::delivery_http::tokens_delivery::requested_scopes;
+use crate::delivery_http::users_delivery::{ISessionStore, IUsersCreatorUsecase};
+use crate::errors::{ApiError, DBError, UsecaseError};
+use crate::model::{
+    AuthMethod, AuthorizationCode, OAuthClient, OAuthConsent, Session, SessionKind, SessionMeta,
+};
+use crate::tokens::{generate_token, is_api_key};
+use async_trait::async_trait;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::{Form, Json};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use std::sync::Arc;
+use url::Url;
+use uuid::Uuid;
+
+const CODE_RESPONSE_TYPE: &str = "code";
+const PKCE_METHOD: &str = "S256";
+
+/// Double-submit token of the login and consent forms. The session cookie is
+/// `SameSite=None`, so without it any site could post an approval on the user's behalf.
+const CSRF_COOKIE: &str = "oauth_csrf";
+const AUTHORIZE_PATH: &str = "/oauth/authorize";
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait IOAuthRepo: Send + Sync {
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, DBError>;
+    /// Confidential clients must present their secret, public ones must present none.
+    async fn authenticate_client<'a>(
+        &self,
+        client_id: &str,
+        client_secret: Option<&'a str>,
+    ) -> Result<Option<OAuthClient>, DBError>;
+    async fn granted_scopes(&self, user_id: Uuid, client_id: &str) -> Result<Vec<String>, DBError>;
+    /// Adds to the scopes already granted to the client.
+    async fn grant_consent(
+        &self,
+        user_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<(), DBError>;
+    async fn list_consents(&self, user_id: Uuid) -> Result<Vec<OAuthConsent>, DBError>;
+    async fn revoke_consent(&self, user_id: Uuid, client_id: &str) -> Result<bool, DBError>;
+    async fn create_authorization_code(&self, grant: &AuthorizationCode)
+    -> Result<String, DBError>;
+    /// Single use: the first redemption burns the code, whatever its outcome.
+    async fn redeem_authorization_code(
+        &self,
+        code: &str,
+    ) -> Result<Option<AuthorizationCode>, DBError>;
+}
+
+/// A checked authorization request.
+struct Authorization {
+    client: OAuthClient,
+    /// Exactly as registered, the token request has to repeat it when it was sent.
+    redirect_uri: String,
+    redirect_uri_sent: bool,
+    scopes: Vec<String>,
+    state: Option<String>,
+    code_challenge: String,
+    nonce: Option<String>,
+}
+
+impl Authorization {
+    fn redirect(&self, params: &[(&str, &str)]) -> Response {
+        let uri = Url::parse(&self.redirect_uri).expect("redirect URI was parsed when checked");
+        redirect(uri, self.state.as_deref(), params)
+    }
+
+    fn error(&self, error: &str, description: &str) -> Response {
+        self.redirect(&[("error", error), ("error_description", description)])
+    }
+}
+
+/// Sends the user back to the client, echoing its `state`.
+fn redirect(mut uri: Url, state: Option<&str>, params: &[(&str, &str)]) -> Response {
+    {
+        let mut query = uri.query_pairs_mut();
+        query.extend_pairs(params);
+        if let Some(state) = state {
+            query.append_pair("state", state);
+        }
+    }
+
+    Redirect::to(uri.as_str()).into_response()
+}
+
+pub struct OAuthDelivery {
+    oauth: Arc<dyn IOAuthRepo>,
+    usecase: Arc<dyn IUsersCreatorUsecase>,
+    session_store: Arc<dyn ISessionStore>,
+    user_id_getter: Arc<dyn IUserIDGetter>,
+    cookies: SessionCookieCodec,
+}
+
+impl OAuthDelivery {
+    pub fn new(
+        oauth: Arc<dyn IOAuthRepo>,
+        usecase: Arc<dyn IUsersCreatorUsecase>,
+        session_store: Arc<dyn ISessionStore>,
+        user_id_getter: Arc<dyn IUserIDGetter>,
+        cookies: SessionCookieCodec,
+    ) -> Self {
+        OAuthDelivery {
+            oauth,
+            usecase,
+            session_store,
+            user_id_getter,
+            cookies,
+        }
+    }
+
+    /// Same rules as the user endpoints: the bearer token wins and API keys are not sessions.
+    async fn authenticate(
+        &self,
+        jar: &CookieJar,
+        bearer: &BearerToken,
+    ) -> Result<Option<Session>, ApiError> {
+        let Some(token) = bearer.0.clone().or_else(|| {
+            jar.get(SESSION_COOKIE)
+                .and_then(|cookie| self.cookies.open(cookie))
+        }) else {
+            return Ok(None);
+        };
+
+        if is_api_key(&token) {
+            return Ok(None);
+        }
+
+        Ok(self.user_id_getter.get_user(&token).await?)
+    }
+
+    /// Without a known client and one of its registered redirect URIs there is
+    /// nowhere safe to send the user back to, so those errors are shown in place.
+    /// Past that point errors go back to the client through the redirect URI.
+    async fn check(&self, request: &AuthorizeRequest) -> Result<Authorization, Response> {
+        let client = match self.oauth.get_client(&request.client_id).await {
+            Ok(Some(client)) => client,
+            Ok(None) => return Err(page(StatusCode::BAD_REQUEST, error_page("Unknown client."))),
+            Err(e) => return Err(ApiError::from(e).into_response()),
+        };
+
+        let redirect_uri = match request.redirect_uri.as_deref() {
+            Some(uri) => client
+                .redirect_uris
+                .iter()
+                .find(|registered| *registered == uri),
+            None if client.redirect_uris.len() == 1 => client.redirect_uris.first(),
+            None => None,
+        };
+
+        let Some((redirect_uri, parsed)) =
+            redirect_uri.and_then(|uri| Url::parse(uri).ok().map(|parsed| (uri.clone(), parsed)))
+        else {
+            return Err(page(
+                StatusCode::BAD_REQUEST,
+                error_page("The redirect URI is not registered for this client."),
+            ));
+        };
+
+        let state = request.state.as_deref();
+        let error = |error: &str, description: &str| {
+            redirect(
+                parsed.clone(),
+                state,
+                &[("error", error), ("error_description", description)],
+            )
+        };
+
+        if request.response_type.as_deref() != Some(CODE_RESPONSE_TYPE) {
+            return Err(error(
+                "unsupported_response_type",
+                "only the code response type is supported",
+            ));
+        }
+
+        let Some(code_challenge) = request.code_challenge.clone() else {
+            return Err(error("invalid_request", "code_challenge is required"));
+        };
+
+        if request.code_challenge_method.as_deref() != Some(PKCE_METHOD) {
+            return Err(error(
+                "invalid_request",
+                "code_challenge_method must be S256",
+            ));
+        }
+
+        let Ok(scopes) = requested_scopes(&client.scopes, request.scope.as_deref()) else {
+            return Err(error(
+                "invalid_scope",
+                "requested scope is not allowed for this client",
+            ));
+        };
+
+        Ok(Authorization {
+            client,
+            redirect_uri,
+            redirect_uri_sent: request.redirect_uri.is_some(),
+            scopes,
+            state: request.state.clone(),
+            code_challenge,
+            nonce: request.nonce.clone(),
+        })
+    }
+
+    /// Redirects with a code when the user already approved every requested scope,
+    /// asks for consent otherwise.
+    async fn complete(
+        &self,
+        jar: CookieJar,
+        request: &AuthorizeRequest,
+        authorization: Authorization,
+        session: &Session,
+        csrf_token: &str,
+    ) -> Result<Response, ApiError> {
+        let granted = self
+            .oauth
+            .granted_scopes(session.user_id, &authorization.client.client_id)
+            .await?;
+
+        if authorization
+            .scopes
+            .iter()
+            .all(|scope| granted.contains(scope))
+        {
+            return self.issue_code(jar, authorization, session).await;
+        }
+
+        let body = consent_page(
+            request,
+            &authorization.client.name,
+            &authorization.scopes,
+            csrf_token,
+        );
+
+        Ok((jar, page(StatusCode::OK, body)).into_response())
+    }
+
+    async fn issue_code(
+        &self,
+        jar: CookieJar,
+        authorization: Authorization,
+        session: &Session,
+    ) -> Result<Response, ApiError> {
+        let code = self
+            .oauth
+            .create_authorization_code(&AuthorizationCode {
+                client_id: authorization.client.client_id.clone(),
+                user_id: session.user_id,
+                session_id: session.id,
+                redirect_uri: authorization.redirect_uri.clone(),
+                redirect_uri_sent: authorization.redirect_uri_sent,
+                scopes: authorization.scopes.clone(),
+                code_challenge: authorization.code_challenge.clone(),
+                nonce: authorization.nonce.clone(),
+            })
+            .await?;
+
+        Ok((jar, authorization.redirect(&[("code", &code)])).into_response())
+    }
+
+    /// Logs the user in with the credentials from the login form, the same check
+    /// as `POST /api/v1/login`. Wrong credentials render the form again.
+    async fn login(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        form: &AuthorizeForm,
+        client_name: &str,
+    ) -> Result<(CookieJar, Session), Response> {
+        let login_failed = |error: &str, status: StatusCode| {
+            page(
+                status,
+                login_page(&form.request, client_name, &form.csrf_token, Some(error)),
+            )
+        };
+
+        let (Some(email), Some(password)) = (&form.email, &form.password) else {
+            return Err(login_failed(
+                "Enter your email and password.",
+                StatusCode::BAD_REQUEST,
+            ));
+        };
+
+        let remember_me = form.remember_me.is_some();
+
+        let user = match self
+            .usecase
+            .login(LoginRequest {
+                email: email.clone(),
+                password: password.clone(),
+                remember_me,
+                issue_access_token: false,
+                cookieless: false,
+            })
+            .await
+        {
+            Ok(user) => user,
+            Err(UsecaseError::UserNotFoundError | UsecaseError::InvalidCreds) => {
+                return Err(login_failed(
+                    "Invalid email or password.",
+                    StatusCode::UNAUTHORIZED,
+                ));
+            }
+            Err(UsecaseError::EmailNotVerified) => {
+                return Err(login_failed(
+                    "Confirm your email address first, follow the link we sent you.",
+                    StatusCode::FORBIDDEN,
+                ));
+            }
+            Err(e) => return Err(ApiError::from(e).into_response()),
+        };
+
+        let kind = if remember_me {
+            SessionKind::Persistent
+        } else {
+            SessionKind::Transient
+        };
+
+        let issued = self
+            .session_store
+            .create_session(
+                user.id,
+                SessionMeta {
+                    ip: client.ip,
+                    user_agent: client.user_agent,
+                    auth_method: AuthMethod::Password,
+                    kind,
+                    parent_id: None,
+                    client_id: None,
+                },
+            )
+            .await
+            .map_err(|e| ApiError::from(e).into_response())?;
+
+        Ok((
+            jar.add(self.cookies.session_cookie(&issued)),
+            issued.session,
+        ))
+    }
+}
+
+fn csrf_cookie(token: String) -> Cookie<'static> {
+    Cookie::build((CSRF_COOKIE, token))
+        .path(AUTHORIZE_PATH)
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+#[async_trait]
+impl IOAuthDelivery for OAuthDelivery {
+    async fn authorize(
+        &self,
+        jar: CookieJar,
+        Query(request): Query<AuthorizeRequest>,
+    ) -> Result<Response, ApiError> {
+        let authorization = match self.check(&request).await {
+            Ok(authorization) => authorization,
+            Err(response) => return Ok(response),
+        };
+
+        let csrf_token = jar
+            .get(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_string())
+            .unwrap_or_else(generate_token);
+        let jar = jar.add(csrf_cookie(csrf_token.clone()));
+
+        let Some(session) = self.authenticate(&jar, &BearerToken(None)).await? else {
+            let body = login_page(&request, &authorization.client.name, &csrf_token, None);
+            return Ok((jar, page(StatusCode::OK, body)).into_response());
+        };
+
+        self.complete(jar, &request, authorization, &session, &csrf_token)
+            .await
+    }
+
+    async fn authorize_submit(
+        &self,
+        jar: CookieJar,
+        client: ClientInfo,
+        Form(form): Form<AuthorizeForm>,
+    ) -> Result<Response, ApiError> {
+        let authorization = match self.check(&form.request).await {
+            Ok(authorization) => authorization,
+            Err(response) => return Ok(response),
+        };
+
+        if jar.get(CSRF_COOKIE).map(|cookie| cookie.value()) != Some(form.csrf_token.as_str()) {
+            return Ok(page(
+                StatusCode::FORBIDDEN,
+                error_page(
+                    "The form has expired, please go back to the application and try again.",
+                ),
+            ));
+        }
+
+        let (jar, session) = match self.authenticate(&jar, &BearerToken(None)).await? {
+            Some(session) => (jar, session),
+            None => match self
+                .login(jar, client, &form, &authorization.client.name)
+                .await
+            {
+                Ok(logged_in) => logged_in,
+                Err(response) => return Ok(response),
+            },
+        };
+
+        match form.consent.as_deref() {
+            Some("deny") => Ok((
+                jar,
+                authorization.error("access_denied", "the user denied the request"),
+            )
+                .into_response()),
+            Some("allow") => {
+                self.oauth
+                    .grant_consent(
+                        session.user_id,
+                        &authorization.client.client_id,
+                        &authorization.scopes,
+                    )
+                    .await?;
+
+                self.issue_code(jar, authorization, &session).await
+            }
+            _ => {
+                self.complete(
+                    jar,
+                    &form.request,
+                    authorization,
+                    &session,
+                    &form.csrf_token,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn list_consents(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        let consents: Vec<OAuthConsentResponse> = self
+            .oauth
+            .list_consents(session.user_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok((StatusCode::OK, Json(consents)).into_response())
+    }
+
+    async fn revoke_consent(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Path(client_id): Path<String>,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if !self
+            .oauth
+            .revoke_consent(session.user_id, &client_id)
+            .await?
+        {
+            return Err(DBError::ConsentNotFound.into());
+        }
+
+        // Tokens issued under the consent go with it.
+        for granted in self.session_store.list_sessions(session.user_id).await? {
+            if granted.client_id.as_deref() == Some(client_id.as_str()) {
+                self.session_store
+                    .remove_user_session(granted.user_id, granted.id)
+                    .await?;
+            }
+        }
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EmailVerificationPolicy, SessionConfig};
+    use crate::model::IssuedSession;
+    use crate::repo::memory_sessions::MemorySessionsRepo;
+    use crate::tokens::TokenHasher;
+    use crate::usecase::users_usecase::{MockIEmailVerifier, MockIUsersRepository, UserUsecase};
+
+    #[tokio::test]
+    async fn test_revoking_consent_revokes_its_tokens() {
+        let sessions = Arc::new(MemorySessionsRepo::new(
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+        ));
+        let cookie = sessions
+            .create_session(
+                Uuid::new_v4(),
+                SessionMeta {
+                    ip: None,
+                    user_agent: None,
+                    auth_method: AuthMethod::Password,
+                    kind: SessionKind::Transient,
+                    parent_id: None,
+                    client_id: None,
+                },
+            )
+            .await
+            .unwrap();
+        let user_id = cookie.session.user_id;
+
+        let mut granted = vec![];
+        for client_id in ["app_test", "app_other"] {
+            let meta = SessionMeta {
+                client_id: Some(client_id.to_string()),
+                ..SessionMeta::token_family(&cookie.session)
+            };
+            granted.push(sessions.create_session(user_id, meta).await.unwrap());
+        }
+
+        let mut oauth = MockIOAuthRepo::new();
+        oauth
+            .expect_revoke_consent()
+            .times(1)
+            .withf(move |id, client_id| *id == user_id && client_id == "app_test")
+            .returning(|_, _| Ok(true));
+        let usecase = UserUsecase::new(
+            Arc::new(MockIUsersRepository::new()),
+            Arc::new(MockIEmailVerifier::new()),
+            EmailVerificationPolicy::Restrict,
+        );
+        let delivery = OAuthDelivery::new(
+            Arc::new(oauth),
+            Arc::new(usecase),
+            sessions.clone(),
+            sessions.clone(),
+            SessionCookieCodec::new(&[0; 64], None),
+        );
+
+        let response = delivery
+            .revoke_consent(
+                CookieJar::new(),
+                BearerToken(Some(cookie.token.clone())),
+                Path("app_test".to_string()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let live = |issued: &IssuedSession| sessions.get_session(user_id, issued.session.id);
+        assert!(live(&granted[0]).await.unwrap().is_none());
+        assert!(live(&granted[1]).await.unwrap().is_some());
+        assert!(live(&cookie).await.unwrap().is_some());
+    }
+}
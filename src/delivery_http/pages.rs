@@ -0,0 +1,184 @@
+use crate::delivery_http::dto::AuthorizeRequest;
+use axum::http::header::{CACHE_CONTROL, X_FRAME_OPTIONS};
+use axum::http::{HeaderName, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
+
+const CONTENT_SECURITY_POLICY: HeaderName = HeaderName::from_static("content-security-policy");
+
+/// Minimal server-rendered pages of the browser sign-in flows.
+///
+/// They must never be framed by another site, or the consent button could be
+/// clickjacked, and must not be cached since they carry CSRF and sign-in tokens.
+pub fn page(status: StatusCode, body: String) -> Response {
+    (
+        status,
+        [
+            (X_FRAME_OPTIONS, "DENY"),
+            (CONTENT_SECURITY_POLICY, "frame-ancestors 'none'"),
+            (CACHE_CONTROL, "no-store"),
+        ],
+        Html(body),
+    )
+        .into_response()
+}
+
+pub fn login_page(
+    request: &AuthorizeRequest,
+    client_name: &str,
+    csrf_token: &str,
+    error: Option<&str>,
+) -> String {
+    let error = error
+        .map(|error| format!(r#"<p class="error">{}</p>"#, escape(error)))
+        .unwrap_or_default();
+
+    layout(&format!(
+        r#"<h1>Sign in to continue to {client}</h1>
+{error}<form method="post" action="/oauth/authorize">
+{hidden}
+<label>Email <input type="email" name="email" required autofocus></label>
+<label>Password <input type="password" name="password" required></label>
+<label><input type="checkbox" name="remember_me"> Remember me</label>
+<button type="submit">Sign in</button>
+</form>"#,
+        client = escape(client_name),
+        hidden = hidden_fields(request, csrf_token),
+    ))
+}
+
+pub fn consent_page(
+    request: &AuthorizeRequest,
+    client_name: &str,
+    scopes: &[String],
+    csrf_token: &str,
+) -> String {
+    let scopes: String = scopes
+        .iter()
+        .map(|scope| format!("<li>{}</li>", escape(scope)))
+        .collect();
+
+    layout(&format!(
+        r#"<h1>{client} wants to access your account</h1>
+<ul>{scopes}</ul>
+<form method="post" action="/oauth/authorize">
+{hidden}
+<button type="submit" name="consent" value="allow">Allow</button>
+<button type="submit" name="consent" value="deny">Deny</button>
+</form>"#,
+        client = escape(client_name),
+        hidden = hidden_fields(request, csrf_token),
+    ))
+}
+
+/// Redeeming a magic link takes a click, so link scanners fetching it
+/// do not burn the single-use token.
+pub fn magic_link_page(token: &str) -> String {
+    layout(&format!(
+        r#"<h1>Sign in</h1>
+<form method="post" action="/api/v1/login/magic-link/verify">
+<input type="hidden" name="token" value="{}">
+<button type="submit">Continue</button>
+</form>"#,
+        escape(token)
+    ))
+}
+
+/// Like [`magic_link_page`], a click keeps link scanners from undoing the change.
+pub fn email_revert_page(token: &str) -> String {
+    layout(&format!(
+        r#"<h1>Restore your email</h1>
+<p>Change the email of your account back, sign out everywhere and revoke its password and API keys.</p>
+<form method="post" action="/api/v1/email/revert">
+<input type="hidden" name="token" value="{}">
+<button type="submit">Restore</button>
+</form>"#,
+        escape(token)
+    ))
+}
+
+pub fn message_page(heading: &str, message: &str) -> String {
+    layout(&format!(
+        "<h1>{}</h1>\n<p>{}</p>",
+        escape(heading),
+        escape(message)
+    ))
+}
+
+pub fn error_page(message: &str) -> String {
+    message_page("Sign-in failed", message)
+}
+
+fn layout(content: &str) -> String {
+    format!(
+        r#"<!doctype html>
+<html>
+<head><meta charset="utf-8"><title>Sign in</title></head>
+<body>
+{content}
+</body>
+</html>"#
+    )
+}
+
+fn hidden_fields(request: &AuthorizeRequest, csrf_token: &str) -> String {
+    [
+        ("client_id", Some(&request.client_id)),
+        ("response_type", request.response_type.as_ref()),
+        ("redirect_uri", request.redirect_uri.as_ref()),
+        ("scope", request.scope.as_ref()),
+        ("state", request.state.as_ref()),
+        ("code_challenge", request.code_challenge.as_ref()),
+        (
+            "code_challenge_method",
+            request.code_challenge_method.as_ref(),
+        ),
+        ("nonce", request.nonce.as_ref()),
+        ("csrf_token", Some(&csrf_token.to_string())),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| {
+        value.map(|value| {
+            format!(
+                r#"<input type="hidden" name="{name}" value="{}">"#,
+                escape(value)
+            )
+        })
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_values_are_escaped() {
+        let request = AuthorizeRequest {
+            client_id: "app_test".to_string(),
+            response_type: Some("code".to_string()),
+            redirect_uri: None,
+            scope: None,
+            state: Some(r#""><script>alert(1)</script>"#.to_string()),
+            code_challenge: None,
+            code_challenge_method: None,
+            nonce: None,
+        };
+
+        let html = login_page(&request, "<b>App</b>", "csrf", None);
+
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>App</b>"));
+        assert!(html.contains(r#"value="&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;""#));
+        assert!(!html.contains(r#"name="redirect_uri""#));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::app::IPasswordResetDelivery;
+use crate::delivery_http::dto::{ForgotPasswordRequest, ResetPasswordRequest};
+use crate::delivery_http::users_delivery::{ISessionStore, IUsersCreatorUsecase};
+use crate::errors::{ApiError, DBError};
+use crate::model::EmailTokenPurpose;
+use crate::usecase::email_links::{EmailLinks, IEmailTokensRepo};
+use crate::usecase::users_usecase::IUsersRepository;
+use async_trait::async_trait;
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use std::sync::Arc;
+use validator::Validate;
+
+pub struct PasswordResetDelivery {
+    email_tokens: Arc<dyn IEmailTokensRepo>,
+    users: Arc<dyn IUsersRepository>,
+    usecase: Arc<dyn IUsersCreatorUsecase>,
+    session_store: Arc<dyn ISessionStore>,
+    links: Arc<EmailLinks>,
+}
+
+impl PasswordResetDelivery {
+    pub fn new(
+        email_tokens: Arc<dyn IEmailTokensRepo>,
+        users: Arc<dyn IUsersRepository>,
+        usecase: Arc<dyn IUsersCreatorUsecase>,
+        session_store: Arc<dyn ISessionStore>,
+        links: Arc<EmailLinks>,
+    ) -> Self {
+        PasswordResetDelivery {
+            email_tokens,
+            users,
+            usecase,
+            session_store,
+            links,
+        }
+    }
+}
+
+#[async_trait]
+impl IPasswordResetDelivery for PasswordResetDelivery {
+    async fn forgot(
+        &self,
+        Json(payload): Json<ForgotPasswordRequest>,
+    ) -> Result<Response, ApiError> {
+        payload.validate()?;
+
+        self.links.send_password_reset(payload.email);
+
+        Ok(StatusCode::ACCEPTED.into_response())
+    }
+
+    async fn reset(&self, Json(payload): Json<ResetPasswordRequest>) -> Result<Response, ApiError> {
+        payload.validate()?;
+
+        let Some(redeemed) = self
+            .email_tokens
+            .redeem_email_token(EmailTokenPurpose::PasswordReset, &payload.token, None)
+            .await?
+        else {
+            return Err(DBError::InvalidEmailToken.into());
+        };
+
+        // A link sent to an address the user no longer has must not take over the account.
+        if !self
+            .usecase
+            .reset_password(redeemed.user_id, &redeemed.email, &payload.password)
+            .await?
+        {
+            return Err(DBError::InvalidEmailToken.into());
+        }
+
+        // Whoever knew the old password is signed out along with everyone else.
+        self.session_store
+            .revoke_all_sessions(redeemed.user_id)
+            .await?;
+        // Receiving the link proves the address as well as a verification email would.
+        self.users
+            .mark_email_verified(redeemed.user_id, &redeemed.email)
+            .await?;
+
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+}
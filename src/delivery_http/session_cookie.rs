@@ -0,0 +1,151 @@
+use crate::model::{IssuedSession, SessionKind};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use chrono::Utc;
+use cookie::{CookieJar, Key};
+use time::Duration;
+use uuid::Uuid;
+
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// Encrypts and authenticates the session cookie, so a forged or tampered value
+/// is rejected without ever reaching the session store.
+///
+/// Cookies are always sealed with the current key. The previous key is only used
+/// to open cookies issued before a rotation.
+#[derive(Clone)]
+pub struct SessionCookieCodec {
+    current: Key,
+    previous: Option<Key>,
+    accept_legacy: bool,
+}
+
+impl SessionCookieCodec {
+    pub fn new(current: &[u8], previous: Option<&[u8]>) -> Self {
+        SessionCookieCodec {
+            current: Key::derive_from(current),
+            previous: previous.map(Key::derive_from),
+            accept_legacy: false,
+        }
+    }
+
+    /// Also accepts the plain session UUIDs cookies carried before they were sealed,
+    /// so sessions moved over by the legacy key migration keep their users signed in.
+    pub fn accept_legacy_values(mut self) -> Self {
+        self.accept_legacy = true;
+        self
+    }
+
+    /// Persistent sessions get a cookie that lives until their absolute expiry,
+    /// transient ones a browser-session cookie without Max-Age.
+    /// The idle timeout is enforced by the session store.
+    pub fn session_cookie(&self, issued: &IssuedSession) -> Cookie<'static> {
+        let session = &issued.session;
+        let mut cookie = Cookie::build((SESSION_COOKIE, issued.token.clone()))
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::None);
+
+        if session.kind == SessionKind::Persistent {
+            let max_age = (session.expires_at - Utc::now()).num_seconds().max(0);
+            cookie = cookie.max_age(Duration::seconds(max_age));
+        }
+
+        self.seal(cookie.build())
+    }
+
+    pub fn seal(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+        let name = cookie.name().to_string();
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.current).add(cookie);
+
+        jar.get(&name)
+            .cloned()
+            .expect("cookie was just added to the jar")
+    }
+
+    /// Returns the plain value carried by a sealed cookie.
+    pub fn open(&self, cookie: &Cookie<'_>) -> Option<String> {
+        self.decrypt(cookie.name(), cookie.value())
+    }
+
+    /// Opens a session cookie value that arrived without its cookie, e.g. over gRPC.
+    pub fn open_value(&self, value: &str) -> Option<String> {
+        self.decrypt(SESSION_COOKIE, value)
+    }
+
+    /// The cookie name is authenticated along with the value,
+    /// so a cookie cannot be replayed under another name.
+    fn decrypt(&self, name: &str, value: &str) -> Option<String> {
+        let sealed = Cookie::new(name.to_string(), value.to_string());
+        let jar = CookieJar::new();
+
+        std::iter::once(&self.current)
+            .chain(self.previous.as_ref())
+            .find_map(|key| jar.private(key).decrypt(sealed.clone()))
+            .map(|cookie| cookie.value().to_string())
+            .or_else(|| self.legacy_value(value))
+    }
+
+    fn legacy_value(&self, value: &str) -> Option<String> {
+        (self.accept_legacy && Uuid::try_parse(value).is_ok()).then(|| value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: &[u8] = b"old-cookie-key-old-cookie-key-old-cookie-key";
+    const NEW_KEY: &[u8] = b"new-cookie-key-new-cookie-key-new-cookie-key";
+
+    #[test]
+    fn test_seal_and_open() {
+        let codec = SessionCookieCodec::new(NEW_KEY, None);
+        let sealed = codec.seal(Cookie::new(SESSION_COOKIE, "token"));
+
+        assert_ne!(sealed.value(), "token");
+        assert_eq!(codec.open(&sealed).as_deref(), Some("token"));
+    }
+
+    #[test]
+    fn test_rejects_tampered_and_plain_values() {
+        let codec = SessionCookieCodec::new(NEW_KEY, None);
+        let sealed = codec.seal(Cookie::new(SESSION_COOKIE, "token"));
+
+        let mut tampered = sealed.value().to_string();
+        tampered.replace_range(0..1, if tampered.starts_with('A') { "B" } else { "A" });
+
+        assert!(codec.open_value(&tampered).is_none());
+        assert!(codec.open_value("token").is_none());
+    }
+
+    #[test]
+    fn test_legacy_values_are_accepted_during_migration() {
+        let legacy = Uuid::new_v4().to_string();
+        let codec = SessionCookieCodec::new(NEW_KEY, None);
+        let migrating = SessionCookieCodec::new(NEW_KEY, None).accept_legacy_values();
+
+        assert!(codec.open_value(&legacy).is_none());
+        assert_eq!(migrating.open_value(&legacy), Some(legacy.clone()));
+        assert_eq!(
+            migrating
+                .open(&Cookie::new(SESSION_COOKIE, legacy.clone()))
+                .as_deref(),
+            Some(legacy.as_str())
+        );
+        assert!(migrating.open_value("token").is_none());
+    }
+
+    #[test]
+    fn test_previous_key_is_accepted_after_rotation() {
+        let old = SessionCookieCodec::new(OLD_KEY, None);
+        let sealed = old.seal(Cookie::new(SESSION_COOKIE, "token"));
+
+        let rotated = SessionCookieCodec::new(NEW_KEY, Some(OLD_KEY));
+        let without_previous = SessionCookieCodec::new(NEW_KEY, None);
+
+        assert_eq!(rotated.open(&sealed).as_deref(), Some("token"));
+        assert!(without_previous.open(&sealed).is_none());
+    }
+}
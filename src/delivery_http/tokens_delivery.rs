@@ -0,0 +1,989 @@
+use crate::app::ITokensDelivery;
+use crate::delivery_http::bearer::BearerToken;
+use crate::delivery_http::calling_service::CallingService;
+use crate::delivery_http::client_auth::BasicCredentials;
+use crate::delivery_http::dto::{
+    AccessTokenResponse, IntrospectionResponse, OAuthTokenActionRequest, OAuthTokenRequest,
+    OAuthTokenResponse, RefreshTokenRequest, ServiceIdentityResponse,
+};
+use crate::delivery_http::oauth_delivery::IOAuthRepo;
+use crate::delivery_http::users_delivery::{IApiKeysRepo, ISessionStore, IUsersRepo};
+use crate::errors::{ApiError, DBError, OAuthError, TokenError};
+use crate::jwt::{AccessClaims, AccessTokenIssuer, Jwks};
+use crate::model::{ApiKey, OAuthClient, ServiceAccount, Session, SessionMeta};
+use crate::oidc::{OPENID_SCOPE, ProviderMetadata, UserInfo};
+use crate::tokens::{is_api_key, verify_pkce};
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
+use std::sync::Arc;
+use uuid::Uuid;
+
+const CLIENT_CREDENTIALS_GRANT: &str = "client_credentials";
+const AUTHORIZATION_CODE_GRANT: &str = "authorization_code";
+
+const ACCESS_TOKEN_TYPE: &str = "access_token";
+const REFRESH_TOKEN_TYPE: &str = "refresh_token";
+const SESSION_TOKEN_TYPE: &str = "session_token";
+const API_KEY_TYPE: &str = "api_key";
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait IServiceAccountsRepo: Send + Sync {
+    async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<Option<ServiceAccount>, DBError>;
+}
+
+/// A client calling the introspection or revocation endpoint.
+enum TokenClient {
+    Service,
+    /// An application using the authorization code grant, it only gets to see its own tokens.
+    App(OAuthClient),
+}
+
+/// A token presented for introspection or revocation, resolved to what it grants.
+enum PresentedToken {
+    Access(AccessClaims),
+    Session(Session),
+    Refresh(Session),
+    ApiKey(ApiKey),
+}
+
+pub struct TokensDelivery {
+    users: Arc<dyn IUsersRepo>,
+    session_store: Arc<dyn ISessionStore>,
+    api_keys: Arc<dyn IApiKeysRepo>,
+    service_accounts: Arc<dyn IServiceAccountsRepo>,
+    oauth: Arc<dyn IOAuthRepo>,
+    access_tokens: Option<Arc<AccessTokenIssuer>>,
+}
+
+impl TokensDelivery {
+    pub fn new(
+        users: Arc<dyn IUsersRepo>,
+        session_store: Arc<dyn ISessionStore>,
+        api_keys: Arc<dyn IApiKeysRepo>,
+        service_accounts: Arc<dyn IServiceAccountsRepo>,
+        oauth: Arc<dyn IOAuthRepo>,
+        access_tokens: Option<Arc<AccessTokenIssuer>>,
+    ) -> Self {
+        TokensDelivery {
+            users,
+            session_store,
+            api_keys,
+            service_accounts,
+            oauth,
+            access_tokens,
+        }
+    }
+
+    fn issuer(&self) -> Result<&AccessTokenIssuer, ApiError> {
+        self.access_tokens
+            .as_deref()
+            .ok_or(TokenError::AccessTokensDisabled.into())
+    }
+
+    /// Authenticates a service account for the client credentials grant.
+    async fn authenticate_client(
+        &self,
+        basic: BasicCredentials,
+        client_id: &Option<String>,
+        client_secret: &Option<String>,
+    ) -> Result<ServiceAccount, ApiError> {
+        let (client_id, Some(client_secret)) = client_credentials(basic, client_id, client_secret)?
+        else {
+            return Err(OAuthError::InvalidClient.into());
+        };
+
+        self.service_accounts
+            .authenticate_client(&client_id, &client_secret)
+            .await?
+            .ok_or(OAuthError::InvalidClient.into())
+    }
+
+    /// Service accounts and applications may both introspect and revoke tokens.
+    /// Public applications identify themselves with their client id alone.
+    async fn authenticate_token_client(
+        &self,
+        basic: BasicCredentials,
+        client_id: &Option<String>,
+        client_secret: &Option<String>,
+    ) -> Result<TokenClient, ApiError> {
+        let (client_id, client_secret) = client_credentials(basic, client_id, client_secret)?;
+
+        if let Some(client_secret) = &client_secret
+            && self
+                .service_accounts
+                .authenticate_client(&client_id, client_secret)
+                .await?
+                .is_some()
+        {
+            return Ok(TokenClient::Service);
+        }
+
+        self.oauth
+            .authenticate_client(&client_id, client_secret.as_deref())
+            .await?
+            .map(TokenClient::App)
+            .ok_or(OAuthError::InvalidClient.into())
+    }
+
+    fn client_credentials(
+        issuer: &AccessTokenIssuer,
+        payload: &OAuthTokenRequest,
+        account: &ServiceAccount,
+    ) -> Result<Response, ApiError> {
+        let scopes = requested_scopes(&account.scopes, payload.scope.as_deref())?;
+
+        let access_token = issuer.issue_for_service(account, &scopes)?;
+
+        Ok((
+            StatusCode::OK,
+            Json(OAuthTokenResponse::new(
+                access_token,
+                Some(scopes.join(" ")),
+            )),
+        )
+            .into_response())
+    }
+
+    /// Public clients only send their client id, confidential ones authenticate
+    /// like service accounts. PKCE is checked for both.
+    async fn authorization_code(
+        &self,
+        issuer: &AccessTokenIssuer,
+        basic: BasicCredentials,
+        payload: &OAuthTokenRequest,
+    ) -> Result<Response, ApiError> {
+        let (client_id, client_secret) = match (basic.0, &payload.client_id, &payload.client_secret)
+        {
+            (Some(_), _, Some(_)) => {
+                return Err(
+                    OAuthError::InvalidRequest("multiple client authentication methods").into(),
+                );
+            }
+            (Some((client_id, client_secret)), body_id, None)
+                if body_id.as_ref().is_none_or(|body_id| *body_id == client_id) =>
+            {
+                (client_id, Some(client_secret))
+            }
+            (None, Some(client_id), client_secret) => (client_id.clone(), client_secret.clone()),
+            _ => return Err(OAuthError::InvalidClient.into()),
+        };
+
+        let client = self
+            .oauth
+            .authenticate_client(&client_id, client_secret.as_deref())
+            .await?
+            .ok_or(OAuthError::InvalidClient)?;
+
+        let (Some(code), Some(code_verifier)) = (&payload.code, &payload.code_verifier) else {
+            return Err(OAuthError::InvalidRequest("code and code_verifier are required").into());
+        };
+
+        let grant = self
+            .oauth
+            .redeem_authorization_code(code)
+            .await?
+            .ok_or(OAuthError::InvalidGrant)?;
+
+        let redirect_uri_matches = match &payload.redirect_uri {
+            Some(redirect_uri) => *redirect_uri == grant.redirect_uri,
+            None => !grant.redirect_uri_sent,
+        };
+
+        if grant.client_id != client.client_id
+            || !redirect_uri_matches
+            || !verify_pkce(code_verifier, &grant.code_challenge)
+        {
+            return Err(OAuthError::InvalidGrant.into());
+        }
+
+        // The user may have logged out between approving and the exchange.
+        let Some(session) = self
+            .session_store
+            .get_session(grant.user_id, grant.session_id)
+            .await?
+        else {
+            return Err(OAuthError::InvalidGrant.into());
+        };
+
+        // Revoking the grant's tokens must not log the user out of the browser session.
+        let family = self
+            .session_store
+            .create_session(
+                grant.user_id,
+                SessionMeta {
+                    client_id: Some(client.client_id.clone()),
+                    ..SessionMeta::token_family(&session)
+                },
+            )
+            .await?;
+
+        let access_token = issuer.issue_for_client(
+            grant.user_id,
+            family.session.id,
+            &client.client_id,
+            &grant.scopes,
+        )?;
+
+        let id_token = if grant.scopes.iter().any(|scope| scope == OPENID_SCOPE) {
+            let user = self
+                .users
+                .get_user(grant.user_id)
+                .await?
+                .ok_or(OAuthError::InvalidGrant)?;
+
+            Some(issuer.issue_id_token(
+                &user,
+                &client.client_id,
+                session.created_at.timestamp(),
+                grant.nonce,
+                &grant.scopes,
+            )?)
+        } else {
+            None
+        };
+
+        Ok((
+            StatusCode::OK,
+            Json(OAuthTokenResponse {
+                id_token,
+                ..OAuthTokenResponse::new(access_token, Some(grant.scopes.join(" ")))
+            }),
+        )
+            .into_response())
+    }
+
+    /// Tells the token kinds apart by shape: API keys carry their marker and JWTs
+    /// have three segments. Session and refresh tokens look alike, so the hint
+    /// only decides which of the two is looked up first.
+    async fn resolve(
+        &self,
+        token: &str,
+        hint: Option<&str>,
+    ) -> Result<Option<PresentedToken>, ApiError> {
+        if is_api_key(token) {
+            let api_key = self.api_keys.resolve_api_key(token).await?;
+            return Ok(api_key.map(PresentedToken::ApiKey));
+        }
+
+        if token.split('.').count() == 3 {
+            return self.resolve_access_token(token).await;
+        }
+
+        if hint == Some(REFRESH_TOKEN_TYPE) {
+            if let Some(session) = self.session_store.refresh_token_session(token).await? {
+                return Ok(Some(PresentedToken::Refresh(session)));
+            }
+
+            let session = self.session_store.find_session(token).await?;
+            return Ok(session.map(PresentedToken::Session));
+        }
+
+        // Looking a session up on a resource server's behalf must not keep it alive.
+        if let Some(session) = self.session_store.find_session(token).await? {
+            return Ok(Some(PresentedToken::Session(session)));
+        }
+
+        let session = self.session_store.refresh_token_session(token).await?;
+        Ok(session.map(PresentedToken::Refresh))
+    }
+
+    /// Tokens never end the session they were issued from, only their own family.
+    /// Tokens issued straight on a session before families existed simply run out.
+    async fn revoke_token_family(&self, session: &Session) -> Result<(), ApiError> {
+        if session.parent_id.is_some() {
+            self.session_store
+                .remove_user_session(session.user_id, session.id)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// User access tokens die with their session, even before they expire.
+    async fn resolve_access_token(&self, token: &str) -> Result<Option<PresentedToken>, ApiError> {
+        let Some(claims) = self
+            .access_tokens
+            .as_deref()
+            .and_then(|issuer| issuer.verify(token).ok())
+        else {
+            return Ok(None);
+        };
+
+        if let Some(session_id) = claims.sid {
+            let Ok(user_id) = claims.sub.parse::<Uuid>() else {
+                return Ok(None);
+            };
+
+            if self
+                .session_store
+                .get_session(user_id, session_id)
+                .await?
+                .is_none()
+            {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(PresentedToken::Access(claims)))
+    }
+}
+
+/// Basic credentials take precedence, RFC 6749 forbids using both methods at once.
+fn client_credentials(
+    basic: BasicCredentials,
+    client_id: &Option<String>,
+    client_secret: &Option<String>,
+) -> Result<(String, Option<String>), ApiError> {
+    match (basic.0, client_id, client_secret) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            Err(OAuthError::InvalidRequest("multiple client authentication methods").into())
+        }
+        (Some((client_id, client_secret)), None, None) => Ok((client_id, Some(client_secret))),
+        (None, Some(client_id), client_secret) => Ok((client_id.clone(), client_secret.clone())),
+        _ => Err(OAuthError::InvalidClient.into()),
+    }
+}
+
+/// Applications may only look at the access tokens issued to them.
+fn visible_to(client: &TokenClient, token: &PresentedToken) -> bool {
+    match (client, token) {
+        (TokenClient::Service, _) => true,
+        (TokenClient::App(app), PresentedToken::Access(claims)) => {
+            claims.client_id.as_deref() == Some(app.client_id.as_str())
+        }
+        (TokenClient::App(_), _) => false,
+    }
+}
+
+/// Tokens issued to an application may only be revoked by that application,
+/// which in turn may not revoke anyone else's tokens.
+fn revocable_by(client: Option<&TokenClient>, token: &PresentedToken) -> bool {
+    let issued_to = match token {
+        PresentedToken::Access(claims) if claims.sid.is_some() => claims.client_id.as_deref(),
+        PresentedToken::Session(session) | PresentedToken::Refresh(session) => {
+            session.client_id.as_deref()
+        }
+        PresentedToken::Access(_) | PresentedToken::ApiKey(_) => None,
+    };
+
+    match client {
+        Some(TokenClient::App(app)) => issued_to == Some(app.client_id.as_str()),
+        _ => issued_to.is_none(),
+    }
+}
+
+fn introspection(token: PresentedToken) -> IntrospectionResponse {
+    match token {
+        PresentedToken::Access(claims) => IntrospectionResponse {
+            active: true,
+            token_type: Some(ACCESS_TOKEN_TYPE),
+            sub: Some(claims.sub),
+            scope: claims.scope,
+            client_id: claims.client_id,
+            sid: claims.sid,
+            iat: Some(claims.iat),
+            exp: Some(claims.exp),
+        },
+        PresentedToken::Session(session) => session_introspection(SESSION_TOKEN_TYPE, session),
+        PresentedToken::Refresh(session) => session_introspection(REFRESH_TOKEN_TYPE, session),
+        PresentedToken::ApiKey(api_key) => IntrospectionResponse {
+            active: true,
+            token_type: Some(API_KEY_TYPE),
+            sub: Some(api_key.user_id.to_string()),
+            scope: Some(api_key.scopes.join(" ")).filter(|scope| !scope.is_empty()),
+            client_id: None,
+            sid: None,
+            iat: Some(api_key.created_at.timestamp()),
+            exp: api_key.expires_at.map(|expires_at| expires_at.timestamp()),
+        },
+    }
+}
+
+fn session_introspection(token_type: &'static str, session: Session) -> IntrospectionResponse {
+    IntrospectionResponse {
+        active: true,
+        token_type: Some(token_type),
+        sub: Some(session.user_id.to_string()),
+        scope: None,
+        client_id: None,
+        sid: Some(session.id),
+        iat: Some(session.created_at.timestamp()),
+        exp: Some(session.expires_at.timestamp()),
+    }
+}
+
+fn bearer_error(status: StatusCode, error: &'static str) -> Response {
+    (
+        status,
+        [(WWW_AUTHENTICATE, format!(r#"Bearer error="{error}""#))],
+    )
+        .into_response()
+}
+
+/// Narrows the granted scopes to the requested ones, all of which must be granted.
+pub fn requested_scopes(
+    granted: &[String],
+    requested: Option<&str>,
+) -> Result<Vec<String>, OAuthError> {
+    let Some(requested) = requested else {
+        return Ok(granted.to_vec());
+    };
+
+    requested
+        .split_whitespace()
+        .map(|scope| {
+            granted
+                .iter()
+                .find(|granted| *granted == scope)
+                .cloned()
+                .ok_or(OAuthError::InvalidScope)
+        })
+        .collect()
+}
+
+#[async_trait]
+impl ITokensDelivery for TokensDelivery {
+    /// An empty key set when access tokens are disabled, so verifiers fail closed.
+    async fn jwks(&self) -> Result<Response, ApiError> {
+        let jwks = self
+            .access_tokens
+            .as_ref()
+            .map(|issuer| issuer.jwks())
+            .unwrap_or_default();
+
+        Ok((StatusCode::OK, Json::<Jwks>(jwks)).into_response())
+    }
+
+    async fn refresh(
+        &self,
+        Json(payload): Json<RefreshTokenRequest>,
+    ) -> Result<Response, ApiError> {
+        let issuer = self.issuer()?;
+
+        let Some(rotated) = self
+            .session_store
+            .rotate_refresh_token(&payload.refresh_token)
+            .await?
+        else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        let access_token = issuer.issue(rotated.session.user_id, rotated.session.id)?;
+
+        Ok((
+            StatusCode::OK,
+            Json(AccessTokenResponse::new(access_token, rotated.token)),
+        )
+            .into_response())
+    }
+
+    async fn token(
+        &self,
+        basic: BasicCredentials,
+        Form(payload): Form<OAuthTokenRequest>,
+    ) -> Result<Response, ApiError> {
+        if ![CLIENT_CREDENTIALS_GRANT, AUTHORIZATION_CODE_GRANT]
+            .contains(&payload.grant_type.as_str())
+        {
+            return Err(OAuthError::UnsupportedGrantType.into());
+        }
+
+        let issuer = self.issuer()?;
+
+        if payload.grant_type == AUTHORIZATION_CODE_GRANT {
+            return self.authorization_code(issuer, basic, &payload).await;
+        }
+
+        let account = self
+            .authenticate_client(basic, &payload.client_id, &payload.client_secret)
+            .await?;
+
+        Self::client_credentials(issuer, &payload, &account)
+    }
+
+    /// Only registered clients may introspect, unknown and dead tokens are reported as inactive,
+    /// and so are tokens an application is not allowed to see.
+    async fn introspect(
+        &self,
+        basic: BasicCredentials,
+        Form(payload): Form<OAuthTokenActionRequest>,
+    ) -> Result<Response, ApiError> {
+        let client = self
+            .authenticate_token_client(basic, &payload.client_id, &payload.client_secret)
+            .await?;
+
+        let response = self
+            .resolve(&payload.token, payload.token_type_hint.as_deref())
+            .await?
+            .filter(|token| visible_to(&client, token))
+            .map(introspection)
+            .unwrap_or_default();
+
+        Ok((StatusCode::OK, Json(response)).into_response())
+    }
+
+    /// Holding a first-party token is enough to revoke it, the way logging out works.
+    /// Tokens issued to an application are only revoked at the request of that application,
+    /// which public clients prove with their client id alone. Credentials that are presented
+    /// must be valid. Unknown tokens are answered with 200 as RFC 7009 requires.
+    async fn revoke(
+        &self,
+        basic: BasicCredentials,
+        Form(payload): Form<OAuthTokenActionRequest>,
+    ) -> Result<Response, ApiError> {
+        let client = if basic.0.is_some() || payload.client_id.is_some() {
+            Some(
+                self.authenticate_token_client(basic, &payload.client_id, &payload.client_secret)
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let token = self
+            .resolve(&payload.token, payload.token_type_hint.as_deref())
+            .await?;
+
+        if token
+            .as_ref()
+            .is_some_and(|token| !revocable_by(client.as_ref(), token))
+        {
+            return Err(OAuthError::UnauthorizedClient.into());
+        }
+
+        match token {
+            None => {}
+            Some(PresentedToken::Session(_)) => {
+                self.session_store.remove_session(&payload.token).await?
+            }
+            // Revoking either token of a family ends the whole family.
+            Some(PresentedToken::Refresh(session)) => self.revoke_token_family(&session).await?,
+            Some(PresentedToken::Access(claims)) => {
+                let (Some(session_id), Ok(user_id)) = (claims.sid, claims.sub.parse::<Uuid>())
+                else {
+                    // Service tokens are stateless and simply run out.
+                    return Err(OAuthError::UnsupportedTokenType.into());
+                };
+
+                if let Some(session) = self.session_store.get_session(user_id, session_id).await? {
+                    self.revoke_token_family(&session).await?;
+                }
+            }
+            Some(PresentedToken::ApiKey(api_key)) => {
+                self.api_keys
+                    .delete_api_key(api_key.user_id, api_key.id)
+                    .await?;
+            }
+        }
+
+        Ok((StatusCode::OK,).into_response())
+    }
+
+    async fn openid_configuration(&self) -> Result<Response, ApiError> {
+        let issuer = self.issuer()?;
+        let metadata = ProviderMetadata::new(issuer.issuer(), issuer.id_token_algorithm());
+
+        Ok((StatusCode::OK, Json(metadata)).into_response())
+    }
+
+    /// Takes access tokens issued to applications with the `openid` scope,
+    /// failures are reported as RFC 6750 bearer token errors.
+    async fn userinfo(&self, bearer: BearerToken) -> Result<Response, ApiError> {
+        let claims = match bearer.0 {
+            Some(token) if token.split('.').count() == 3 => {
+                match self.resolve_access_token(&token).await? {
+                    Some(PresentedToken::Access(claims)) if claims.sid.is_some() => Some(claims),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        let Some(claims) = claims else {
+            return Ok(bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"));
+        };
+
+        let scopes: Vec<String> = claims
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+
+        if !scopes.iter().any(|scope| scope == OPENID_SCOPE) {
+            return Ok(bearer_error(StatusCode::FORBIDDEN, "insufficient_scope"));
+        }
+
+        let user_id = claims.sub.parse::<Uuid>().map_err(DBError::from)?;
+        let Some(user) = self.users.get_user(user_id).await? else {
+            return Ok(bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"));
+        };
+
+        Ok((StatusCode::OK, Json(UserInfo::new(&user, &scopes))).into_response())
+    }
+
+    async fn calling_service(&self, caller: CallingService) -> Result<Response, ApiError> {
+        let Some(service) = caller.0 else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        Ok((
+            StatusCode::OK,
+            Json::<ServiceIdentityResponse>(service.into()),
+        )
+            .into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SessionConfig;
+    use crate::delivery_http::oauth_delivery::MockIOAuthRepo;
+    use crate::delivery_http::users_delivery::{MockIApiKeysRepo, MockIUsersRepo};
+    use crate::model::{AuthMethod, IssuedSession, SessionKind};
+    use crate::repo::memory_sessions::MemorySessionsRepo;
+    use crate::tokens::TokenHasher;
+    use chrono::Utc;
+    use ed25519_dalek::SigningKey;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+
+    fn account() -> ServiceAccount {
+        ServiceAccount {
+            id: Uuid::new_v4(),
+            client_id: "svc_test".to_string(),
+            name: "test".to_string(),
+            scopes: vec!["users:read".to_string(), "keys:resolve".to_string()],
+        }
+    }
+
+    fn app(client_id: &str) -> OAuthClient {
+        OAuthClient {
+            id: Uuid::new_v4(),
+            client_id: client_id.to_string(),
+            name: "test".to_string(),
+            redirect_uris: vec![],
+            scopes: vec![],
+            confidential: false,
+        }
+    }
+
+    fn delivery(sessions: Arc<MemorySessionsRepo>, oauth: MockIOAuthRepo) -> TokensDelivery {
+        let pem = SigningKey::from_bytes(&[1; 32])
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap();
+        let issuer = AccessTokenIssuer::from_pem(&pem, None, None, "auth".to_string(), 60).unwrap();
+
+        TokensDelivery::new(
+            Arc::new(MockIUsersRepo::new()),
+            sessions,
+            Arc::new(MockIApiKeysRepo::new()),
+            Arc::new(MockIServiceAccountsRepo::new()),
+            Arc::new(oauth),
+            Some(Arc::new(issuer)),
+        )
+    }
+
+    fn sessions() -> Arc<MemorySessionsRepo> {
+        Arc::new(MemorySessionsRepo::new(
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+        ))
+    }
+
+    async fn login(sessions: &MemorySessionsRepo) -> IssuedSession {
+        let meta = SessionMeta {
+            ip: None,
+            user_agent: None,
+            auth_method: AuthMethod::Password,
+            kind: SessionKind::Transient,
+            parent_id: None,
+            client_id: None,
+        };
+
+        sessions.create_session(Uuid::new_v4(), meta).await.unwrap()
+    }
+
+    fn revocation(token: &str) -> Form<OAuthTokenActionRequest> {
+        Form(OAuthTokenActionRequest {
+            token: token.to_string(),
+            token_type_hint: None,
+            client_id: None,
+            client_secret: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_revoking_tokens_keeps_the_cookie_session() {
+        let sessions = sessions();
+        let delivery = delivery(sessions.clone(), MockIOAuthRepo::new());
+        let cookie = login(&sessions).await;
+        let user_id = cookie.session.user_id;
+
+        for revoke_access_token in [true, false] {
+            let family = sessions
+                .create_session(user_id, SessionMeta::token_family(&cookie.session))
+                .await
+                .unwrap();
+            let access_token = delivery
+                .issuer()
+                .unwrap()
+                .issue(user_id, family.session.id)
+                .unwrap()
+                .token;
+            let refresh_token = sessions.create_refresh_token(&family.token).await.unwrap();
+
+            let token = if revoke_access_token {
+                &access_token
+            } else {
+                &refresh_token
+            };
+            let response = delivery
+                .revoke(BasicCredentials(None), revocation(token))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(
+                delivery
+                    .resolve(&access_token, None)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(
+                sessions
+                    .refresh_token_session(&refresh_token)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(
+                sessions
+                    .find_session(&cookie.token)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+
+        // Tokens issued on the session itself cannot end it either.
+        let access_token = delivery
+            .issuer()
+            .unwrap()
+            .issue(user_id, cookie.session.id)
+            .unwrap()
+            .token;
+        delivery
+            .revoke(BasicCredentials(None), revocation(&access_token))
+            .await
+            .unwrap();
+        assert!(
+            sessions
+                .find_session(&cookie.token)
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_the_issuing_app_revokes_its_tokens() {
+        let sessions = sessions();
+        let mut oauth = MockIOAuthRepo::new();
+        oauth
+            .expect_authenticate_client()
+            .returning(|client_id, _| Ok(Some(app(client_id))));
+        let delivery = delivery(sessions.clone(), oauth);
+        let cookie = login(&sessions).await;
+        let user_id = cookie.session.user_id;
+
+        let meta = SessionMeta {
+            client_id: Some("app_test".to_string()),
+            ..SessionMeta::token_family(&cookie.session)
+        };
+        let family = sessions.create_session(user_id, meta).await.unwrap();
+        let access_token = delivery
+            .issuer()
+            .unwrap()
+            .issue_for_client(user_id, family.session.id, "app_test", &[])
+            .unwrap()
+            .token;
+        let revoke = |client_id: Option<&str>| {
+            delivery.revoke(
+                BasicCredentials(None),
+                Form(OAuthTokenActionRequest {
+                    client_id: client_id.map(str::to_string),
+                    ..revocation(&access_token).0
+                }),
+            )
+        };
+
+        for client_id in [None, Some("app_other")] {
+            assert!(matches!(
+                revoke(client_id).await,
+                Err(ApiError::OAuthError(OAuthError::UnauthorizedClient))
+            ));
+        }
+        assert!(
+            delivery
+                .resolve(&access_token, None)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        assert_eq!(
+            revoke(Some("app_test")).await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert!(
+            delivery
+                .resolve(&access_token, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            sessions
+                .find_session(&cookie.token)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        // Nor may an application revoke first-party tokens.
+        assert!(matches!(
+            delivery
+                .revoke(
+                    BasicCredentials(None),
+                    Form(OAuthTokenActionRequest {
+                        client_id: Some("app_test".to_string()),
+                        ..revocation(&cookie.token).0
+                    }),
+                )
+                .await,
+            Err(ApiError::OAuthError(OAuthError::UnauthorizedClient))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_token_families_die_with_their_session() {
+        let sessions = sessions();
+        let delivery = delivery(sessions.clone(), MockIOAuthRepo::new());
+        let cookie = login(&sessions).await;
+        let user_id = cookie.session.user_id;
+
+        let family = sessions
+            .create_session(user_id, SessionMeta::token_family(&cookie.session))
+            .await
+            .unwrap();
+        let access_token = delivery
+            .issuer()
+            .unwrap()
+            .issue(user_id, family.session.id)
+            .unwrap()
+            .token;
+        let refresh_token = sessions.create_refresh_token(&family.token).await.unwrap();
+
+        sessions.remove_session(&cookie.token).await.unwrap();
+
+        assert!(
+            delivery
+                .resolve(&access_token, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            sessions
+                .rotate_refresh_token(&refresh_token)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_introspection_of_api_key() {
+        let now = Utc::now();
+        let api_key = ApiKey {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            name: "ci".to_string(),
+            prefix: "ak_0123abcd".to_string(),
+            scopes: vec![],
+            expires_at: None,
+            last_used_at: None,
+            created_at: now,
+        };
+        let user_id = api_key.user_id.to_string();
+
+        let response = introspection(PresentedToken::ApiKey(api_key));
+
+        assert!(response.active);
+        assert_eq!(response.sub, Some(user_id));
+        assert_eq!(response.scope, None);
+        assert_eq!(response.exp, None);
+        assert_eq!(response.iat, Some(now.timestamp()));
+    }
+
+    #[test]
+    fn test_apps_only_see_their_own_tokens() {
+        let app = TokenClient::App(OAuthClient {
+            id: Uuid::new_v4(),
+            client_id: "app_test".to_string(),
+            name: "test".to_string(),
+            redirect_uris: vec![],
+            scopes: vec![],
+            confidential: true,
+        });
+        let access = |client_id: &str| {
+            PresentedToken::Access(AccessClaims {
+                iss: "auth".to_string(),
+                sub: Uuid::new_v4().to_string(),
+                sid: Some(Uuid::new_v4()),
+                client_id: Some(client_id.to_string()),
+                scope: None,
+                iat: 0,
+                exp: 0,
+            })
+        };
+
+        assert!(visible_to(&app, &access("app_test")));
+        assert!(!visible_to(&app, &access("app_other")));
+        assert!(visible_to(&TokenClient::Service, &access("app_other")));
+    }
+
+    #[test]
+    fn test_requested_scopes() {
+        let account = account();
+
+        assert_eq!(
+            requested_scopes(&account.scopes, None).unwrap(),
+            account.scopes
+        );
+        assert_eq!(
+            requested_scopes(&account.scopes, Some("keys:resolve")).unwrap(),
+            vec!["keys:resolve".to_string()]
+        );
+        assert!(matches!(
+            requested_scopes(&account.scopes, Some("users:read users:write")),
+            Err(OAuthError::InvalidScope)
+        ));
+    }
+}
@@ -1,25 +1,38 @@
 use crate::app::IUsersDelivery;
+use crate::delivery_http::bearer::BearerToken;
+use crate::delivery_http::client_info::ClientInfo;
 use crate::delivery_http::dto::{
-    LoginRequest, RegisterRequest, UpdateUserRequest, UserNotFoundResponse, UserResponse,
+    AccessTokenResponse, ApiKeyResponse, ChangeEmailRequest, ChangePasswordRequest,
+    CreateApiKeyRequest, CreatedApiKeyResponse, LoginRequest, LoginResponse, RegisterRequest,
+    SessionResponse, UpdateUserRequest, UserNotFoundResponse, UserResponse,
 };
+use crate::delivery_http::session_cookie::{SESSION_COOKIE, SessionCookieCodec};
 use crate::errors::ApiError::UseCaseError;
-use crate::errors::{ApiError, DBError, UsecaseError};
-use crate::model::User;
+use crate::errors::{ApiError, DBError, TokenError, UsecaseError};
+use crate::external_idp::ExternalIdentity;
+use crate::jwt::AccessTokenIssuer;
+use crate::model::{
+    ApiKey, AuthMethod, IssuedApiKey, IssuedSession, NewApiKey, RotatedRefreshToken, Session,
+    SessionKind, SessionMeta, User,
+};
+use crate::tokens::is_api_key;
 use async_trait::async_trait;
 use axum::Json;
 use axum::extract::Path;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 
-use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
 
-use crate::delivery_grpc::users_delivery::IUserIDGetter;
-use crate::errors::DBError::FailedToParseUUID;
+use crate::delivery_grpc::users_delivery::{IApiKeyResolver, IUserIDGetter};
 use std::sync::Arc;
-use time::Duration;
 use uuid::Uuid;
 use validator::Validate;
 
+/// API keys need this scope to read the profile of their owner.
+pub const PROFILE_READ_SCOPE: &str = "profile:read";
+
+#[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait IUsersRepo: Send + Sync {
     async fn update_user(&self, user: User) -> Result<Option<User>, DBError>;
@@ -31,12 +44,116 @@ pub trait IUsersRepo: Send + Sync {
 pub trait IUsersCreatorUsecase: Send + Sync {
     async fn create_user(&self, user_payload: RegisterRequest) -> Result<User, UsecaseError>;
     async fn login(&self, login_payload: LoginRequest) -> Result<User, UsecaseError>;
+    /// Just-in-time sign-up of a user coming from an external identity provider,
+    /// linked to their account at `provider`.
+    /// Fails with [`DBError::UserAlreadyExists`] when the email is taken.
+    async fn create_external_user(
+        &self,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<User, UsecaseError>;
+    /// Fails with [`UsecaseError::InvalidCreds`] unless `current_password` is right.
+    async fn change_password(
+        &self,
+        user: &User,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<(), UsecaseError>;
+    /// Mails a confirmation link to `new_email`, the address only changes once it is followed.
+    async fn request_email_change(
+        &self,
+        user: &User,
+        current_password: &str,
+        new_email: String,
+    ) -> Result<(), UsecaseError>;
+    /// Sets a new password for the user, as long as they still have `email`,
+    /// the address the reset link went to. Returns `false` when nothing changed.
+    async fn reset_password(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        password: &str,
+    ) -> Result<bool, UsecaseError>;
+    /// Replaces the password with one nobody knows, so only a reset link sent to
+    /// `email` lets the user sign in with a password again.
+    async fn lock_password(&self, user_id: Uuid, email: &str) -> Result<bool, UsecaseError>;
+    /// Whether the email verification policy lets the user sign in.
+    fn can_sign_in(&self, user: &User) -> bool;
+    /// Whether the email verification policy lets the user issue credentials
+    /// such as API keys.
+    fn has_full_access(&self, user: &User) -> bool;
 }
 
 #[async_trait]
 pub trait ISessionStore: Send + Sync {
-    async fn create_session(&self, user_id: Uuid) -> Result<Uuid, DBError>;
-    async fn remove_session(&self, session_id: Uuid) -> Result<(), DBError>;
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        meta: SessionMeta,
+    ) -> Result<IssuedSession, DBError>;
+    async fn remove_session(&self, token: &str) -> Result<(), DBError>;
+    /// The live session behind a token without sliding its expiry, for looking at
+    /// a session on behalf of someone other than its holder.
+    async fn find_session(&self, token: &str) -> Result<Option<Session>, DBError>;
+    /// The live session with this id, without sliding its expiry.
+    async fn get_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, DBError>;
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DBError>;
+    async fn remove_user_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool, DBError>;
+    async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<usize, DBError>;
+    /// Revokes every session of the user except `keep`.
+    async fn revoke_other_sessions(&self, user_id: Uuid, keep: Uuid) -> Result<usize, DBError>;
+    /// Moves the session to a fresh token and id keeping its data, the old token stops working.
+    /// Meant for privilege changes such as a password change.
+    async fn rotate_session(&self, token: &str) -> Result<Option<IssuedSession>, DBError>;
+    /// Starts the refresh token chain of a live session.
+    async fn create_refresh_token(&self, session_token: &str) -> Result<String, DBError>;
+    /// Redeems a single-use refresh token for the next one in its chain.
+    /// Presenting a redeemed token again revokes the whole session and yields
+    /// [`DBError::RefreshTokenReused`]; a chain whose session is gone yields `None`.
+    async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<Option<RotatedRefreshToken>, DBError>;
+    /// The live session an unused refresh token belongs to, without redeeming it.
+    async fn refresh_token_session(&self, refresh_token: &str) -> Result<Option<Session>, DBError>;
+}
+
+#[async_trait]
+pub trait IApiKeysRepo: IApiKeyResolver {
+    async fn create_api_key(
+        &self,
+        user_id: Uuid,
+        new_key: NewApiKey,
+    ) -> Result<IssuedApiKey, DBError>;
+    async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DBError>;
+    async fn delete_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<bool, DBError>;
+    async fn revoke_api_keys(&self, user_id: Uuid) -> Result<usize, DBError>;
+}
+
+#[cfg(test)]
+mockall::mock! {
+    pub IApiKeysRepo {}
+
+    #[async_trait]
+    impl IApiKeyResolver for IApiKeysRepo {
+        async fn resolve_api_key(&self, key: &str) -> Result<Option<ApiKey>, DBError>;
+    }
+
+    #[async_trait]
+    impl IApiKeysRepo for IApiKeysRepo {
+        async fn create_api_key(
+            &self,
+            user_id: Uuid,
+            new_key: NewApiKey,
+        ) -> Result<IssuedApiKey, DBError>;
+        async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DBError>;
+        async fn delete_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<bool, DBError>;
+        async fn revoke_api_keys(&self, user_id: Uuid) -> Result<usize, DBError>;
+    }
 }
 
 pub struct UsersDelivery {
@@ -44,6 +161,9 @@ pub struct UsersDelivery {
     usecase: Arc<dyn IUsersCreatorUsecase>,
     session_store: Arc<dyn ISessionStore>,
     user_id_getter: Arc<dyn IUserIDGetter>,
+    api_keys: Arc<dyn IApiKeysRepo>,
+    cookies: SessionCookieCodec,
+    access_tokens: Option<Arc<AccessTokenIssuer>>,
 }
 
 impl UsersDelivery {
@@ -52,12 +172,18 @@ impl UsersDelivery {
         usecase: Arc<dyn IUsersCreatorUsecase>,
         session_store: Arc<dyn ISessionStore>,
         user_id_getter: Arc<dyn IUserIDGetter>,
+        api_keys: Arc<dyn IApiKeysRepo>,
+        cookies: SessionCookieCodec,
+        access_tokens: Option<Arc<AccessTokenIssuer>>,
     ) -> Self {
         UsersDelivery {
             repo,
             usecase,
             session_store,
             user_id_getter,
+            api_keys,
+            cookies,
+            access_tokens,
         }
     }
 
@@ -73,14 +199,109 @@ impl UsersDelivery {
         }
     }
 
-    fn create_auth_cookie(session_id: Uuid) -> Cookie<'static> {
-        Cookie::build(("session_id", session_id.to_string()))
-            .path("/")
-            .http_only(true)
-            .secure(true)
-            .same_site(SameSite::None)
-            .max_age(Duration::days(1))
-            .build()
+    /// The bearer token wins over the cookie when both are sent.
+    /// Forged or tampered cookies yield no token at all.
+    fn presented_token(&self, jar: &CookieJar, bearer: &BearerToken) -> Option<String> {
+        bearer.0.clone().or_else(|| {
+            jar.get(SESSION_COOKIE)
+                .and_then(|cookie| self.cookies.open(cookie))
+        })
+    }
+
+    fn session_meta(client: ClientInfo, auth_method: AuthMethod, kind: SessionKind) -> SessionMeta {
+        SessionMeta {
+            ip: client.ip,
+            user_agent: client.user_agent,
+            auth_method,
+            kind,
+            parent_id: None,
+            client_id: None,
+        }
+    }
+
+    /// Access and refresh tokens get a token family of their own next to `issued`,
+    /// so revoking them leaves the session they were issued from alone.
+    async fn issue_access_token(
+        &self,
+        issuer: &AccessTokenIssuer,
+        issued: &IssuedSession,
+    ) -> Result<AccessTokenResponse, ApiError> {
+        let family = self
+            .session_store
+            .create_session(
+                issued.session.user_id,
+                SessionMeta::token_family(&issued.session),
+            )
+            .await?;
+
+        let access_token = issuer.issue(family.session.user_id, family.session.id)?;
+        let refresh_token = self
+            .session_store
+            .create_refresh_token(&family.token)
+            .await?;
+
+        Ok(AccessTokenResponse::new(access_token, refresh_token))
+    }
+
+    /// Drops the session the client came with so a planted session id
+    /// never survives authentication.
+    async fn revoke_presented_session(
+        &self,
+        jar: &CookieJar,
+        bearer: &BearerToken,
+    ) -> Result<(), ApiError> {
+        let Some(token) = self.presented_token(jar, bearer) else {
+            return Ok(());
+        };
+
+        match self.session_store.remove_session(&token).await {
+            Ok(()) | Err(DBError::SessionNotFound) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Resolves the presented token into the live session it points to, if any.
+    /// API keys are not sessions and never authenticate here.
+    async fn authenticate(
+        &self,
+        jar: &CookieJar,
+        bearer: &BearerToken,
+    ) -> Result<Option<Session>, ApiError> {
+        let Some(token) = self.presented_token(jar, bearer) else {
+            return Ok(None);
+        };
+
+        if is_api_key(&token) {
+            return Ok(None);
+        }
+
+        Ok(self.user_id_getter.get_user(&token).await?)
+    }
+
+    /// Resolves either a session or an API key into the user acting on its behalf.
+    /// Only for endpoints that do not manage credentials, API keys need `scope` granted.
+    async fn authenticate_user(
+        &self,
+        jar: &CookieJar,
+        bearer: &BearerToken,
+        scope: &'static str,
+    ) -> Result<Option<Uuid>, ApiError> {
+        if let BearerToken(Some(token)) = bearer
+            && is_api_key(token)
+        {
+            let Some(api_key) = self.api_keys.resolve_api_key(token).await? else {
+                return Ok(None);
+            };
+
+            if !api_key.scopes.iter().any(|granted| granted == scope) {
+                return Err(TokenError::InsufficientScope(scope).into());
+            }
+
+            return Ok(Some(api_key.user_id));
+        }
+
+        let session = self.authenticate(jar, bearer).await?;
+        Ok(session.map(|session| session.user_id))
     }
 }
 
@@ -89,6 +310,8 @@ impl IUsersDelivery for UsersDelivery {
     async fn create_user(
         &self,
         jar: CookieJar,
+        bearer: BearerToken,
+        client: ClientInfo,
         Json(payload): Json<RegisterRequest>,
     ) -> Result<Response, ApiError> {
         if let Err(e) = payload.validate() {
@@ -101,9 +324,22 @@ impl IUsersDelivery for UsersDelivery {
             .await
             .map_err(UseCaseError)?;
 
-        let session_id = self.session_store.create_session(user.id).await?;
+        // The account waits for its email to be verified before anyone signs in to it.
+        if !self.usecase.can_sign_in(&user) {
+            return Ok((StatusCode::CREATED, Json::<UserResponse>(user.into())).into_response());
+        }
+
+        self.revoke_presented_session(&jar, &bearer).await?;
 
-        let cookie = Self::create_auth_cookie(session_id);
+        let issued = self
+            .session_store
+            .create_session(
+                user.id,
+                Self::session_meta(client, AuthMethod::Registration, SessionKind::Transient),
+            )
+            .await?;
+
+        let cookie = self.cookies.session_cookie(&issued);
 
         Ok((
             StatusCode::CREATED,
@@ -133,6 +369,7 @@ impl IUsersDelivery for UsersDelivery {
     async fn delete_user(&self, Path(payload): Path<Uuid>) -> Result<Response, ApiError> {
         let is_deleted = self.repo.delete_user(payload).await?;
         if is_deleted {
+            self.session_store.revoke_all_sessions(payload).await?;
             Ok(StatusCode::NO_CONTENT.into_response())
         } else {
             Ok((
@@ -146,49 +383,609 @@ impl IUsersDelivery for UsersDelivery {
     async fn login(
         &self,
         jar: CookieJar,
+        bearer: BearerToken,
+        client: ClientInfo,
         Json(payload): Json<LoginRequest>,
     ) -> Result<Response, ApiError> {
+        let kind = if payload.remember_me {
+            SessionKind::Persistent
+        } else {
+            SessionKind::Transient
+        };
+
+        // Checked before the credentials so a misconfigured client learns nothing about them.
+        let access_tokens = match (payload.issue_access_token, &self.access_tokens) {
+            (false, _) => None,
+            (true, Some(issuer)) => Some(issuer),
+            (true, None) => return Err(TokenError::AccessTokensDisabled.into()),
+        };
+        let cookieless = payload.cookieless;
+
         let user = self.usecase.login(payload).await?;
 
-        let session_id = self.session_store.create_session(user.id).await?;
+        self.revoke_presented_session(&jar, &bearer).await?;
+
+        let issued = self
+            .session_store
+            .create_session(
+                user.id,
+                Self::session_meta(client, AuthMethod::Password, kind),
+            )
+            .await?;
 
-        let cookie = Self::create_auth_cookie(session_id);
+        let access_token = match access_tokens {
+            Some(issuer) => Some(self.issue_access_token(issuer, &issued).await?),
+            None => None,
+        };
+
+        let (jar, session_token) = if cookieless {
+            (jar, Some(issued.token.clone()))
+        } else {
+            (jar.add(self.cookies.session_cookie(&issued)), None)
+        };
 
         Ok((
             StatusCode::OK,
-            jar.add(cookie),
-            Json::<UserResponse>(user.into()),
+            jar,
+            Json(LoginResponse {
+                user: user.into(),
+                access_token,
+                session_token,
+            }),
         )
             .into_response())
     }
 
-    async fn logout(&self, jar: CookieJar) -> Result<Response, ApiError> {
-        if let Some(cookie) = jar.get("session_id") {
-            let session_id = cookie.value().to_string();
-
-            let session_id = Uuid::parse_str(session_id.as_str()).map_err(FailedToParseUUID)?;
-
-            self.session_store.remove_session(session_id).await?;
+    async fn logout(&self, jar: CookieJar, bearer: BearerToken) -> Result<Response, ApiError> {
+        if let Some(token) = self.presented_token(&jar, &bearer) {
+            self.session_store.remove_session(&token).await?;
         }
 
-        let removal_cookie = Cookie::build("session_id").path("/").build();
+        let removal_cookie = Cookie::build(SESSION_COOKIE).path("/").build();
         Ok((StatusCode::OK, jar.remove(removal_cookie)).into_response())
     }
 
-    async fn get_user_from_cookie(&self, jar: CookieJar) -> Result<Response, ApiError> {
-        if let Some(cookie) = jar.get("session_id") {
-            let session_id = cookie.value().to_string();
+    async fn logout_all(&self, jar: CookieJar, bearer: BearerToken) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        self.session_store
+            .revoke_all_sessions(session.user_id)
+            .await?;
 
-            let session_id = Uuid::parse_str(session_id.as_str()).map_err(FailedToParseUUID)?;
+        let removal_cookie = Cookie::build(SESSION_COOKIE).path("/").build();
+        Ok((StatusCode::OK, jar.remove(removal_cookie)).into_response())
+    }
 
-            if let Some(user_id) = self.user_id_getter.get_user(session_id).await? {
-                if let Some(user) = self.repo.get_user(user_id).await? {
-                    return Self::respond_with_user(Some(user));
-                }
-                return Self::respond_with_user(None);
+    async fn get_user_from_cookie(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError> {
+        if let Some(user_id) = self
+            .authenticate_user(&jar, &bearer, PROFILE_READ_SCOPE)
+            .await?
+        {
+            if let Some(user) = self.repo.get_user(user_id).await? {
+                return Self::respond_with_user(Some(user));
             }
+            return Self::respond_with_user(None);
         }
 
         Ok((StatusCode::UNAUTHORIZED,).into_response())
     }
+
+    async fn change_password(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Json(payload): Json<ChangePasswordRequest>,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if let Err(e) = payload.validate() {
+            return Ok(ApiError::ValidationError(e).into_response());
+        }
+
+        let access_tokens = match (payload.issue_access_token, &self.access_tokens) {
+            (false, _) => None,
+            (true, Some(issuer)) => Some(issuer),
+            (true, None) => return Err(TokenError::AccessTokensDisabled.into()),
+        };
+
+        let Some(user) = self.repo.get_user(session.user_id).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        self.usecase
+            .change_password(&user, &payload.current_password, &payload.new_password)
+            .await?;
+
+        // Like a password reset or an email change, every other session and API key ends.
+        self.session_store
+            .revoke_other_sessions(user.id, session.id)
+            .await?;
+        self.api_keys.revoke_api_keys(user.id).await?;
+
+        // Whoever got hold of the session token before the change does not keep it.
+        let rotated = match self.presented_token(&jar, &bearer) {
+            Some(token) => self.session_store.rotate_session(&token).await?,
+            None => None,
+        };
+
+        let access_token = match (access_tokens, &rotated) {
+            (Some(issuer), Some(issued)) => Some(self.issue_access_token(issuer, issued).await?),
+            _ => None,
+        };
+
+        let (jar, session_token) = match rotated {
+            Some(issued) if bearer.0.is_some() => (jar, Some(issued.token)),
+            Some(issued) => (jar.add(self.cookies.session_cookie(&issued)), None),
+            None => (jar, None),
+        };
+
+        Ok((
+            StatusCode::OK,
+            jar,
+            Json(LoginResponse {
+                user: user.into(),
+                access_token,
+                session_token,
+            }),
+        )
+            .into_response())
+    }
+
+    async fn request_email_change(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Json(payload): Json<ChangeEmailRequest>,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if let Err(e) = payload.validate() {
+            return Ok(ApiError::ValidationError(e).into_response());
+        }
+
+        let Some(user) = self.repo.get_user(session.user_id).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        self.usecase
+            .request_email_change(&user, &payload.current_password, payload.new_email)
+            .await?;
+
+        Ok(StatusCode::ACCEPTED.into_response())
+    }
+
+    async fn list_sessions(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError> {
+        let Some(current_session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        let sessions: Vec<SessionResponse> = self
+            .session_store
+            .list_sessions(current_session.user_id)
+            .await?
+            .into_iter()
+            // Token families show up as part of the session they were issued from.
+            .filter(|session| session.parent_id.is_none())
+            .map(|session| {
+                let current = session.id == current_session.id;
+                SessionResponse::new(session, current)
+            })
+            .collect();
+
+        Ok((StatusCode::OK, Json(sessions)).into_response())
+    }
+
+    async fn delete_session(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Path(session_id): Path<Uuid>,
+    ) -> Result<Response, ApiError> {
+        let Some(current_session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if self
+            .session_store
+            .remove_user_session(current_session.user_id, session_id)
+            .await?
+        {
+            Ok(StatusCode::NO_CONTENT.into_response())
+        } else {
+            Err(DBError::SessionNotFound.into())
+        }
+    }
+
+    async fn create_api_key(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Json(payload): Json<CreateApiKeyRequest>,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if let Err(e) = payload.validate() {
+            return Ok(ApiError::ValidationError(e).into_response());
+        }
+
+        let Some(user) = self.repo.get_user(session.user_id).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if !self.usecase.has_full_access(&user) {
+            return Err(UseCaseError(UsecaseError::EmailNotVerified));
+        }
+
+        let issued = self
+            .api_keys
+            .create_api_key(session.user_id, payload.into())
+            .await?;
+
+        Ok((
+            StatusCode::CREATED,
+            Json::<CreatedApiKeyResponse>(issued.into()),
+        )
+            .into_response())
+    }
+
+    async fn list_api_keys(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        let keys: Vec<ApiKeyResponse> = self
+            .api_keys
+            .list_api_keys(session.user_id)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok((StatusCode::OK, Json(keys)).into_response())
+    }
+
+    async fn delete_api_key(
+        &self,
+        jar: CookieJar,
+        bearer: BearerToken,
+        Path(key_id): Path<Uuid>,
+    ) -> Result<Response, ApiError> {
+        let Some(session) = self.authenticate(&jar, &bearer).await? else {
+            return Ok((StatusCode::UNAUTHORIZED,).into_response());
+        };
+
+        if self
+            .api_keys
+            .delete_api_key(session.user_id, key_id)
+            .await?
+        {
+            Ok(StatusCode::NO_CONTENT.into_response())
+        } else {
+            Err(DBError::ApiKeyNotFound.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{EmailVerificationPolicy, SessionConfig};
+    use crate::repo::memory_sessions::MemorySessionsRepo;
+    use crate::tokens::TokenHasher;
+    use crate::usecase::users_usecase::{MockIEmailVerifier, MockIUsersRepository, UserUsecase};
+    use argon2::password_hash::SaltString;
+    use argon2::password_hash::rand_core::OsRng;
+    use argon2::{Argon2, PasswordHasher};
+    use axum::http::header::SET_COOKIE;
+    use serde_json::Value;
+
+    const PASSWORD: &str = "VeryGoodPassword123!";
+
+    struct Fixture {
+        delivery: UsersDelivery,
+        sessions: Arc<MemorySessionsRepo>,
+        user: User,
+    }
+
+    fn fixture(users: MockIUsersRepository, api_keys: MockIApiKeysRepo) -> Fixture {
+        let salt = SaltString::generate(&mut OsRng);
+        let user = User {
+            id: Uuid::new_v4(),
+            email: "writer@example.com".to_string(),
+            password_hash: Argon2::default()
+                .hash_password(PASSWORD.as_bytes(), &salt)
+                .unwrap()
+                .to_string(),
+            ..Default::default()
+        };
+
+        let mut users = users;
+        let found = user.clone();
+        users
+            .expect_login()
+            .returning(move |_| Ok(Some(found.clone())));
+        let mut repo = MockIUsersRepo::new();
+        let found = user.clone();
+        repo.expect_get_user()
+            .returning(move |_| Ok(Some(found.clone())));
+
+        let sessions = Arc::new(MemorySessionsRepo::new(
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+        ));
+        let usecase = UserUsecase::new(
+            Arc::new(users),
+            Arc::new(MockIEmailVerifier::new()),
+            EmailVerificationPolicy::Off,
+        );
+
+        Fixture {
+            delivery: UsersDelivery::new(
+                Arc::new(repo),
+                Arc::new(usecase),
+                sessions.clone(),
+                sessions.clone(),
+                Arc::new(api_keys),
+                SessionCookieCodec::new(&[7; 64], None),
+                None,
+            ),
+            sessions,
+            user,
+        }
+    }
+
+    fn client(ip: &str) -> ClientInfo {
+        ClientInfo {
+            ip: Some(ip.to_string()),
+            user_agent: Some("test-agent".to_string()),
+        }
+    }
+
+    fn session_cookie(response: &Response) -> Cookie<'static> {
+        response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|header| Cookie::parse_encoded(header.to_str().unwrap().to_string()).unwrap())
+            .find(|cookie| cookie.name() == SESSION_COOKIE)
+            .unwrap()
+    }
+
+    /// Signs in from a browser holding `jar`, returns the cookie it got.
+    async fn login(fixture: &Fixture, jar: CookieJar, remember_me: bool) -> Cookie<'static> {
+        let response = fixture
+            .delivery
+            .login(
+                jar,
+                BearerToken(None),
+                client("192.0.2.1"),
+                Json(LoginRequest {
+                    email: fixture.user.email.clone(),
+                    password: PASSWORD.to_string(),
+                    remember_me,
+                    issue_access_token: false,
+                    cookieless: false,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        session_cookie(&response)
+    }
+
+    fn jar(cookie: &Cookie<'static>) -> CookieJar {
+        CookieJar::new().add(cookie.clone())
+    }
+
+    fn token(fixture: &Fixture, cookie: &Cookie<'static>) -> String {
+        fixture.delivery.cookies.open(cookie).unwrap()
+    }
+
+    async fn is_signed_in(fixture: &Fixture, cookie: &Cookie<'static>) -> bool {
+        let token = token(fixture, cookie);
+        fixture.sessions.get_user(&token).await.unwrap().is_some()
+    }
+
+    async fn list_sessions(fixture: &Fixture, cookie: &Cookie<'static>) -> Vec<Value> {
+        let response = fixture
+            .delivery
+            .list_sessions(jar(cookie), BearerToken(None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn change_password(new_password: &str) -> Json<ChangePasswordRequest> {
+        Json(ChangePasswordRequest {
+            current_password: PASSWORD.to_string(),
+            new_password: new_password.to_string(),
+            issue_access_token: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_login_records_where_the_session_came_from() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+        let cookie = login(&fixture, CookieJar::new(), false).await;
+
+        let sessions = list_sessions(&fixture, &cookie).await;
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["current"], true);
+        assert_eq!(sessions[0]["ip"], "192.0.2.1");
+        assert_eq!(sessions[0]["user_agent"], "test-agent");
+        assert_eq!(sessions[0]["auth_method"], "password");
+        assert_eq!(sessions[0]["kind"], "transient");
+        assert!(sessions[0]["created_at"].is_string());
+        assert!(sessions[0]["last_seen_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_remember_me_decides_the_cookie_lifetime() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+
+        let transient = login(&fixture, CookieJar::new(), false).await;
+        assert_eq!(transient.max_age(), None);
+
+        let persistent = login(&fixture, CookieJar::new(), true).await;
+        let max_age = persistent.max_age().unwrap().whole_seconds();
+        assert!((7190..=7200).contains(&max_age), "{max_age}");
+
+        let sessions = list_sessions(&fixture, &persistent).await;
+        let current = sessions.iter().find(|s| s["current"] == true).unwrap();
+        assert_eq!(current["kind"], "persistent");
+    }
+
+    #[tokio::test]
+    async fn test_login_replaces_the_presented_session() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+        let planted = login(&fixture, CookieJar::new(), false).await;
+
+        let cookie = login(&fixture, jar(&planted), false).await;
+
+        assert!(!is_signed_in(&fixture, &planted).await);
+        assert!(is_signed_in(&fixture, &cookie).await);
+        assert_ne!(token(&fixture, &planted), token(&fixture, &cookie));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_revoked_one_at_a_time() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+        let cookie = login(&fixture, CookieJar::new(), false).await;
+        let other = login(&fixture, CookieJar::new(), false).await;
+        let other_id = fixture
+            .sessions
+            .find_session(&token(&fixture, &other))
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+
+        assert_eq!(list_sessions(&fixture, &cookie).await.len(), 2);
+
+        let response = fixture
+            .delivery
+            .delete_session(jar(&cookie), BearerToken(None), Path(other_id))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(!is_signed_in(&fixture, &other).await);
+        assert!(is_signed_in(&fixture, &cookie).await);
+        assert_eq!(list_sessions(&fixture, &cookie).await.len(), 1);
+
+        assert!(
+            fixture
+                .delivery
+                .delete_session(jar(&cookie), BearerToken(None), Path(other_id))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_logout_all_ends_every_session() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+        let cookie = login(&fixture, CookieJar::new(), false).await;
+        let other = login(&fixture, CookieJar::new(), true).await;
+
+        let response = fixture
+            .delivery
+            .logout_all(jar(&cookie), BearerToken(None))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!is_signed_in(&fixture, &cookie).await);
+        assert!(!is_signed_in(&fixture, &other).await);
+    }
+
+    #[tokio::test]
+    async fn test_change_password_signs_out_everywhere_else() {
+        let mut users = MockIUsersRepository::new();
+        users
+            .expect_update_password()
+            .times(1)
+            .returning(|_, _, _| Ok(true));
+        let mut api_keys = MockIApiKeysRepo::new();
+        api_keys
+            .expect_revoke_api_keys()
+            .times(1)
+            .returning(|_| Ok(1));
+        let fixture = fixture(users, api_keys);
+        let cookie = login(&fixture, CookieJar::new(), false).await;
+        let other = login(&fixture, CookieJar::new(), true).await;
+
+        let response = fixture
+            .delivery
+            .change_password(
+                jar(&cookie),
+                BearerToken(None),
+                change_password("AnotherGoodPassword456!"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // The current session carries on under a new token.
+        let rotated = session_cookie(&response);
+        assert!(is_signed_in(&fixture, &rotated).await);
+        assert!(!is_signed_in(&fixture, &cookie).await);
+        assert!(!is_signed_in(&fixture, &other).await);
+        assert_eq!(list_sessions(&fixture, &rotated).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_user_ends_their_sessions() {
+        let fixture = fixture(MockIUsersRepository::new(), MockIApiKeysRepo::new());
+        let cookie = login(&fixture, CookieJar::new(), true).await;
+        let user_id = fixture.user.id;
+
+        let delivery = UsersDelivery {
+            repo: {
+                let mut repo = MockIUsersRepo::new();
+                repo.expect_delete_user()
+                    .times(1)
+                    .withf(move |id| *id == user_id)
+                    .returning(|_| Ok(true));
+                Arc::new(repo)
+            },
+            ..fixture.delivery
+        };
+
+        let response = delivery.delete_user(Path(user_id)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let token = delivery.cookies.open(&cookie).unwrap();
+        assert!(fixture.sessions.get_user(&token).await.unwrap().is_none());
+    }
 }
@@ -1,10 +1,12 @@
 use axum::Json;
-use axum::http::StatusCode;
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
 use serde_json::json;
 use thiserror::Error;
 use validator::ValidationErrors;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum ApiError {
     #[error("DB error {0}")]
@@ -15,6 +17,126 @@ pub enum ApiError {
 
     #[error("Validation error {0}")]
     ValidationError(#[from] ValidationErrors),
+
+    #[error("Token error {0}")]
+    TokenError(#[from] TokenError),
+
+    #[error("OAuth error {0}")]
+    OAuthError(#[from] OAuthError),
+}
+
+/// Errors of the OAuth endpoints, rendered as RFC 6749 section 5.2 error responses.
+#[derive(Error, Debug)]
+pub enum OAuthError {
+    #[error("{0}")]
+    InvalidRequest(&'static str),
+
+    #[error("Client authentication failed")]
+    InvalidClient,
+
+    #[error("Grant type is not supported")]
+    UnsupportedGrantType,
+
+    #[error("Authorization grant is invalid, expired or was already used")]
+    InvalidGrant,
+
+    #[error("Requested scope exceeds the granted scopes")]
+    InvalidScope,
+
+    #[error("Token type cannot be revoked")]
+    UnsupportedTokenType,
+
+    #[error("Token was not issued to this client")]
+    UnauthorizedClient,
+}
+
+impl OAuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::InvalidGrant => "invalid_grant",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::UnsupportedTokenType => "unsupported_token_type",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+        }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self {
+            OAuthError::InvalidClient => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TokenError {
+    #[error("Invalid signing key {0}")]
+    InvalidSigningKey(#[source] jsonwebtoken::errors::Error),
+
+    #[error("Failed to parse signing key {0}")]
+    FailedToParseSigningKey(#[from] ed25519_dalek::pkcs8::Error),
+
+    #[error("Failed to sign token {0}")]
+    FailedToSign(#[source] jsonwebtoken::errors::Error),
+
+    #[error("Access tokens are not enabled")]
+    AccessTokensDisabled,
+
+    #[error("Invalid token {0}")]
+    InvalidToken(#[source] jsonwebtoken::errors::Error),
+
+    #[error("Token is signed with an unknown key")]
+    UnknownSigningKey,
+
+    #[error("Token was not issued to a service")]
+    NotAServiceToken,
+
+    #[error("Token lacks the {0} scope")]
+    InsufficientScope(&'static str),
+}
+
+impl TokenError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            TokenError::AccessTokensDisabled => StatusCode::BAD_REQUEST,
+            TokenError::InvalidToken(_)
+            | TokenError::UnknownSigningKey
+            | TokenError::NotAServiceToken => StatusCode::UNAUTHORIZED,
+            TokenError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Failures of the sign-in at an external identity provider, shown to the user as a page.
+#[derive(Error, Debug)]
+pub enum ExternalIdpError {
+    #[error("Request to the identity provider failed {0}")]
+    RequestFailed(#[from] reqwest::Error),
+
+    #[error("Identity provider did not return the {0} of the user")]
+    MissingClaim(&'static str),
+
+    #[error("Email is not verified by the identity provider")]
+    EmailNotVerified,
+}
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("Invalid email address {0}")]
+    InvalidAddress(#[from] lettre::address::AddressError),
+
+    #[error("Failed to build email {0}")]
+    FailedToBuild(#[from] lettre::error::Error),
+
+    #[error("Failed to connect to the mail server {0}")]
+    FailedToConnect(#[source] lettre::transport::smtp::Error),
+
+    #[error("Failed to send email {0}")]
+    FailedToSend(#[source] lettre::transport::smtp::Error),
 }
 
 #[derive(Error, Debug)]
@@ -46,6 +168,8 @@ pub enum UsecaseError {
     UserNotFoundError,
     #[error("Invalid credentials")]
     InvalidCreds,
+    #[error("Email is not verified")]
+    EmailNotVerified,
 }
 
 impl UsecaseError {
@@ -55,6 +179,7 @@ impl UsecaseError {
             UsecaseError::HashPasswordError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             UsecaseError::UserNotFoundError => StatusCode::NOT_FOUND,
             UsecaseError::InvalidCreds => StatusCode::CONFLICT,
+            UsecaseError::EmailNotVerified => StatusCode::FORBIDDEN,
         }
     }
 }
@@ -88,9 +213,57 @@ pub enum DBError {
     #[error("Failed to delete session {0}")]
     FailedToDeleteSession(#[source] deadpool_redis::redis::RedisError),
 
+    #[error("Failed to list sessions {0}")]
+    FailedToListSessions(#[source] deadpool_redis::redis::RedisError),
+
+    #[error("Failed to query sessions {0}")]
+    FailedToQuerySessions(#[source] sqlx::Error),
+
+    #[error("Failed to migrate sessions {0}")]
+    FailedToMigrateSessions(#[source] deadpool_redis::redis::RedisError),
+
+    #[error("Failed to (de)serialize session {0}")]
+    FailedToSerializeSession(#[source] serde_json::Error),
+
     #[error("Session not found")]
     SessionNotFound,
 
+    #[error("Refresh token was already used")]
+    RefreshTokenReused,
+
+    #[error("Failed to query api keys {0}")]
+    FailedToQueryApiKeys(#[source] sqlx::Error),
+
+    #[error("Api key not found")]
+    ApiKeyNotFound,
+
+    #[error("Failed to query service accounts {0}")]
+    FailedToQueryServiceAccounts(#[source] sqlx::Error),
+
+    #[error("Failed to query oauth clients {0}")]
+    FailedToQueryOAuthClients(#[source] sqlx::Error),
+
+    #[error("Failed to query oauth consents {0}")]
+    FailedToQueryOAuthConsents(#[source] sqlx::Error),
+
+    #[error("Failed to query authorization codes {0}")]
+    FailedToQueryAuthorizationCodes(#[source] sqlx::Error),
+
+    #[error("Consent not found")]
+    ConsentNotFound,
+
+    #[error("Failed to query identities {0}")]
+    FailedToQueryIdentities(#[source] sqlx::Error),
+
+    #[error("Identity is already linked to an account")]
+    IdentityAlreadyLinked,
+
+    #[error("Failed to query email tokens {0}")]
+    FailedToQueryEmailTokens(#[source] sqlx::Error),
+
+    #[error("Token is invalid, expired or was already used")]
+    InvalidEmailToken,
+
     #[error("Failed to parse UUID {0}")]
     FailedToParseUUID(#[from] uuid::Error),
 
@@ -102,6 +275,11 @@ impl DBError {
     fn status_code(&self) -> StatusCode {
         match self {
             DBError::SessionNotFound => StatusCode::NOT_FOUND,
+            DBError::RefreshTokenReused => StatusCode::UNAUTHORIZED,
+            DBError::ApiKeyNotFound => StatusCode::NOT_FOUND,
+            DBError::ConsentNotFound => StatusCode::NOT_FOUND,
+            DBError::IdentityAlreadyLinked => StatusCode::CONFLICT,
+            DBError::InvalidEmailToken => StatusCode::BAD_REQUEST,
             DBError::UserAlreadyExists => StatusCode::CONFLICT,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -113,6 +291,26 @@ impl IntoResponse for ApiError {
         let (code, err_body) = match self {
             ApiError::DataBaseError(err) => (err.status_code(), err.to_string()),
             ApiError::UseCaseError(err) => (err.status_code(), err.to_string()),
+            ApiError::TokenError(err) => (err.status_code(), err.to_string()),
+            ApiError::OAuthError(err) => {
+                let mut response = (
+                    err.status_code(),
+                    Json(json!({
+                        "error": err.code(),
+                        "error_description": err.to_string()
+                    })),
+                )
+                    .into_response();
+
+                if matches!(err, OAuthError::InvalidClient) {
+                    response.headers_mut().insert(
+                        WWW_AUTHENTICATE,
+                        HeaderValue::from_static(r#"Basic realm="oauth""#),
+                    );
+                }
+
+                return response;
+            }
             ApiError::ValidationError(errors) => {
                 return (
                     StatusCode::BAD_REQUEST,
@@ -0,0 +1,409 @@
+use crate::config::{ClaimMapping, ExternalIdpConfig};
+use crate::errors::ExternalIdpError;
+use serde::Deserialize;
+use serde_json::Value;
+use std::str::FromStr;
+use url::Url;
+
+/// How the userinfo endpoint expects the access token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserInfoMethod {
+    /// `GET` with the access token as a bearer token.
+    Get,
+    /// `POST` of a form carrying the access token and the client id, as VK ID expects.
+    Post,
+}
+
+impl FromStr for UserInfoMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "get" => Ok(UserInfoMethod::Get),
+            "post" => Ok(UserInfoMethod::Post),
+            other => Err(format!("unknown userinfo method {other}")),
+        }
+    }
+}
+
+/// Endpoints and claim mapping of a well-known provider.
+pub struct Preset {
+    pub authorize_url: &'static str,
+    pub token_url: &'static str,
+    pub userinfo_url: &'static str,
+    pub userinfo_method: UserInfoMethod,
+    pub scopes: &'static str,
+    pub subject: &'static str,
+    pub email: &'static str,
+    pub email_verified: Option<&'static str>,
+    pub username: Option<&'static str>,
+}
+
+pub fn preset(name: &str) -> Option<Preset> {
+    match name {
+        "google" => Some(Preset {
+            authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+            token_url: "https://oauth2.googleapis.com/token",
+            userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo",
+            userinfo_method: UserInfoMethod::Get,
+            scopes: "openid email profile",
+            subject: "sub",
+            email: "email",
+            email_verified: Some("email_verified"),
+            username: None,
+        }),
+        // The email is only returned when the user made it public on their profile.
+        "github" => Some(Preset {
+            authorize_url: "https://github.com/login/oauth/authorize",
+            token_url: "https://github.com/login/oauth/access_token",
+            userinfo_url: "https://api.github.com/user",
+            userinfo_method: UserInfoMethod::Get,
+            scopes: "read:user user:email",
+            subject: "id",
+            email: "email",
+            email_verified: None,
+            username: Some("login"),
+        }),
+        "yandex" => Some(Preset {
+            authorize_url: "https://oauth.yandex.ru/authorize",
+            token_url: "https://oauth.yandex.ru/token",
+            userinfo_url: "https://login.yandex.ru/info?format=json",
+            userinfo_method: UserInfoMethod::Get,
+            scopes: "login:email login:info",
+            subject: "id",
+            email: "default_email",
+            email_verified: None,
+            username: Some("login"),
+        }),
+        "vk" => Some(Preset {
+            authorize_url: "https://id.vk.com/authorize",
+            token_url: "https://id.vk.com/oauth2/auth",
+            userinfo_url: "https://id.vk.com/oauth2/user_info",
+            userinfo_method: UserInfoMethod::Post,
+            scopes: "email",
+            subject: "user.user_id",
+            email: "user.email",
+            email_verified: None,
+            username: None,
+        }),
+        _ => None,
+    }
+}
+
+/// The provider account a user proved they own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: String,
+    pub username: String,
+    /// Only set when the provider asserts it, providers without an
+    /// `email_verified` claim are not trusted with addresses.
+    pub email_verified: bool,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Client side of the authorization code flow with PKCE against one provider.
+pub struct IdentityProvider {
+    config: ExternalIdpConfig,
+    authorize_url: Url,
+    http: reqwest::Client,
+}
+
+impl IdentityProvider {
+    pub fn new(config: ExternalIdpConfig, http: reqwest::Client) -> Result<Self, url::ParseError> {
+        Ok(IdentityProvider {
+            authorize_url: Url::parse(&config.authorize_url)?,
+            config,
+            http,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Where to send the browser to sign in at the provider.
+    pub fn authorization_url(&self, redirect_uri: &str, state: &str, code_challenge: &str) -> Url {
+        let mut url = self.authorize_url.clone();
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", state)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        url
+    }
+
+    /// Exchanges the authorization code and reads the user from the userinfo endpoint.
+    ///
+    /// VK ID hands a `device_id` to the callback and wants it back in the token request.
+    pub async fn identify(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        redirect_uri: &str,
+        device_id: Option<&str>,
+    ) -> Result<ExternalIdentity, ExternalIdpError> {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret));
+        }
+        if let Some(device_id) = device_id {
+            form.push(("device_id", device_id));
+        }
+
+        let token: TokenResponse = self
+            .http
+            .post(&self.config.token_url)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let request = match self.config.userinfo_method {
+            UserInfoMethod::Get => self
+                .http
+                .get(&self.config.userinfo_url)
+                .bearer_auth(&token.access_token),
+            UserInfoMethod::Post => self.http.post(&self.config.userinfo_url).form(&[
+                ("access_token", token.access_token.as_str()),
+                ("client_id", &self.config.client_id),
+            ]),
+        };
+
+        let userinfo: Value = request
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        map_claims(&userinfo, &self.config.claims)
+    }
+}
+
+fn map_claims(
+    userinfo: &Value,
+    claims: &ClaimMapping,
+) -> Result<ExternalIdentity, ExternalIdpError> {
+    let subject = claim(userinfo, &claims.subject).ok_or(ExternalIdpError::MissingClaim("id"))?;
+    let email = claim(userinfo, &claims.email).ok_or(ExternalIdpError::MissingClaim("email"))?;
+
+    // A provider vouching for an address it never verified would let anyone
+    // claim someone else's email, so a missing flag counts as unverified.
+    if let Some(path) = &claims.email_verified {
+        let verified = match lookup(userinfo, path) {
+            Some(Value::Bool(verified)) => *verified,
+            Some(Value::String(verified)) => verified == "true",
+            _ => false,
+        };
+
+        if !verified {
+            return Err(ExternalIdpError::EmailNotVerified);
+        }
+    }
+
+    let username = claims
+        .username
+        .as_deref()
+        .and_then(|path| claim(userinfo, path))
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+
+    Ok(ExternalIdentity {
+        subject,
+        email,
+        username,
+        email_verified: claims.email_verified.is_some(),
+    })
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+/// A non-empty string or number, providers disagree on the type of their ids.
+fn claim(value: &Value, path: &str) -> Option<String> {
+    match lookup(value, path)? {
+        Value::String(value) if !value.is_empty() => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::pkce_challenge;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    /// A provider shaped like GitHub: numeric ids and no email verification flag.
+    async fn mock_idp() -> String {
+        let router = Router::new()
+            .route(
+                "/token",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    let valid = form.get("code").map(String::as_str) == Some("good-code")
+                        && form.get("code_verifier").map(String::as_str) == Some(VERIFIER);
+
+                    if valid {
+                        Ok(Json(json!({"access_token": "mock-access-token"})))
+                    } else {
+                        Err(StatusCode::BAD_REQUEST)
+                    }
+                }),
+            )
+            .route(
+                "/userinfo",
+                get(|headers: HeaderMap| async move {
+                    if headers.get(AUTHORIZATION).map(|v| v.as_bytes())
+                        != Some(b"Bearer mock-access-token")
+                    {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+
+                    Ok(Json(json!({
+                        "id": 583231,
+                        "login": "octocat",
+                        "email": "octocat@example.com"
+                    })))
+                }),
+            );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        base
+    }
+
+    fn provider(base: &str) -> IdentityProvider {
+        let config = ExternalIdpConfig {
+            name: "mock".to_string(),
+            client_id: "client".to_string(),
+            client_secret: Some("secret".to_string()),
+            authorize_url: format!("{base}/authorize"),
+            token_url: format!("{base}/token"),
+            userinfo_url: format!("{base}/userinfo"),
+            userinfo_method: UserInfoMethod::Get,
+            scopes: vec!["read:user".to_string(), "user:email".to_string()],
+            claims: ClaimMapping {
+                subject: "id".to_string(),
+                email: "email".to_string(),
+                email_verified: None,
+                username: Some("login".to_string()),
+            },
+        };
+
+        IdentityProvider::new(config, reqwest::Client::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_identify_against_mock_idp() {
+        let base = mock_idp().await;
+        let idp = provider(&base);
+
+        let url = idp.authorization_url("http://app/callback", "state", &pkce_challenge(VERIFIER));
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(
+            query["code_challenge"],
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        assert_eq!(query["scope"], "read:user user:email");
+
+        let identity = idp
+            .identify("good-code", VERIFIER, "http://app/callback", None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            identity,
+            ExternalIdentity {
+                subject: "583231".to_string(),
+                email: "octocat@example.com".to_string(),
+                username: "octocat".to_string(),
+                email_verified: false,
+            }
+        );
+
+        let wrong_verifier = idp
+            .identify("good-code", &VERIFIER[1..], "http://app/callback", None)
+            .await;
+        assert!(matches!(
+            wrong_verifier,
+            Err(ExternalIdpError::RequestFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_map_claims() {
+        let vk = ClaimMapping {
+            subject: "user.user_id".to_string(),
+            email: "user.email".to_string(),
+            email_verified: None,
+            username: None,
+        };
+        let identity = map_claims(
+            &json!({"user": {"user_id": "1234", "email": "ivan@example.ru"}}),
+            &vk,
+        )
+        .unwrap();
+        assert_eq!(identity.subject, "1234");
+        assert_eq!(identity.username, "ivan");
+        assert!(!identity.email_verified);
+
+        assert!(matches!(
+            map_claims(&json!({"user": {"user_id": "1234", "email": ""}}), &vk),
+            Err(ExternalIdpError::MissingClaim("email"))
+        ));
+
+        let google = ClaimMapping {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: Some("email_verified".to_string()),
+            username: None,
+        };
+        assert!(matches!(
+            map_claims(
+                &json!({"sub": "1", "email": "a@example.com", "email_verified": false}),
+                &google
+            ),
+            Err(ExternalIdpError::EmailNotVerified)
+        ));
+        assert!(matches!(
+            map_claims(&json!({"sub": "1", "email": "a@example.com"}), &google),
+            Err(ExternalIdpError::EmailNotVerified)
+        ));
+        assert!(
+            map_claims(
+                &json!({"sub": "1", "email": "a@example.com", "email_verified": true}),
+                &google
+            )
+            .unwrap()
+            .email_verified
+        );
+    }
+}
@@ -1,6 +1,8 @@
 // This file is @generated by prost-build.
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct GetUserRequest {
+    /// Value of the session_id cookie exactly as the client sent it.
+    /// Ignored when the call carries "authorization: Bearer <token>" metadata.
     #[prost(string, tag = "1")]
     pub session_id: ::prost::alloc::string::String,
 }
@@ -8,6 +10,46 @@ pub struct GetUserRequest {
 pub struct GetUserResponse {
     #[prost(string, tag = "1")]
     pub user_id: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub session: ::core::option::Option<SessionInfo>,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct SessionInfo {
+    #[prost(string, tag = "1")]
+    pub session_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub ip: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub user_agent: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub auth_method: ::prost::alloc::string::String,
+    /// Unix timestamps, seconds.
+    #[prost(int64, tag = "5")]
+    pub created_at: i64,
+    #[prost(int64, tag = "6")]
+    pub last_seen_at: i64,
+    #[prost(int64, tag = "7")]
+    pub expires_at: i64,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct ResolveApiKeyRequest {
+    /// The full key as presented by the client.
+    #[prost(string, tag = "1")]
+    pub api_key: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct ResolveApiKeyResponse {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "4")]
+    pub scopes: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Unix timestamp, seconds. Zero when the key never expires.
+    #[prost(int64, tag = "5")]
+    pub expires_at: i64,
 }
 /// Generated server implementations.
 pub mod users_provider_server {
@@ -22,11 +64,22 @@ pub mod users_provider_server {
     /// Generated trait containing gRPC methods that should be implemented for use with UsersProviderServer.
     #[async_trait]
     pub trait UsersProvider: std::marker::Send + std::marker::Sync + 'static {
+        /// Requires the users:read scope when called with a service token.
         async fn get_user(
             &self,
             request: tonic::Request<super::GetUserRequest>,
         ) -> std::result::Result<tonic::Response<super::GetUserResponse>, tonic::Status>;
+        /// Requires the keys:resolve scope when called with a service token.
+        async fn resolve_api_key(
+            &self,
+            request: tonic::Request<super::ResolveApiKeyRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ResolveApiKeyResponse>,
+            tonic::Status,
+        >;
     }
+    /// Calling services identify themselves with "x-service-token: <access token>" metadata,
+    /// obtained from the client credentials grant at POST /oauth/token.
     #[derive(Debug)]
     pub struct UsersProviderServer<T> {
         inner: Arc<T>,
@@ -148,6 +201,51 @@ pub mod users_provider_server {
                     };
                     Box::pin(fut)
                 }
+                "/auth.UsersProvider/ResolveApiKey" => {
+                    #[allow(non_camel_case_types)]
+                    struct ResolveApiKeySvc<T: UsersProvider>(pub Arc<T>);
+                    impl<
+                        T: UsersProvider,
+                    > tonic::server::UnaryService<super::ResolveApiKeyRequest>
+                    for ResolveApiKeySvc<T> {
+                        type Response = super::ResolveApiKeyResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ResolveApiKeyRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as UsersProvider>::resolve_api_key(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ResolveApiKeySvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(
@@ -1,9 +1,19 @@
 use crate::app::AuthApp;
-use crate::delivery_http::dto::{LoginRequest, RegisterRequest, UpdateUserRequest};
+use crate::delivery_http::bearer::BearerToken;
+use crate::delivery_http::calling_service::CallingService;
+use crate::delivery_http::client_auth::BasicCredentials;
+use crate::delivery_http::client_info::ClientInfo;
+use crate::delivery_http::dto::{
+    AuthorizeForm, AuthorizeRequest, ChangeEmailRequest, ChangePasswordRequest,
+    CreateApiKeyRequest, EmailRevertToken, ExternalLoginCallback, ExternalLoginRequest,
+    ForgotPasswordRequest, LoginRequest, MagicLinkRequest, MagicLinkToken, OAuthTokenActionRequest,
+    OAuthTokenRequest, RefreshTokenRequest, RegisterRequest, ResendVerificationRequest,
+    ResetPasswordRequest, UpdateUserRequest, VerifyEmailRequest,
+};
 use crate::errors::ApiError;
-use axum::Json;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::response::IntoResponse;
+use axum::{Form, Json};
 use axum_extra::extract::CookieJar;
 use std::sync::Arc;
 use uuid::Uuid;
@@ -11,9 +21,13 @@ use uuid::Uuid;
 pub async fn create_user(
     State(app): State<Arc<AuthApp>>,
     jar: CookieJar,
+    bearer: BearerToken,
+    client: ClientInfo,
     payload: Json<RegisterRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
-    app.http_delivery.create_user(jar, payload).await
+    app.http_delivery
+        .create_user(jar, bearer, client, payload)
+        .await
 }
 
 pub async fn update_user(
@@ -41,21 +55,289 @@ pub async fn get_user(
 pub async fn login(
     State(app): State<Arc<AuthApp>>,
     jar: CookieJar,
+    bearer: BearerToken,
+    client: ClientInfo,
     payload: Json<LoginRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
-    app.http_delivery.login(jar, payload).await
+    app.http_delivery.login(jar, bearer, client, payload).await
 }
 
 pub async fn logout(
     State(app): State<Arc<AuthApp>>,
     jar: CookieJar,
+    bearer: BearerToken,
 ) -> Result<impl IntoResponse, ApiError> {
-    app.http_delivery.logout(jar).await
+    app.http_delivery.logout(jar, bearer).await
+}
+
+pub async fn logout_all(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.logout_all(jar, bearer).await
 }
 
 pub async fn get_user_from_cookie(
     State(app): State<Arc<AuthApp>>,
     jar: CookieJar,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.get_user_from_cookie(jar, bearer).await
+}
+
+pub async fn change_password(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    payload: Json<ChangePasswordRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery
+        .change_password(jar, bearer, payload)
+        .await
+}
+
+pub async fn request_email_change(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    payload: Json<ChangeEmailRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery
+        .request_email_change(jar, bearer, payload)
+        .await
+}
+
+pub async fn list_sessions(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.list_sessions(jar, bearer).await
+}
+
+pub async fn delete_session(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    session_id: Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery
+        .delete_session(jar, bearer, session_id)
+        .await
+}
+
+pub async fn jwks(State(app): State<Arc<AuthApp>>) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.jwks().await
+}
+
+pub async fn refresh_token(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<RefreshTokenRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.refresh(payload).await
+}
+
+pub async fn create_api_key(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    payload: Json<CreateApiKeyRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.create_api_key(jar, bearer, payload).await
+}
+
+pub async fn list_api_keys(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.list_api_keys(jar, bearer).await
+}
+
+pub async fn delete_api_key(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    key_id: Path<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.http_delivery.delete_api_key(jar, bearer, key_id).await
+}
+
+pub async fn oauth_token(
+    State(app): State<Arc<AuthApp>>,
+    basic: BasicCredentials,
+    payload: Form<OAuthTokenRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.token(basic, payload).await
+}
+
+pub async fn oauth_authorize(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    request: Query<AuthorizeRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.oauth_delivery.authorize(jar, request).await
+}
+
+pub async fn oauth_authorize_submit(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    client: ClientInfo,
+    form: Form<AuthorizeForm>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.oauth_delivery.authorize_submit(jar, client, form).await
+}
+
+pub async fn request_magic_link(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    payload: Json<MagicLinkRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.magic_link_delivery.request(jar, payload).await
+}
+
+pub async fn confirm_magic_link(
+    State(app): State<Arc<AuthApp>>,
+    link: Query<MagicLinkToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.magic_link_delivery.confirm(link).await
+}
+
+pub async fn verify_magic_link(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    client: ClientInfo,
+    link: Form<MagicLinkToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.magic_link_delivery.verify(jar, client, link).await
+}
+
+pub async fn verify_email(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<VerifyEmailRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.email_verification_delivery.verify(payload).await
+}
+
+pub async fn resend_email_verification(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<ResendVerificationRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.email_verification_delivery.resend(payload).await
+}
+
+pub async fn confirm_email_change(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<VerifyEmailRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.email_verification_delivery
+        .confirm_change(payload)
+        .await
+}
+
+pub async fn revert_email_page(
+    State(app): State<Arc<AuthApp>>,
+    link: Query<EmailRevertToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.email_verification_delivery.revert_page(link).await
+}
+
+pub async fn revert_email(
+    State(app): State<Arc<AuthApp>>,
+    link: Form<EmailRevertToken>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.email_verification_delivery.revert(link).await
+}
+
+pub async fn forgot_password(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<ForgotPasswordRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.password_reset_delivery.forgot(payload).await
+}
+
+pub async fn reset_password(
+    State(app): State<Arc<AuthApp>>,
+    payload: Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.password_reset_delivery.reset(payload).await
+}
+
+pub async fn external_login(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    provider: Path<String>,
+    request: Query<ExternalLoginRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.external_login_delivery
+        .start(jar, provider, request)
+        .await
+}
+
+pub async fn external_login_callback(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    client: ClientInfo,
+    provider: Path<String>,
+    callback: Query<ExternalLoginCallback>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.external_login_delivery
+        .callback(jar, client, provider, callback)
+        .await
+}
+
+pub async fn list_oauth_consents(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.oauth_delivery.list_consents(jar, bearer).await
+}
+
+pub async fn revoke_oauth_consent(
+    State(app): State<Arc<AuthApp>>,
+    jar: CookieJar,
+    bearer: BearerToken,
+    client_id: Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.oauth_delivery
+        .revoke_consent(jar, bearer, client_id)
+        .await
+}
+
+pub async fn oauth_introspect(
+    State(app): State<Arc<AuthApp>>,
+    basic: BasicCredentials,
+    payload: Form<OAuthTokenActionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.introspect(basic, payload).await
+}
+
+pub async fn oauth_revoke(
+    State(app): State<Arc<AuthApp>>,
+    basic: BasicCredentials,
+    payload: Form<OAuthTokenActionRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.revoke(basic, payload).await
+}
+
+pub async fn openid_configuration(
+    State(app): State<Arc<AuthApp>>,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.openid_configuration().await
+}
+
+pub async fn userinfo(
+    State(app): State<Arc<AuthApp>>,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, ApiError> {
+    app.tokens_delivery.userinfo(bearer).await
+}
+
+pub async fn calling_service(
+    State(app): State<Arc<AuthApp>>,
+    caller: CallingService,
 ) -> Result<impl IntoResponse, ApiError> {
-    app.http_delivery.get_user_from_cookie(jar).await
+    app.tokens_delivery.calling_service(caller).await
 }
@@ -0,0 +1,66 @@
+use crate::errors::MailError;
+use async_trait::async_trait;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+/// A plain text message.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait IMailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), MailError>;
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(url: &str, from: &str) -> Result<Self, MailError> {
+        Ok(SmtpMailer {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)
+                .map_err(MailError::FailedToConnect)?
+                .build(),
+            from: from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl IMailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(email.to.parse()?)
+            .subject(email.subject)
+            .body(email.body)?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(MailError::FailedToSend)?;
+
+        Ok(())
+    }
+}
+
+/// Prints messages instead of sending them, links can be followed from the log.
+pub struct LogMailer;
+
+#[async_trait]
+impl IMailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<(), MailError> {
+        println!(
+            "mail to {}\nsubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+
+        Ok(())
+    }
+}
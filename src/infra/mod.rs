@@ -1,2 +1,3 @@
+pub mod mailer;
 pub mod postgres;
 pub mod redis;
@@ -5,6 +5,7 @@ use crate::errors::DBInfraError::{
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{Connection, Pool, Postgres};
 
+#[derive(Clone)]
 pub struct PGPool {
     pub pool: Pool<Postgres>,
 }
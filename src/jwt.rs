@@ -0,0 +1,525 @@
+use crate::errors::TokenError;
+use crate::model::{ServiceAccount, ServiceIdentity, User};
+use crate::oidc::{IdTokenClaims, UserInfo};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use ed25519_dalek::SigningKey;
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::RsaPrivateKey;
+use rsa::traits::PublicKeyParts;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+const KEY_TYPE: &str = "OKP";
+const CURVE: &str = "Ed25519";
+const ALGORITHM: &str = "EdDSA";
+const RSA_KEY_TYPE: &str = "RSA";
+const RSA_ALGORITHM: &str = "RS256";
+const KEY_USE: &str = "sig";
+
+/// Claims of an access token.
+///
+/// User tokens carry the user id in `sub` and the public id of their session in `sid`.
+/// Tokens issued to an application through the authorization code grant add its
+/// `client_id` and the approved `scope`.
+/// Service tokens from the client credentials grant carry the client id in both `sub`
+/// and `client_id`, and the granted scopes space-separated in `scope`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl AccessClaims {
+    /// The calling service, `None` for tokens issued to users.
+    pub fn service(&self) -> Option<ServiceIdentity> {
+        if self.sid.is_some() {
+            return None;
+        }
+
+        Some(ServiceIdentity {
+            client_id: self.client_id.clone()?,
+            scopes: self
+                .scope
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+}
+
+pub struct AccessToken {
+    pub token: String,
+    pub expires_in: u64,
+}
+
+/// Public half of a signing key as published in the JWKS document.
+///
+/// Ed25519 keys carry `crv` and `x`, RSA keys the modulus `n` and exponent `e`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    pub kid: String,
+    pub alg: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+}
+
+impl Jwk {
+    fn from_signing_key(key: &SigningKey) -> Self {
+        let x = URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes());
+
+        // RFC 7638 thumbprint: members in lexicographic order, no whitespace.
+        let canonical = format!(r#"{{"crv":"{CURVE}","kty":"{KEY_TYPE}","x":"{x}"}}"#);
+        let kid = URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()));
+
+        Jwk {
+            kty: KEY_TYPE.to_string(),
+            crv: Some(CURVE.to_string()),
+            x: Some(x),
+            n: None,
+            e: None,
+            kid,
+            alg: ALGORITHM.to_string(),
+            key_use: KEY_USE.to_string(),
+        }
+    }
+
+    fn from_rsa_key(key: &RsaPrivateKey) -> Self {
+        let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+        let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+
+        let canonical = format!(r#"{{"e":"{e}","kty":"{RSA_KEY_TYPE}","n":"{n}"}}"#);
+        let kid = URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()));
+
+        Jwk {
+            kty: RSA_KEY_TYPE.to_string(),
+            crv: None,
+            x: None,
+            n: Some(n),
+            e: Some(e),
+            kid,
+            alg: RSA_ALGORITHM.to_string(),
+            key_use: KEY_USE.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Signs short-lived EdDSA access tokens so other services can authenticate
+/// requests offline against the published JWKS.
+pub struct AccessTokenIssuer {
+    encoding_key: EncodingKey,
+    /// Keyed by kid, covers the current and the previous key.
+    decoding_keys: Vec<(String, DecodingKey)>,
+    jwk: Jwk,
+    /// Keys retired by a rotation, still published until the tokens they signed expire.
+    previous: Vec<Jwk>,
+    /// RSA key for RS256 id_tokens, which every OpenID Connect client can verify.
+    /// Without it id_tokens are signed with the EdDSA access token key.
+    id_token_key: Option<(EncodingKey, Jwk)>,
+    issuer: String,
+    ttl: u64,
+}
+
+impl AccessTokenIssuer {
+    /// Both signing keys are PKCS#8 PEM encoded Ed25519 private keys,
+    /// the id_token key is a PKCS#8 PEM encoded RSA private key.
+    pub fn from_pem(
+        signing_key_pem: &str,
+        previous_key_pem: Option<&str>,
+        id_token_key_pem: Option<&str>,
+        issuer: String,
+        ttl: u64,
+    ) -> Result<Self, TokenError> {
+        let signing_key = SigningKey::from_pkcs8_pem(signing_key_pem)?;
+        let encoding_key = EncodingKey::from_ed_pem(signing_key_pem.as_bytes())
+            .map_err(TokenError::InvalidSigningKey)?;
+
+        let previous: Vec<Jwk> = previous_key_pem
+            .map(SigningKey::from_pkcs8_pem)
+            .transpose()?
+            .iter()
+            .map(Jwk::from_signing_key)
+            .collect();
+
+        let jwk = Jwk::from_signing_key(&signing_key);
+        let decoding_keys = std::iter::once(&jwk)
+            .chain(&previous)
+            .map(|jwk| {
+                DecodingKey::from_ed_components(jwk.x.as_deref().unwrap_or_default())
+                    .map(|key| (jwk.kid.clone(), key))
+                    .map_err(TokenError::InvalidSigningKey)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let id_token_key = id_token_key_pem
+            .map(|pem| -> Result<_, TokenError> {
+                let jwk = Jwk::from_rsa_key(&RsaPrivateKey::from_pkcs8_pem(pem)?);
+                let key = EncodingKey::from_rsa_pem(pem.as_bytes())
+                    .map_err(TokenError::InvalidSigningKey)?;
+                Ok((key, jwk))
+            })
+            .transpose()?;
+
+        Ok(AccessTokenIssuer {
+            encoding_key,
+            decoding_keys,
+            jwk,
+            previous,
+            id_token_key,
+            issuer,
+            ttl,
+        })
+    }
+
+    pub fn issue(&self, user_id: Uuid, session_id: Uuid) -> Result<AccessToken, TokenError> {
+        self.sign(self.user_claims(user_id, session_id))
+    }
+
+    /// Authorization code grant, `scopes` are the ones the user approved for the client.
+    pub fn issue_for_client(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<AccessToken, TokenError> {
+        self.sign(AccessClaims {
+            client_id: Some(client_id.to_string()),
+            scope: Some(scopes.join(" ")),
+            ..self.user_claims(user_id, session_id)
+        })
+    }
+
+    /// OpenID Connect id_token for `client_id`, with the claims its scopes allow.
+    pub fn issue_id_token(
+        &self,
+        user: &User,
+        client_id: &str,
+        auth_time: i64,
+        nonce: Option<String>,
+        scopes: &[String],
+    ) -> Result<String, TokenError> {
+        let now = Utc::now().timestamp();
+
+        let claims = IdTokenClaims {
+            iss: self.issuer.clone(),
+            aud: client_id.to_string(),
+            iat: now,
+            exp: now + self.ttl as i64,
+            auth_time,
+            nonce,
+            user: UserInfo::new(user, scopes),
+        };
+
+        let (header, key) = match &self.id_token_key {
+            Some((key, jwk)) => {
+                let mut header = Header::new(Algorithm::RS256);
+                header.kid = Some(jwk.kid.clone());
+                (header, key)
+            }
+            None => (self.header(), &self.encoding_key),
+        };
+
+        jsonwebtoken::encode(&header, &claims, key).map_err(TokenError::FailedToSign)
+    }
+
+    /// The `alg` id_tokens are signed with.
+    pub fn id_token_algorithm(&self) -> &'static str {
+        match self.id_token_key {
+            Some(_) => RSA_ALGORITHM,
+            None => ALGORITHM,
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    fn user_claims(&self, user_id: Uuid, session_id: Uuid) -> AccessClaims {
+        let now = Utc::now().timestamp();
+
+        AccessClaims {
+            iss: self.issuer.clone(),
+            sub: user_id.to_string(),
+            sid: Some(session_id),
+            client_id: None,
+            scope: None,
+            iat: now,
+            exp: now + self.ttl as i64,
+        }
+    }
+
+    /// Client credentials grant, `scopes` must already be checked against the account.
+    pub fn issue_for_service(
+        &self,
+        account: &ServiceAccount,
+        scopes: &[String],
+    ) -> Result<AccessToken, TokenError> {
+        let now = Utc::now().timestamp();
+
+        self.sign(AccessClaims {
+            iss: self.issuer.clone(),
+            sub: account.client_id.clone(),
+            sid: None,
+            client_id: Some(account.client_id.clone()),
+            scope: Some(scopes.join(" ")),
+            iat: now,
+            exp: now + self.ttl as i64,
+        })
+    }
+
+    /// Checks signature, issuer and expiry against the current and the previous key.
+    pub fn verify(&self, token: &str) -> Result<AccessClaims, TokenError> {
+        let header = jsonwebtoken::decode_header(token).map_err(TokenError::InvalidToken)?;
+
+        let Some((_, key)) = self
+            .decoding_keys
+            .iter()
+            .find(|(kid, _)| Some(kid) == header.kid.as_ref())
+        else {
+            return Err(TokenError::UnknownSigningKey);
+        };
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[&self.issuer]);
+
+        jsonwebtoken::decode(token, key, &validation)
+            .map(|data| data.claims)
+            .map_err(TokenError::InvalidToken)
+    }
+
+    /// Resolves a service access token into the calling service.
+    pub fn verify_service(&self, token: &str) -> Result<ServiceIdentity, TokenError> {
+        self.verify(token)?
+            .service()
+            .ok_or(TokenError::NotAServiceToken)
+    }
+
+    fn header(&self) -> Header {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.jwk.kid.clone());
+        header
+    }
+
+    fn sign(&self, claims: AccessClaims) -> Result<AccessToken, TokenError> {
+        let token = jsonwebtoken::encode(&self.header(), &claims, &self.encoding_key)
+            .map_err(TokenError::FailedToSign)?;
+
+        Ok(AccessToken {
+            token,
+            expires_in: self.ttl,
+        })
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: std::iter::once(&self.jwk)
+                .chain(&self.previous)
+                .chain(self.id_token_key.as_ref().map(|(_, jwk)| jwk))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::pkcs8::EncodePrivateKey;
+    use ed25519_dalek::pkcs8::spki::der::pem::LineEnding;
+
+    fn pem(seed: u8) -> String {
+        SigningKey::from_bytes(&[seed; 32])
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_issued_token_verifies_against_jwks() {
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), None, None, "auth".to_string(), 60).unwrap();
+        let (user_id, session_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let access = issuer.issue(user_id, session_id).unwrap();
+        let header = jsonwebtoken::decode_header(&access.token).unwrap();
+
+        let jwks = issuer.jwks();
+        let jwk = jwks
+            .keys
+            .iter()
+            .find(|jwk| Some(&jwk.kid) == header.kid.as_ref())
+            .unwrap();
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&["auth"]);
+        let claims = jsonwebtoken::decode::<AccessClaims>(
+            &access.token,
+            &DecodingKey::from_ed_components(jwk.x.as_deref().unwrap()).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+
+        assert_eq!(claims.sub, user_id.to_string());
+        assert_eq!(claims.sid, Some(session_id));
+        assert!(claims.service().is_none());
+        assert_eq!(claims.exp - claims.iat, 60);
+    }
+
+    #[test]
+    fn test_jwks_publishes_previous_key() {
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), Some(&pem(2)), None, "auth".to_string(), 60)
+                .unwrap();
+        let jwks = issuer.jwks();
+
+        assert_eq!(jwks.keys.len(), 2);
+        assert_ne!(jwks.keys[0].kid, jwks.keys[1].kid);
+        assert_eq!(jwks.keys[0].kid, issuer.jwk.kid);
+    }
+
+    #[test]
+    fn test_service_token_round_trip() {
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), None, None, "auth".to_string(), 60).unwrap();
+        let account = ServiceAccount {
+            id: Uuid::new_v4(),
+            client_id: "svc_test".to_string(),
+            name: "test".to_string(),
+            scopes: vec!["users:read".to_string(), "keys:resolve".to_string()],
+        };
+
+        let access = issuer.issue_for_service(&account, &account.scopes).unwrap();
+        let service = issuer.verify(&access.token).unwrap().service().unwrap();
+
+        assert_eq!(service.client_id, account.client_id);
+        assert_eq!(service.scopes, account.scopes);
+    }
+
+    #[test]
+    fn test_client_token_is_not_a_service_token() {
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), None, None, "auth".to_string(), 60).unwrap();
+        let scopes = vec!["profile".to_string()];
+
+        let access = issuer
+            .issue_for_client(Uuid::new_v4(), Uuid::new_v4(), "app_test", &scopes)
+            .unwrap();
+        let claims = issuer.verify(&access.token).unwrap();
+
+        assert_eq!(claims.client_id.as_deref(), Some("app_test"));
+        assert_eq!(claims.scope.as_deref(), Some("profile"));
+        assert!(claims.service().is_none());
+        assert!(issuer.verify_service(&access.token).is_err());
+    }
+
+    #[test]
+    fn test_id_token_carries_audience_and_nonce() {
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), None, None, "auth".to_string(), 60).unwrap();
+        let user = User {
+            email: "writer@example.com".to_string(),
+            ..Default::default()
+        };
+        let scopes = vec!["openid".to_string(), "email".to_string()];
+
+        let id_token = issuer
+            .issue_id_token(&user, "app_test", 1, Some("n-0S6".to_string()), &scopes)
+            .unwrap();
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&["app_test"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(
+            &id_token,
+            &DecodingKey::from_ed_components(issuer.jwk.x.as_deref().unwrap()).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+
+        assert_eq!(claims.nonce.as_deref(), Some("n-0S6"));
+        assert_eq!(claims.user.email.as_deref(), Some("writer@example.com"));
+        assert!(claims.user.preferred_username.is_none());
+        assert!(issuer.verify(&id_token).is_err());
+    }
+
+    #[test]
+    fn test_id_token_is_signed_with_rsa_key() {
+        let rsa_pem = include_str!("../tests/fixtures/id_token_key.pem");
+        let issuer =
+            AccessTokenIssuer::from_pem(&pem(1), None, Some(rsa_pem), "auth".to_string(), 60)
+                .unwrap();
+        assert_eq!(issuer.id_token_algorithm(), "RS256");
+
+        let id_token = issuer
+            .issue_id_token(&User::default(), "app_test", 1, None, &[])
+            .unwrap();
+        let header = jsonwebtoken::decode_header(&id_token).unwrap();
+        assert_eq!(header.alg, Algorithm::RS256);
+
+        let jwks = issuer.jwks();
+        let jwk = jwks
+            .keys
+            .iter()
+            .find(|jwk| Some(&jwk.kid) == header.kid.as_ref())
+            .unwrap();
+        assert_eq!(jwk.kty, "RSA");
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&["app_test"]);
+        let key =
+            DecodingKey::from_rsa_components(jwk.n.as_deref().unwrap(), jwk.e.as_deref().unwrap())
+                .unwrap();
+        assert!(jsonwebtoken::decode::<IdTokenClaims>(&id_token, &key, &validation).is_ok());
+
+        // Access tokens keep using the EdDSA key.
+        let access = issuer.issue(Uuid::new_v4(), Uuid::new_v4()).unwrap();
+        assert!(issuer.verify(&access.token).is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_foreign_and_rotated_out_keys() {
+        let old = AccessTokenIssuer::from_pem(&pem(1), None, None, "auth".to_string(), 60).unwrap();
+        let rotated =
+            AccessTokenIssuer::from_pem(&pem(2), Some(&pem(1)), None, "auth".to_string(), 60)
+                .unwrap();
+        let foreign =
+            AccessTokenIssuer::from_pem(&pem(3), None, None, "auth".to_string(), 60).unwrap();
+
+        let token = old.issue(Uuid::new_v4(), Uuid::new_v4()).unwrap().token;
+
+        assert!(rotated.verify(&token).is_ok());
+        assert!(matches!(
+            foreign.verify(&token),
+            Err(TokenError::UnknownSigningKey)
+        ));
+    }
+}
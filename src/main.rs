@@ -1,12 +1,17 @@
 mod app;
+mod cli;
 mod config;
 mod delivery_grpc;
 mod delivery_http;
 mod errors;
+mod external_idp;
 mod handlers;
 mod infra;
+mod jwt;
 mod model;
+mod oidc;
 mod repo;
+mod tokens;
 mod usecase;
 
 use crate::app::{AuthApp, init_router, serve};
@@ -17,6 +22,12 @@ use std::sync::Arc;
 async fn main() {
     let config = AppConfig::new();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        cli::run(config, args).await;
+        return;
+    }
+
     let (http_app, grpc_router) = AuthApp::new(config.clone()).await;
     let router = init_router(Arc::new(http_app));
 
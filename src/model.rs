@@ -1,6 +1,9 @@
+use crate::config::SessionConfig;
 use crate::delivery_http::dto::UpdateUserRequest;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, Local, Utc};
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Default, FromRow)]
 pub struct User {
@@ -8,6 +11,7 @@ pub struct User {
     pub email: String,
     pub username: String,
     pub password_hash: String,
+    pub email_verified_at: Option<DateTime<Local>>,
     pub created_at: DateTime<Local>,
     pub updated_at: DateTime<Local>,
 }
@@ -19,8 +23,318 @@ impl From<UpdateUserRequest> for User {
             email: "".to_string(),
             username: value.new_username,
             password_hash: "".to_string(),
+            email_verified_at: None,
             created_at: Default::default(),
             updated_at: Default::default(),
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    Password,
+    Registration,
+    /// Signed in through an external identity provider.
+    External,
+    MagicLink,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthMethod::Password => "password",
+            AuthMethod::Registration => "registration",
+            AuthMethod::External => "external",
+            AuthMethod::MagicLink => "magic_link",
+        }
+    }
+}
+
+impl TryFrom<String> for AuthMethod {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "password" => Ok(AuthMethod::Password),
+            "registration" => Ok(AuthMethod::Registration),
+            "external" => Ok(AuthMethod::External),
+            "magic_link" => Ok(AuthMethod::MagicLink),
+            _ => Err(format!("unknown auth method {value}")),
+        }
+    }
+}
+
+/// Transient sessions end with the browser session and expire when idle,
+/// persistent ones are issued for "remember me" logins and only have an absolute lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionKind {
+    #[default]
+    Transient,
+    Persistent,
+}
+
+impl SessionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionKind::Transient => "transient",
+            SessionKind::Persistent => "persistent",
+        }
+    }
+}
+
+impl TryFrom<String> for SessionKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "transient" => Ok(SessionKind::Transient),
+            "persistent" => Ok(SessionKind::Persistent),
+            _ => Err(format!("unknown session kind {value}")),
+        }
+    }
+}
+
+/// Client details captured when a session is created.
+#[derive(Debug, Clone)]
+pub struct SessionMeta {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub auth_method: AuthMethod,
+    pub kind: SessionKind,
+    /// Set for a token family, see [`SessionMeta::token_family`].
+    pub parent_id: Option<Uuid>,
+    /// The OAuth client a token family was granted to.
+    pub client_id: Option<String>,
+}
+
+impl SessionMeta {
+    /// A session of its own for access and refresh tokens issued on behalf of `session`.
+    /// Revoking the tokens ends this family and leaves `session` alone, while the family
+    /// only lives as long as `session` does. Its token is never handed out.
+    pub fn token_family(session: &Session) -> Self {
+        SessionMeta {
+            ip: session.ip.clone(),
+            user_agent: session.user_agent.clone(),
+            auth_method: session.auth_method,
+            kind: session.kind,
+            parent_id: Some(session.id),
+            client_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    #[sqlx(try_from = "String")]
+    pub auth_method: AuthMethod,
+    #[serde(default)]
+    #[sqlx(try_from = "String")]
+    pub kind: SessionKind,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The session a token family was issued from.
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+impl Session {
+    pub fn new(user_id: Uuid, meta: SessionMeta, config: &SessionConfig) -> Self {
+        let now = Utc::now();
+        let lifetime = match meta.kind {
+            SessionKind::Transient => config.absolute_lifetime,
+            SessionKind::Persistent => config.remember_me_lifetime,
+        };
+
+        Session {
+            id: Uuid::new_v4(),
+            user_id,
+            ip: meta.ip,
+            user_agent: meta.user_agent,
+            auth_method: meta.auth_method,
+            kind: meta.kind,
+            created_at: now,
+            last_seen_at: now,
+            expires_at: now + Duration::seconds(lifetime as i64),
+            parent_id: meta.parent_id,
+            client_id: meta.client_id,
+        }
+    }
+
+    /// Seconds until the session expires if it is not used again.
+    /// Zero means the session is already dead.
+    pub fn ttl(&self, config: &SessionConfig, now: DateTime<Utc>) -> u64 {
+        let remaining = (self.expires_at - now).num_seconds().max(0) as u64;
+
+        match self.kind {
+            SessionKind::Transient => remaining.min(config.idle_timeout),
+            SessionKind::Persistent => remaining,
+        }
+    }
+}
+
+/// A freshly issued session together with its bearer token.
+/// The token is handed to the client once and never stored.
+#[derive(Debug, Clone)]
+pub struct IssuedSession {
+    pub token: String,
+    pub session: Session,
+}
+
+/// The next refresh token of a session's chain and the session it belongs to.
+#[derive(Debug, Clone)]
+pub struct RotatedRefreshToken {
+    pub token: String,
+    pub session: Session,
+}
+
+/// A personal API key. Only the keyed hash of the key is stored,
+/// `prefix` stays in clear so users can tell their keys apart.
+#[derive(Debug, Clone, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A freshly created API key, the key itself is shown to the user once.
+#[derive(Debug, Clone)]
+pub struct IssuedApiKey {
+    pub key: String,
+    pub api_key: ApiKey,
+}
+
+/// A non-human caller authenticating with the client credentials grant.
+#[derive(Debug, Clone, FromRow)]
+pub struct ServiceAccount {
+    pub id: Uuid,
+    pub client_id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+/// A freshly created service account, the secret is shown once.
+#[derive(Debug, Clone)]
+pub struct IssuedServiceAccount {
+    pub client_secret: String,
+    pub account: ServiceAccount,
+}
+
+/// The service behind a request, taken from a verified service access token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceIdentity {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+}
+
+/// A web application signing users in through the authorization code grant.
+/// Public clients have no secret and rely on PKCE alone.
+#[derive(Debug, Clone, FromRow)]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub confidential: bool,
+}
+
+/// A freshly registered client, the secret of a confidential one is shown once.
+#[derive(Debug, Clone)]
+pub struct IssuedOAuthClient {
+    pub client_secret: Option<String>,
+    pub client: OAuthClient,
+}
+
+/// What an authorization code stands for. The code itself is only stored hashed.
+///
+/// Tokens issued for the code are bound to the browser session the user approved
+/// the request from, so logging out there also cuts off the application.
+#[derive(Debug, Clone, FromRow)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub user_id: Uuid,
+    pub session_id: Uuid,
+    pub redirect_uri: String,
+    /// The authorization request named `redirect_uri` rather than relying on the only
+    /// registered one, the token request then has to repeat it (RFC 6749 section 4.1.3).
+    pub redirect_uri_sent: bool,
+    pub scopes: Vec<String>,
+    pub code_challenge: String,
+    /// OpenID Connect nonce, echoed in the id_token.
+    pub nonce: Option<String>,
+}
+
+/// Scopes a user has approved for a client, so they are not asked again.
+#[derive(Debug, Clone, FromRow)]
+pub struct OAuthConsent {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    pub granted_at: DateTime<Utc>,
+}
+
+/// What a token sent by email may be redeemed for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailTokenPurpose {
+    MagicLink,
+    EmailVerification,
+    PasswordReset,
+    /// Sent to the new address, confirms a change of email.
+    EmailChange,
+    /// Sent to the old address after a change, undoes it.
+    EmailRevert,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailTokenPurpose::MagicLink => "magic_link",
+            EmailTokenPurpose::EmailVerification => "email_verification",
+            EmailTokenPurpose::PasswordReset => "password_reset",
+            EmailTokenPurpose::EmailChange => "email_change",
+            EmailTokenPurpose::EmailRevert => "email_revert",
+        }
+    }
+}
+
+/// A single-use token to be sent to `email`.
+#[derive(Debug, Clone)]
+pub struct NewEmailToken {
+    pub purpose: EmailTokenPurpose,
+    pub user_id: Uuid,
+    pub email: String,
+    /// Secret kept by the browser that asked for the token, it has to be presented
+    /// along with the token.
+    pub browser_secret: Option<String>,
+    /// Seconds the token stays valid.
+    pub ttl: u64,
+}
+
+/// What a redeemed email token stands for.
+#[derive(Debug, Clone, FromRow)]
+pub struct EmailToken {
+    pub user_id: Uuid,
+    /// The address the token was sent to.
+    pub email: String,
+}
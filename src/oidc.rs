@@ -0,0 +1,138 @@
+use crate::model::User;
+use serde::{Deserialize, Serialize};
+
+pub const OPENID_SCOPE: &str = "openid";
+pub const PROFILE_SCOPE: &str = "profile";
+pub const EMAIL_SCOPE: &str = "email";
+
+/// Standard claims about the user, each released only when its scope was granted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+}
+
+impl UserInfo {
+    pub fn new(user: &User, scopes: &[String]) -> Self {
+        let granted = |scope: &str| scopes.iter().any(|granted| granted == scope);
+
+        UserInfo {
+            sub: user.id.to_string(),
+            email: granted(EMAIL_SCOPE).then(|| user.email.clone()),
+            email_verified: granted(EMAIL_SCOPE).then_some(user.email_verified_at.is_some()),
+            preferred_username: granted(PROFILE_SCOPE).then(|| user.username.clone()),
+        }
+    }
+}
+
+/// Claims of an id_token issued to a client alongside its access token.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// When the user actually logged in, that is when the session began.
+    pub auth_time: i64,
+    /// Echoed from the authorization request so the client can detect replays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserInfo,
+}
+
+/// OpenID Connect discovery document (OpenID Connect Discovery 1.0, section 3).
+#[derive(Debug, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub scopes_supported: [&'static str; 3],
+    pub response_types_supported: [&'static str; 1],
+    pub grant_types_supported: [&'static str; 2],
+    pub subject_types_supported: [&'static str; 1],
+    pub id_token_signing_alg_values_supported: [&'static str; 1],
+    pub token_endpoint_auth_methods_supported: [&'static str; 3],
+    pub code_challenge_methods_supported: [&'static str; 1],
+    pub claims_supported: [&'static str; 10],
+}
+
+impl ProviderMetadata {
+    /// `id_token_algorithm` is the one alg id_tokens are signed with.
+    pub fn new(issuer: &str, id_token_algorithm: &'static str) -> Self {
+        let base = issuer.trim_end_matches('/');
+        let endpoint = |path: &str| format!("{base}{path}");
+
+        ProviderMetadata {
+            issuer: issuer.to_string(),
+            authorization_endpoint: endpoint("/oauth/authorize"),
+            token_endpoint: endpoint("/oauth/token"),
+            userinfo_endpoint: endpoint("/userinfo"),
+            jwks_uri: endpoint("/.well-known/jwks.json"),
+            introspection_endpoint: endpoint("/oauth/introspect"),
+            revocation_endpoint: endpoint("/oauth/revoke"),
+            scopes_supported: [OPENID_SCOPE, PROFILE_SCOPE, EMAIL_SCOPE],
+            response_types_supported: ["code"],
+            grant_types_supported: ["authorization_code", "client_credentials"],
+            subject_types_supported: ["public"],
+            id_token_signing_alg_values_supported: [id_token_algorithm],
+            token_endpoint_auth_methods_supported: [
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ],
+            code_challenge_methods_supported: ["S256"],
+            claims_supported: [
+                "iss",
+                "sub",
+                "aud",
+                "iat",
+                "exp",
+                "auth_time",
+                "nonce",
+                "email",
+                "email_verified",
+                "preferred_username",
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_info_releases_claims_per_scope() {
+        let user = User {
+            email: "writer@example.com".to_string(),
+            username: "writer".to_string(),
+            ..Default::default()
+        };
+
+        let openid = UserInfo::new(&user, &[OPENID_SCOPE.to_string()]);
+        assert_eq!(openid.sub, user.id.to_string());
+        assert!(openid.email.is_none() && openid.preferred_username.is_none());
+
+        let full = UserInfo::new(
+            &user,
+            &[
+                OPENID_SCOPE.to_string(),
+                EMAIL_SCOPE.to_string(),
+                PROFILE_SCOPE.to_string(),
+            ],
+        );
+        assert_eq!(full.email.as_deref(), Some("writer@example.com"));
+        assert_eq!(full.email_verified, Some(false));
+        assert_eq!(full.preferred_username.as_deref(), Some("writer"));
+    }
+}
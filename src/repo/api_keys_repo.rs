@@ -0,0 +1,105 @@
+use crate::delivery_grpc::users_delivery::IApiKeyResolver;
+use crate::delivery_http::users_delivery::IApiKeysRepo;
+use crate::errors::DBError;
+use crate::errors::DBError::FailedToQueryApiKeys;
+use crate::infra::postgres::PGPool;
+use crate::model::{ApiKey, IssuedApiKey, NewApiKey};
+use crate::tokens::{TokenHasher, generate_api_key};
+use async_trait::async_trait;
+use uuid::Uuid;
+
+const API_KEY_COLUMNS: &str =
+    "id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at";
+
+pub struct ApiKeysRepo {
+    pub repo: PGPool,
+    hasher: TokenHasher,
+}
+
+impl ApiKeysRepo {
+    pub fn new(repo: PGPool, hasher: TokenHasher) -> Self {
+        ApiKeysRepo { repo, hasher }
+    }
+}
+
+#[async_trait]
+impl IApiKeysRepo for ApiKeysRepo {
+    async fn create_api_key(
+        &self,
+        user_id: Uuid,
+        new_key: NewApiKey,
+    ) -> Result<IssuedApiKey, DBError> {
+        let (prefix, key) = generate_api_key();
+
+        let api_key = sqlx::query_as(&format!(
+            r"insert into api_keys (id, user_id, name, prefix, key_hash, scopes, expires_at)
+            values ($1, $2, $3, $4, $5, $6, $7)
+            returning {API_KEY_COLUMNS};"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(new_key.name)
+        .bind(prefix)
+        .bind(self.hasher.hash(&key))
+        .bind(new_key.scopes)
+        .bind(new_key.expires_at)
+        .fetch_one(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryApiKeys)?;
+
+        Ok(IssuedApiKey { key, api_key })
+    }
+
+    async fn list_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DBError> {
+        let keys = sqlx::query_as(&format!(
+            r"select {API_KEY_COLUMNS}
+            from api_keys
+            where user_id = $1
+            order by created_at desc;"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryApiKeys)?;
+
+        Ok(keys)
+    }
+
+    async fn delete_api_key(&self, user_id: Uuid, key_id: Uuid) -> Result<bool, DBError> {
+        let res = sqlx::query(r"delete from api_keys where user_id = $1 and id = $2;")
+            .bind(user_id)
+            .bind(key_id)
+            .execute(&self.repo.pool)
+            .await
+            .map_err(FailedToQueryApiKeys)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn revoke_api_keys(&self, user_id: Uuid) -> Result<usize, DBError> {
+        let res = sqlx::query(r"delete from api_keys where user_id = $1;")
+            .bind(user_id)
+            .execute(&self.repo.pool)
+            .await
+            .map_err(FailedToQueryApiKeys)?;
+
+        Ok(res.rows_affected() as usize)
+    }
+}
+
+#[async_trait]
+impl IApiKeyResolver for ApiKeysRepo {
+    async fn resolve_api_key(&self, key: &str) -> Result<Option<ApiKey>, DBError> {
+        let api_key = sqlx::query_as(&format!(
+            r"update api_keys set last_used_at = now()
+            where key_hash = $1 and (expires_at is null or expires_at > now())
+            returning {API_KEY_COLUMNS};"
+        ))
+        .bind(self.hasher.hash(key))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryApiKeys)?;
+
+        Ok(api_key)
+    }
+}
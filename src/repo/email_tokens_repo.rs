@@ -0,0 +1,193 @@
+use crate::errors::DBError;
+use crate::errors::DBError::FailedToQueryEmailTokens;
+use crate::infra::postgres::PGPool;
+use crate::model::{EmailToken, EmailTokenPurpose, NewEmailToken};
+use crate::tokens::{TokenHasher, generate_token};
+use crate::usecase::email_links::IEmailTokensRepo;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+pub struct EmailTokensRepo {
+    pub repo: PGPool,
+    hasher: TokenHasher,
+}
+
+impl EmailTokensRepo {
+    pub fn new(repo: PGPool, hasher: TokenHasher) -> Self {
+        EmailTokensRepo { repo, hasher }
+    }
+}
+
+#[async_trait]
+impl IEmailTokensRepo for EmailTokensRepo {
+    async fn issue_email_token(
+        &self,
+        new_token: &NewEmailToken,
+        min_interval: u64,
+    ) -> Result<Option<String>, DBError> {
+        let token = generate_token();
+
+        sqlx::query(r"delete from email_tokens where expires_at < now();")
+            .execute(&self.repo.pool)
+            .await
+            .map_err(FailedToQueryEmailTokens)?;
+
+        let res = sqlx::query(
+            r"insert into email_tokens
+                (token_hash, purpose, user_id, email, browser_secret_hash, expires_at)
+            select $1, $2, $3, $4, $5, $6
+            where not exists (
+                select 1 from email_tokens
+                where user_id = $3 and purpose = $2 and created_at > $7
+            );",
+        )
+        .bind(self.hasher.hash(&token))
+        .bind(new_token.purpose.as_str())
+        .bind(new_token.user_id)
+        .bind(&new_token.email)
+        .bind(
+            new_token
+                .browser_secret
+                .as_deref()
+                .map(|secret| self.hasher.hash(secret)),
+        )
+        .bind(Utc::now() + Duration::seconds(new_token.ttl as i64))
+        .bind(Utc::now() - Duration::seconds(min_interval as i64))
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryEmailTokens)?;
+
+        Ok((res.rows_affected() == 1).then_some(token))
+    }
+
+    async fn redeem_email_token(
+        &self,
+        purpose: EmailTokenPurpose,
+        token: &str,
+        browser_secret: Option<&str>,
+    ) -> Result<Option<EmailToken>, DBError> {
+        let redeemed = sqlx::query_as(
+            r"update email_tokens set used_at = now()
+            where token_hash = $1 and purpose = $2 and used_at is null and expires_at > now()
+                and browser_secret_hash is not distinct from $3
+            returning user_id, email;",
+        )
+        .bind(self.hasher.hash(token))
+        .bind(purpose.as_str())
+        .bind(browser_secret.map(|secret| self.hasher.hash(secret)))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryEmailTokens)?;
+
+        Ok(redeemed)
+    }
+
+    async fn revoke_email_tokens(
+        &self,
+        user_id: Uuid,
+        purpose: EmailTokenPurpose,
+    ) -> Result<(), DBError> {
+        // Marked as used rather than deleted so they still count towards the throttle.
+        sqlx::query(
+            r"update email_tokens set used_at = now()
+            where user_id = $1 and purpose = $2 and used_at is null;",
+        )
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryEmailTokens)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery_http::users_delivery::IUsersRepo;
+    use crate::model::User;
+    use crate::repo::users_repo::UsersRepo;
+    use crate::usecase::users_usecase::IUsersRepository;
+
+    fn magic_link(user_id: Uuid, browser_secret: &str, ttl: u64) -> NewEmailToken {
+        NewEmailToken {
+            purpose: EmailTokenPurpose::MagicLink,
+            user_id,
+            email: "writer@example.com".to_string(),
+            browser_secret: Some(browser_secret.to_string()),
+            ttl,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn test_magic_link_tokens() {
+        let pool = PGPool::new(std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let users = UsersRepo::new(pool.clone());
+        let repo = EmailTokensRepo::new(pool, TokenHasher::new("test-key"));
+        let user_id = Uuid::new_v4();
+        users
+            .create_user(User {
+                id: user_id,
+                email: format!("magic-{}@example.com", user_id.simple()),
+                username: "magic".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let redeem = |token: String, secret: Option<&'static str>| {
+            let repo = &repo;
+            async move {
+                repo.redeem_email_token(EmailTokenPurpose::MagicLink, &token, secret)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let token = repo
+            .issue_email_token(&magic_link(user_id, "s3cret", 60), 60)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Throttled while the previous link is recent.
+        assert!(
+            repo.issue_email_token(&magic_link(user_id, "s3cret", 60), 60)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // A wrong or missing secret leaves the token usable.
+        assert!(redeem(token.clone(), Some("other")).await.is_none());
+        assert!(redeem(token.clone(), None).await.is_none());
+
+        let redeemed = redeem(token.clone(), Some("s3cret")).await.unwrap();
+        assert_eq!(redeemed.user_id, user_id);
+        assert!(redeem(token, Some("s3cret")).await.is_none());
+
+        let expiring = repo
+            .issue_email_token(&magic_link(user_id, "s3cret", 1), 0)
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        assert!(redeem(expiring, Some("s3cret")).await.is_none());
+
+        let revoked = repo
+            .issue_email_token(&magic_link(user_id, "s3cret", 60), 0)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.revoke_email_tokens(user_id, EmailTokenPurpose::MagicLink)
+            .await
+            .unwrap();
+        assert!(redeem(revoked, Some("s3cret")).await.is_none());
+
+        users.delete_user(user_id).await.unwrap();
+    }
+}
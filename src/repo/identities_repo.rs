@@ -0,0 +1,78 @@
+use crate::delivery_http::external_login_delivery::IIdentitiesRepo;
+use crate::errors::DBError;
+use crate::errors::DBError::FailedToQueryIdentities;
+use crate::external_idp::ExternalIdentity;
+use crate::infra::postgres::PGPool;
+use async_trait::async_trait;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+pub struct IdentitiesRepo {
+    pub repo: PGPool,
+}
+
+impl IdentitiesRepo {
+    pub fn new(repo: PGPool) -> Self {
+        IdentitiesRepo { repo }
+    }
+}
+
+#[async_trait]
+impl IIdentitiesRepo for IdentitiesRepo {
+    async fn find_user(&self, provider: &str, subject: &str) -> Result<Option<Uuid>, DBError> {
+        let user_id = sqlx::query_scalar(
+            r"select user_id from identities
+            where provider = $1 and subject = $2;",
+        )
+        .bind(provider)
+        .bind(subject)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryIdentities)?;
+
+        Ok(user_id)
+    }
+
+    async fn link_identity(
+        &self,
+        user_id: Uuid,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<(), DBError> {
+        insert_identity(&self.repo.pool, user_id, provider, identity).await
+    }
+}
+
+/// Shared with the sign-up of external users, which links inside its own transaction.
+pub(crate) async fn insert_identity(
+    executor: impl PgExecutor<'_>,
+    user_id: Uuid,
+    provider: &str,
+    identity: &ExternalIdentity,
+) -> Result<(), DBError> {
+    let res = sqlx::query(
+        r"insert into identities (id, user_id, provider, subject, email)
+        values ($1, $2, $3, $4, $5);",
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(provider)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(executor)
+    .await;
+
+    match res {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            if let Some(db_err) = e.as_database_error()
+                && let Some(code) = db_err.code()
+                && code == "23505"
+            {
+                return Err(DBError::IdentityAlreadyLinked);
+            }
+
+            Err(FailedToQueryIdentities(e))
+        }
+    }
+}
@@ -0,0 +1,649 @@
+use crate::config::SessionConfig;
+use crate::delivery_grpc::users_delivery::IUserIDGetter;
+use crate::delivery_http::users_delivery::ISessionStore;
+use crate::errors::DBError;
+use crate::errors::DBError::{RefreshTokenReused, SessionNotFound};
+use crate::model::{IssuedSession, RotatedRefreshToken, Session, SessionMeta};
+use crate::tokens::{TokenHasher, generate_token};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+struct Entry {
+    session: Session,
+    expires_at: DateTime<Utc>,
+}
+
+impl Entry {
+    fn is_alive(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at > now
+    }
+}
+
+struct RefreshEntry {
+    token_hash: String,
+    used: bool,
+}
+
+/// Process-local session store for development and tests.
+/// Mirrors the Redis layout: entries are keyed by token hash and expire after their TTL.
+pub struct MemorySessionsRepo {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Keyed by refresh token hash. Always locked after `entries`.
+    refresh_tokens: Mutex<HashMap<String, RefreshEntry>>,
+    config: SessionConfig,
+    hasher: TokenHasher,
+}
+
+impl MemorySessionsRepo {
+    pub fn new(config: SessionConfig, hasher: TokenHasher) -> Self {
+        MemorySessionsRepo {
+            entries: Mutex::new(HashMap::new()),
+            refresh_tokens: Mutex::new(HashMap::new()),
+            config,
+            hasher,
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        // A panic while holding the lock cannot leave the map half-updated.
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn refresh_tokens(&self) -> std::sync::MutexGuard<'_, HashMap<String, RefreshEntry>> {
+        self.refresh_tokens
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Drops expired sessions along with their token families and refresh chains,
+    /// so the maps only grow with the live sessions of a long running process.
+    fn purge(&self, entries: &mut HashMap<String, Entry>) {
+        let now = Utc::now();
+        entries.retain(|_, entry| entry.is_alive(now));
+
+        let ids: HashSet<Uuid> = entries.values().map(|entry| entry.session.id).collect();
+        entries.retain(|_, entry| entry.session.parent_id.is_none_or(|id| ids.contains(&id)));
+
+        self.refresh_tokens()
+            .retain(|_, refresh| entries.contains_key(&refresh.token_hash));
+    }
+
+    /// Whether the session a token family was issued from is still alive.
+    fn parent_alive(&self, entries: &HashMap<String, Entry>, session: &Session) -> bool {
+        let Some(parent_id) = session.parent_id else {
+            return true;
+        };
+        let now = Utc::now();
+
+        entries.values().any(|entry| {
+            entry.session.id == parent_id
+                && entry.is_alive(now)
+                && entry.session.ttl(&self.config, now) > 0
+        })
+    }
+
+    /// Loads a live session and slides its expiry.
+    fn touch(&self, entries: &mut HashMap<String, Entry>, key: &str) -> Option<Session> {
+        let now = Utc::now();
+        let entry = entries.get_mut(key)?;
+
+        let ttl = entry.session.ttl(&self.config, now);
+        if !entry.is_alive(now) || ttl == 0 {
+            entries.remove(key);
+            return None;
+        }
+
+        entry.session.last_seen_at = now;
+        entry.expires_at = now + Duration::seconds(ttl as i64);
+
+        Some(entry.session.clone())
+    }
+
+    fn insert_refresh_token(
+        &self,
+        entries: &HashMap<String, Entry>,
+        refresh_tokens: &mut HashMap<String, RefreshEntry>,
+        token_hash: &str,
+    ) -> String {
+        // Chains die with their session, drop them lazily.
+        refresh_tokens.retain(|_, refresh| entries.contains_key(&refresh.token_hash));
+
+        let refresh_token = generate_token();
+        refresh_tokens.insert(
+            self.hasher.hash(&refresh_token),
+            RefreshEntry {
+                token_hash: token_hash.to_string(),
+                used: false,
+            },
+        );
+
+        refresh_token
+    }
+
+    fn insert(&self, entries: &mut HashMap<String, Entry>, session: Session) -> IssuedSession {
+        let token = generate_token();
+        let now = Utc::now();
+        let ttl = session.ttl(&self.config, now);
+
+        entries.insert(
+            self.hasher.hash(&token),
+            Entry {
+                session: session.clone(),
+                expires_at: now + Duration::seconds(ttl as i64),
+            },
+        );
+
+        IssuedSession { token, session }
+    }
+}
+
+#[async_trait]
+impl ISessionStore for MemorySessionsRepo {
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        meta: SessionMeta,
+    ) -> Result<IssuedSession, DBError> {
+        let session = Session::new(user_id, meta, &self.config);
+        let mut entries = self.entries();
+        self.purge(&mut entries);
+
+        Ok(self.insert(&mut entries, session))
+    }
+
+    async fn remove_session(&self, token: &str) -> Result<(), DBError> {
+        let now = Utc::now();
+
+        match self.entries().remove(&self.hasher.hash(token)) {
+            Some(entry) if entry.is_alive(now) => Ok(()),
+            _ => Err(SessionNotFound),
+        }
+    }
+
+    async fn find_session(&self, token: &str) -> Result<Option<Session>, DBError> {
+        let now = Utc::now();
+        let session = self
+            .entries()
+            .get(&self.hasher.hash(token))
+            .filter(|entry| entry.is_alive(now) && entry.session.ttl(&self.config, now) > 0)
+            .map(|entry| entry.session.clone());
+
+        Ok(session)
+    }
+
+    async fn get_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, DBError> {
+        let now = Utc::now();
+        let entries = self.entries();
+        let session = entries
+            .values()
+            .find(|entry| entry.session.user_id == user_id && entry.session.id == session_id)
+            .filter(|entry| entry.is_alive(now) && entry.session.ttl(&self.config, now) > 0)
+            .map(|entry| entry.session.clone())
+            .filter(|session| self.parent_alive(&entries, session));
+
+        Ok(session)
+    }
+
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DBError> {
+        let mut entries = self.entries();
+        self.purge(&mut entries);
+
+        let mut sessions: Vec<Session> = entries
+            .values()
+            .filter(|entry| entry.session.user_id == user_id)
+            .map(|entry| entry.session.clone())
+            .collect();
+
+        sessions.sort_by_key(|session| Reverse(session.last_seen_at));
+
+        Ok(sessions)
+    }
+
+    async fn remove_user_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool, DBError> {
+        let mut entries = self.entries();
+        self.purge(&mut entries);
+
+        let before = entries.len();
+        entries.retain(|_, entry| {
+            !(entry.session.user_id == user_id && entry.session.id == session_id)
+        });
+
+        Ok(before != entries.len())
+    }
+
+    async fn rotate_session(&self, token: &str) -> Result<Option<IssuedSession>, DBError> {
+        let now = Utc::now();
+        let mut entries = self.entries();
+
+        let Some(entry) = entries.remove(&self.hasher.hash(token)) else {
+            return Ok(None);
+        };
+
+        if !entry.is_alive(now) || entry.session.ttl(&self.config, now) == 0 {
+            return Ok(None);
+        }
+
+        let mut session = entry.session;
+        session.id = Uuid::new_v4();
+
+        Ok(Some(self.insert(&mut entries, session)))
+    }
+
+    async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<usize, DBError> {
+        let mut entries = self.entries();
+        self.purge(&mut entries);
+
+        let before = entries.len();
+        entries.retain(|_, entry| entry.session.user_id != user_id);
+
+        Ok(before - entries.len())
+    }
+
+    async fn revoke_other_sessions(&self, user_id: Uuid, keep: Uuid) -> Result<usize, DBError> {
+        let mut entries = self.entries();
+        self.purge(&mut entries);
+
+        let before = entries.len();
+        entries.retain(|_, entry| {
+            entry.session.user_id != user_id
+                || entry.session.id == keep
+                || entry.session.parent_id == Some(keep)
+        });
+
+        Ok(before - entries.len())
+    }
+
+    async fn create_refresh_token(&self, session_token: &str) -> Result<String, DBError> {
+        let now = Utc::now();
+        let token_hash = self.hasher.hash(session_token);
+        let entries = self.entries();
+
+        if !entries
+            .get(&token_hash)
+            .is_some_and(|entry| entry.is_alive(now))
+        {
+            return Err(SessionNotFound);
+        }
+
+        let mut refresh_tokens = self.refresh_tokens();
+        Ok(self.insert_refresh_token(&entries, &mut refresh_tokens, &token_hash))
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<Option<RotatedRefreshToken>, DBError> {
+        let mut entries = self.entries();
+        let mut refresh_tokens = self.refresh_tokens();
+
+        let Some(refresh) = refresh_tokens.get_mut(&self.hasher.hash(refresh_token)) else {
+            return Ok(None);
+        };
+
+        let token_hash = refresh.token_hash.clone();
+
+        if refresh.used {
+            entries.remove(&token_hash);
+            return Err(RefreshTokenReused);
+        }
+        refresh.used = true;
+
+        let Some(session) = self.touch(&mut entries, &token_hash) else {
+            return Ok(None);
+        };
+
+        // Using the tokens keeps the session they were issued from alive as well.
+        if let Some(parent_id) = session.parent_id {
+            let parent = entries
+                .iter()
+                .find(|(_, entry)| entry.session.id == parent_id)
+                .map(|(key, _)| key.clone());
+
+            if parent.is_none_or(|key| self.touch(&mut entries, &key).is_none()) {
+                entries.remove(&token_hash);
+                return Ok(None);
+            }
+        }
+
+        let token = self.insert_refresh_token(&entries, &mut refresh_tokens, &token_hash);
+
+        Ok(Some(RotatedRefreshToken { token, session }))
+    }
+
+    async fn refresh_token_session(&self, refresh_token: &str) -> Result<Option<Session>, DBError> {
+        let now = Utc::now();
+        let entries = self.entries();
+        let refresh_tokens = self.refresh_tokens();
+
+        let session = refresh_tokens
+            .get(&self.hasher.hash(refresh_token))
+            .filter(|refresh| !refresh.used)
+            .and_then(|refresh| entries.get(&refresh.token_hash))
+            .filter(|entry| entry.is_alive(now) && entry.session.ttl(&self.config, now) > 0)
+            .map(|entry| entry.session.clone())
+            .filter(|session| self.parent_alive(&entries, session));
+
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl IUserIDGetter for MemorySessionsRepo {
+    async fn get_user(&self, token: &str) -> Result<Option<Session>, DBError> {
+        let mut entries = self.entries();
+        Ok(self.touch(&mut entries, &self.hasher.hash(token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AuthMethod, SessionKind};
+    use crate::repo::session_store_conformance as conformance;
+
+    fn config() -> SessionConfig {
+        SessionConfig {
+            idle_timeout: 60,
+            absolute_lifetime: 3600,
+            remember_me_lifetime: 7200,
+        }
+    }
+
+    fn meta(kind: SessionKind) -> SessionMeta {
+        SessionMeta {
+            ip: Some("127.0.0.1".to_string()),
+            user_agent: Some("test-agent".to_string()),
+            auth_method: AuthMethod::Password,
+            kind,
+            parent_id: None,
+            client_id: None,
+        }
+    }
+
+    fn store() -> MemorySessionsRepo {
+        MemorySessionsRepo::new(config(), TokenHasher::new("test-key"))
+    }
+
+    #[tokio::test]
+    async fn test_create_and_get_session() {
+        let store = store();
+        let user_id = Uuid::new_v4();
+
+        let issued = store
+            .create_session(user_id, meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        let session = store.get_user(&issued.token).await.unwrap().unwrap();
+
+        assert_eq!(session.user_id, user_id);
+        assert_eq!(session.id, issued.session.id);
+        assert_eq!(session.ip.as_deref(), Some("127.0.0.1"));
+        assert!(store.get_user("unknown-token").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_session_does_not_slide_expiry() {
+        let store = store();
+        let issued = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        let key = store.hasher.hash(&issued.token);
+        let expires_at = Utc::now() + Duration::seconds(5);
+        store.entries().get_mut(&key).unwrap().expires_at = expires_at;
+
+        let session = store.find_session(&issued.token).await.unwrap().unwrap();
+        assert_eq!(session.id, issued.session.id);
+        assert_eq!(store.entries()[&key].expires_at, expires_at);
+
+        store.get_user(&issued.token).await.unwrap().unwrap();
+        assert!(store.entries()[&key].expires_at > expires_at);
+        assert!(store.find_session("unknown-token").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_session_is_not_returned() {
+        let store = store();
+        let issued = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        let key = store.hasher.hash(&issued.token);
+        store.entries().get_mut(&key).unwrap().expires_at = Utc::now() - Duration::seconds(1);
+
+        assert!(store.get_user(&issued.token).await.unwrap().is_none());
+        assert!(matches!(
+            store.remove_session(&issued.token).await,
+            Err(SessionNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_remove_user_session() {
+        let store = store();
+        let user_id = Uuid::new_v4();
+
+        let first = store
+            .create_session(user_id, meta(SessionKind::Transient))
+            .await
+            .unwrap();
+        let second = store
+            .create_session(user_id, meta(SessionKind::Persistent))
+            .await
+            .unwrap();
+        store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        assert_eq!(store.list_sessions(user_id).await.unwrap().len(), 2);
+
+        assert!(
+            !store
+                .remove_user_session(Uuid::new_v4(), first.session.id)
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .remove_user_session(user_id, first.session.id)
+                .await
+                .unwrap()
+        );
+
+        assert!(store.get_user(&first.token).await.unwrap().is_none());
+        assert!(store.get_user(&second.token).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_rotate_session_keeps_data() {
+        let store = store();
+        let issued = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Persistent))
+            .await
+            .unwrap();
+
+        let rotated = store.rotate_session(&issued.token).await.unwrap().unwrap();
+
+        assert_ne!(rotated.token, issued.token);
+        assert_ne!(rotated.session.id, issued.session.id);
+        assert_eq!(rotated.session.created_at, issued.session.created_at);
+        assert_eq!(rotated.session.kind, SessionKind::Persistent);
+        assert!(store.get_user(&issued.token).await.unwrap().is_none());
+        assert!(store.get_user(&rotated.token).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_all_sessions() {
+        let store = store();
+        let user_id = Uuid::new_v4();
+        let other = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            store
+                .create_session(user_id, meta(SessionKind::Transient))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(store.revoke_all_sessions(user_id).await.unwrap(), 3);
+        assert!(store.list_sessions(user_id).await.unwrap().is_empty());
+        assert!(store.get_user(&other.token).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_other_sessions() {
+        let store = store();
+        let user_id = Uuid::new_v4();
+        let other_user = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+        let current = store
+            .create_session(user_id, meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            store
+                .create_session(user_id, meta(SessionKind::Transient))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            store
+                .revoke_other_sessions(user_id, current.session.id)
+                .await
+                .unwrap(),
+            2
+        );
+        let left = store.list_sessions(user_id).await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].id, current.session.id);
+        assert!(store.get_user(&other_user.token).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_rotation_and_reuse() {
+        let store = store();
+        let issued = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Persistent))
+            .await
+            .unwrap();
+
+        let first = store.create_refresh_token(&issued.token).await.unwrap();
+        assert!(store.refresh_token_session(&first).await.unwrap().is_some());
+
+        let rotated = store.rotate_refresh_token(&first).await.unwrap().unwrap();
+        assert!(store.refresh_token_session(&first).await.unwrap().is_none());
+
+        assert_ne!(rotated.token, first);
+        assert_eq!(rotated.session.id, issued.session.id);
+
+        assert!(matches!(
+            store.rotate_refresh_token(&first).await,
+            Err(RefreshTokenReused)
+        ));
+        assert!(store.get_user(&issued.token).await.unwrap().is_none());
+        assert!(
+            store
+                .rotate_refresh_token(&rotated.token)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoked_session_ends_refresh_chain() {
+        let store = store();
+        let issued = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        let refresh_token = store.create_refresh_token(&issued.token).await.unwrap();
+        store.remove_session(&issued.token).await.unwrap();
+
+        assert!(
+            store
+                .rotate_refresh_token(&refresh_token)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            store.create_refresh_token(&issued.token).await,
+            Err(SessionNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expired_sessions_are_purged() {
+        let store = store();
+        let expired = store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+        store.create_refresh_token(&expired.token).await.unwrap();
+
+        let key = store.hasher.hash(&expired.token);
+        store.entries().get_mut(&key).unwrap().expires_at = Utc::now() - Duration::seconds(1);
+
+        store
+            .create_session(Uuid::new_v4(), meta(SessionKind::Transient))
+            .await
+            .unwrap();
+
+        assert_eq!(store.entries().len(), 1);
+        assert!(store.refresh_tokens().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_session_by_id() {
+        let store = store();
+        let user_id = Uuid::new_v4();
+        let issued = store
+            .create_session(user_id, meta(SessionKind::Transient))
+            .await
+            .unwrap();
+        let id = issued.session.id;
+
+        assert_eq!(
+            store.get_session(user_id, id).await.unwrap().unwrap().id,
+            id
+        );
+        assert!(
+            store
+                .get_session(Uuid::new_v4(), id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        store.remove_session(&issued.token).await.unwrap();
+        assert!(store.get_session(user_id, id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conformance() {
+        let store = MemorySessionsRepo::new(conformance::config(), TokenHasher::new("test-key"));
+
+        conformance::check_all(&store, || async { Uuid::new_v4() }).await;
+    }
+}
@@ -1,2 +1,11 @@
+pub mod api_keys_repo;
+pub mod email_tokens_repo;
+pub mod identities_repo;
+pub mod memory_sessions;
+pub mod oauth_repo;
+pub mod pg_sessions;
+pub mod service_accounts_repo;
+#[cfg(test)]
+pub mod session_store_conformance;
 pub mod sessions;
 pub mod users_repo;
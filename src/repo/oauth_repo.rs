@@ -0,0 +1,218 @@
+use crate::delivery_http::oauth_delivery::IOAuthRepo;
+use crate::errors::DBError;
+use crate::errors::DBError::{
+    FailedToQueryAuthorizationCodes, FailedToQueryOAuthClients, FailedToQueryOAuthConsents,
+};
+use crate::infra::postgres::PGPool;
+use crate::model::{AuthorizationCode, IssuedOAuthClient, OAuthClient, OAuthConsent};
+use crate::tokens::{TokenHasher, generate_oauth_client_id, generate_token};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+const OAUTH_CLIENT_COLUMNS: &str =
+    "id, client_id, name, redirect_uris, scopes, secret_hash is not null as confidential";
+
+const AUTHORIZATION_CODE_COLUMNS: &str = "client_id, user_id, session_id, redirect_uri, redirect_uri_sent, scopes, code_challenge, nonce";
+
+/// Codes are exchanged right after the redirect, a minute is plenty.
+const AUTHORIZATION_CODE_TTL: i64 = 60;
+
+/// Client secrets and authorization codes are random tokens, stored as keyed hashes.
+pub struct OAuthRepo {
+    pub repo: PGPool,
+    hasher: TokenHasher,
+}
+
+impl OAuthRepo {
+    pub fn new(repo: PGPool, hasher: TokenHasher) -> Self {
+        OAuthRepo { repo, hasher }
+    }
+
+    pub async fn create_client(
+        &self,
+        name: String,
+        redirect_uris: Vec<String>,
+        scopes: Vec<String>,
+        confidential: bool,
+    ) -> Result<IssuedOAuthClient, DBError> {
+        let client_secret = confidential.then(generate_token);
+
+        let client = sqlx::query_as(&format!(
+            r"insert into oauth_clients (id, client_id, secret_hash, name, redirect_uris, scopes)
+            values ($1, $2, $3, $4, $5, $6)
+            returning {OAUTH_CLIENT_COLUMNS};"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(generate_oauth_client_id())
+        .bind(
+            client_secret
+                .as_deref()
+                .map(|secret| self.hasher.hash(secret)),
+        )
+        .bind(name)
+        .bind(redirect_uris)
+        .bind(scopes)
+        .fetch_one(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthClients)?;
+
+        Ok(IssuedOAuthClient {
+            client_secret,
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl IOAuthRepo for OAuthRepo {
+    async fn get_client(&self, client_id: &str) -> Result<Option<OAuthClient>, DBError> {
+        let client = sqlx::query_as(&format!(
+            r"select {OAUTH_CLIENT_COLUMNS}
+            from oauth_clients
+            where client_id = $1;"
+        ))
+        .bind(client_id)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthClients)?;
+
+        Ok(client)
+    }
+
+    async fn authenticate_client<'a>(
+        &self,
+        client_id: &str,
+        client_secret: Option<&'a str>,
+    ) -> Result<Option<OAuthClient>, DBError> {
+        let client = sqlx::query_as(&format!(
+            r"select {OAUTH_CLIENT_COLUMNS}
+            from oauth_clients
+            where client_id = $1 and secret_hash is not distinct from $2;"
+        ))
+        .bind(client_id)
+        .bind(client_secret.map(|secret| self.hasher.hash(secret)))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthClients)?;
+
+        Ok(client)
+    }
+
+    async fn granted_scopes(&self, user_id: Uuid, client_id: &str) -> Result<Vec<String>, DBError> {
+        let scopes: Option<Vec<String>> = sqlx::query_scalar(
+            r"select scopes from oauth_consents where user_id = $1 and client_id = $2;",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthConsents)?;
+
+        Ok(scopes.unwrap_or_default())
+    }
+
+    async fn grant_consent(
+        &self,
+        user_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<(), DBError> {
+        sqlx::query(
+            r"insert into oauth_consents (user_id, client_id, scopes)
+            values ($1, $2, $3)
+            on conflict (user_id, client_id) do update
+            set scopes = array(
+                    select distinct unnest(oauth_consents.scopes || excluded.scopes)
+                ),
+                granted_at = now();",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .bind(scopes)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthConsents)?;
+
+        Ok(())
+    }
+
+    async fn list_consents(&self, user_id: Uuid) -> Result<Vec<OAuthConsent>, DBError> {
+        let consents = sqlx::query_as(
+            r"select c.client_id, cl.name as client_name, c.scopes, c.granted_at
+            from oauth_consents c
+            join oauth_clients cl on cl.client_id = c.client_id
+            where c.user_id = $1
+            order by c.granted_at desc;",
+        )
+        .bind(user_id)
+        .fetch_all(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryOAuthConsents)?;
+
+        Ok(consents)
+    }
+
+    async fn revoke_consent(&self, user_id: Uuid, client_id: &str) -> Result<bool, DBError> {
+        let res = sqlx::query(r"delete from oauth_consents where user_id = $1 and client_id = $2;")
+            .bind(user_id)
+            .bind(client_id)
+            .execute(&self.repo.pool)
+            .await
+            .map_err(FailedToQueryOAuthConsents)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn create_authorization_code(
+        &self,
+        grant: &AuthorizationCode,
+    ) -> Result<String, DBError> {
+        let code = generate_token();
+
+        // Expired codes are useless, dropping them here keeps the table small.
+        sqlx::query(r"delete from oauth_authorization_codes where expires_at < now();")
+            .execute(&self.repo.pool)
+            .await
+            .map_err(FailedToQueryAuthorizationCodes)?;
+
+        sqlx::query(
+            r"insert into oauth_authorization_codes
+                (code_hash, client_id, user_id, session_id, redirect_uri, redirect_uri_sent, scopes,
+                 code_challenge, nonce, expires_at)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);",
+        )
+        .bind(self.hasher.hash(&code))
+        .bind(&grant.client_id)
+        .bind(grant.user_id)
+        .bind(grant.session_id)
+        .bind(&grant.redirect_uri)
+        .bind(grant.redirect_uri_sent)
+        .bind(&grant.scopes)
+        .bind(&grant.code_challenge)
+        .bind(&grant.nonce)
+        .bind(Utc::now() + Duration::seconds(AUTHORIZATION_CODE_TTL))
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryAuthorizationCodes)?;
+
+        Ok(code)
+    }
+
+    async fn redeem_authorization_code(
+        &self,
+        code: &str,
+    ) -> Result<Option<AuthorizationCode>, DBError> {
+        let grant = sqlx::query_as(&format!(
+            r"update oauth_authorization_codes set used_at = now()
+            where code_hash = $1 and used_at is null and expires_at > now()
+            returning {AUTHORIZATION_CODE_COLUMNS};"
+        ))
+        .bind(self.hasher.hash(code))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryAuthorizationCodes)?;
+
+        Ok(grant)
+    }
+}
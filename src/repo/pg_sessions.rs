@@ -0,0 +1,509 @@
+use crate::config::SessionConfig;
+use crate::delivery_grpc::users_delivery::IUserIDGetter;
+use crate::delivery_http::users_delivery::ISessionStore;
+use crate::errors::DBError;
+use crate::errors::DBError::{FailedToQuerySessions, RefreshTokenReused, SessionNotFound};
+use crate::infra::postgres::PGPool;
+use crate::model::{IssuedSession, RotatedRefreshToken, Session, SessionMeta};
+use crate::tokens::{TokenHasher, generate_token};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgExecutor;
+use std::sync::Arc;
+use uuid::Uuid;
+
+const SESSION_COLUMNS: &str = "id, user_id, ip, user_agent, auth_method, kind, created_at, \
+    last_seen_at, expires_at, parent_id, client_id";
+
+/// Token families only count while the session they were issued from is alive.
+const PARENT_ALIVE: &str = "(s.parent_id is null or exists (
+        select 1 from sessions p
+        where p.id = s.parent_id and p.idle_expires_at > now() and p.expires_at > now()
+    ))";
+
+/// Session store on top of Postgres for deployments without Redis.
+/// Rows are keyed by token hash, `idle_expires_at` plays the role of the Redis TTL
+/// and expired rows are purged by [`PgSessionsRepo::spawn_cleanup`].
+/// Refresh tokens reference their session row and go away with it,
+/// as do token families with the session they were issued from.
+pub struct PgSessionsRepo {
+    pub repo: PGPool,
+    config: SessionConfig,
+    hasher: TokenHasher,
+}
+
+impl PgSessionsRepo {
+    pub fn new(repo: PGPool, config: SessionConfig, hasher: TokenHasher) -> Self {
+        PgSessionsRepo {
+            repo,
+            config,
+            hasher,
+        }
+    }
+
+    fn idle_expires_at(&self, session: &Session, now: DateTime<Utc>) -> DateTime<Utc> {
+        now + Duration::seconds(session.ttl(&self.config, now) as i64)
+    }
+
+    async fn insert(&self, session: &Session, now: DateTime<Utc>) -> Result<String, DBError> {
+        let token = generate_token();
+
+        sqlx::query(
+            r"insert into sessions (token_hash, id, user_id, ip, user_agent, auth_method, kind,
+                created_at, last_seen_at, expires_at, idle_expires_at, parent_id, client_id)
+            values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13);",
+        )
+        .bind(self.hasher.hash(&token))
+        .bind(session.id)
+        .bind(session.user_id)
+        .bind(&session.ip)
+        .bind(&session.user_agent)
+        .bind(session.auth_method.as_str())
+        .bind(session.kind.as_str())
+        .bind(session.created_at)
+        .bind(session.last_seen_at)
+        .bind(session.expires_at)
+        .bind(self.idle_expires_at(session, now))
+        .bind(session.parent_id)
+        .bind(&session.client_id)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(token)
+    }
+
+    /// Loads a live session and slides its expiry.
+    async fn touch(&self, token_hash: &str) -> Result<Option<Session>, DBError> {
+        let session: Option<Session> = sqlx::query_as(&format!(
+            r"select {SESSION_COLUMNS}
+            from sessions
+            where token_hash = $1 and idle_expires_at > now() and expires_at > now();"
+        ))
+        .bind(token_hash)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        let Some(mut session) = session else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        session.last_seen_at = now;
+
+        sqlx::query(
+            r"update sessions set last_seen_at = $2, idle_expires_at = $3
+            where token_hash = $1;",
+        )
+        .bind(token_hash)
+        .bind(now)
+        .bind(self.idle_expires_at(&session, now))
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(Some(session))
+    }
+
+    async fn insert_refresh_token(
+        &self,
+        executor: impl PgExecutor<'_>,
+        token_hash: &str,
+    ) -> Result<String, DBError> {
+        let refresh_token = generate_token();
+
+        sqlx::query(
+            r"insert into refresh_tokens (token_hash, session_token_hash) values ($1, $2);",
+        )
+        .bind(self.hasher.hash(&refresh_token))
+        .bind(token_hash)
+        .execute(executor)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(refresh_token)
+    }
+
+    pub async fn remove_expired(&self) -> Result<u64, DBError> {
+        let res = sqlx::query(
+            r"delete from sessions where idle_expires_at <= now() or expires_at <= now();",
+        )
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Periodically purges expired sessions, lookups ignore them in the meantime.
+    pub fn spawn_cleanup(self: Arc<Self>, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(e) = self.remove_expired().await {
+                    eprintln!("error removing expired sessions: {e}");
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl ISessionStore for PgSessionsRepo {
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        meta: SessionMeta,
+    ) -> Result<IssuedSession, DBError> {
+        let session = Session::new(user_id, meta, &self.config);
+        let token = self.insert(&session, session.created_at).await?;
+
+        Ok(IssuedSession { token, session })
+    }
+
+    async fn remove_session(&self, token: &str) -> Result<(), DBError> {
+        let res = sqlx::query(
+            r"delete from sessions
+            where token_hash = $1 and idle_expires_at > now() and expires_at > now();",
+        )
+        .bind(self.hasher.hash(token))
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        if res.rows_affected() == 0 {
+            return Err(SessionNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn find_session(&self, token: &str) -> Result<Option<Session>, DBError> {
+        let session = sqlx::query_as(&format!(
+            r"select {SESSION_COLUMNS}
+            from sessions
+            where token_hash = $1 and idle_expires_at > now() and expires_at > now();"
+        ))
+        .bind(self.hasher.hash(token))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(session)
+    }
+
+    async fn get_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, DBError> {
+        let session = sqlx::query_as(&format!(
+            r"select {SESSION_COLUMNS}
+            from sessions s
+            where user_id = $1 and id = $2 and idle_expires_at > now() and expires_at > now()
+                and {PARENT_ALIVE};"
+        ))
+        .bind(user_id)
+        .bind(session_id)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(session)
+    }
+
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DBError> {
+        let sessions = sqlx::query_as(&format!(
+            r"select {SESSION_COLUMNS}
+            from sessions
+            where user_id = $1 and idle_expires_at > now() and expires_at > now()
+            order by last_seen_at desc;"
+        ))
+        .bind(user_id)
+        .fetch_all(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(sessions)
+    }
+
+    async fn remove_user_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool, DBError> {
+        let res = sqlx::query(
+            r"delete from sessions
+            where user_id = $1 and id = $2 and idle_expires_at > now() and expires_at > now();",
+        )
+        .bind(user_id)
+        .bind(session_id)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn rotate_session(&self, token: &str) -> Result<Option<IssuedSession>, DBError> {
+        let session: Option<Session> = sqlx::query_as(&format!(
+            r"delete from sessions
+            where token_hash = $1 and idle_expires_at > now() and expires_at > now()
+            returning {SESSION_COLUMNS};"
+        ))
+        .bind(self.hasher.hash(token))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        let Some(mut session) = session else {
+            return Ok(None);
+        };
+
+        session.id = Uuid::new_v4();
+        let token = self.insert(&session, Utc::now()).await?;
+
+        Ok(Some(IssuedSession { token, session }))
+    }
+
+    async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<usize, DBError> {
+        let res = sqlx::query(
+            r"delete from sessions
+            where user_id = $1 and idle_expires_at > now() and expires_at > now();",
+        )
+        .bind(user_id)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(res.rows_affected() as usize)
+    }
+
+    async fn revoke_other_sessions(&self, user_id: Uuid, keep: Uuid) -> Result<usize, DBError> {
+        let res = sqlx::query(
+            r"delete from sessions
+            where user_id = $1 and id <> $2 and parent_id is distinct from $2
+                and idle_expires_at > now() and expires_at > now();",
+        )
+        .bind(user_id)
+        .bind(keep)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(res.rows_affected() as usize)
+    }
+
+    async fn create_refresh_token(&self, session_token: &str) -> Result<String, DBError> {
+        let token_hash = self.hasher.hash(session_token);
+
+        let exists: Option<(Uuid,)> = sqlx::query_as(
+            r"select id from sessions
+            where token_hash = $1 and idle_expires_at > now() and expires_at > now();",
+        )
+        .bind(&token_hash)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        if exists.is_none() {
+            return Err(SessionNotFound);
+        }
+
+        self.insert_refresh_token(&self.repo.pool, &token_hash)
+            .await
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<Option<RotatedRefreshToken>, DBError> {
+        // Marks the token used and reports whether it already was. The row stays locked
+        // until the next token is issued, a concurrent redemption waits and counts as reuse.
+        let mut tx = self
+            .repo
+            .pool
+            .begin()
+            .await
+            .map_err(FailedToQuerySessions)?;
+
+        let redeemed: Option<(String, Option<DateTime<Utc>>)> = sqlx::query_as(
+            r"with old as (
+                select token_hash, session_token_hash, used_at
+                from refresh_tokens where token_hash = $1
+                for update
+            )
+            update refresh_tokens r set used_at = coalesce(r.used_at, now())
+            from old where r.token_hash = old.token_hash
+            returning old.session_token_hash, old.used_at;",
+        )
+        .bind(self.hasher.hash(refresh_token))
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        let Some((token_hash, used_at)) = redeemed else {
+            return Ok(None);
+        };
+
+        if used_at.is_some() {
+            sqlx::query(r"delete from sessions where token_hash = $1;")
+                .bind(&token_hash)
+                .execute(&mut *tx)
+                .await
+                .map_err(FailedToQuerySessions)?;
+            tx.commit().await.map_err(FailedToQuerySessions)?;
+
+            return Err(RefreshTokenReused);
+        }
+
+        let Some(session) = self.touch(&token_hash).await? else {
+            return Ok(None);
+        };
+
+        // Using the tokens keeps the session they were issued from alive as well.
+        if let Some(parent_id) = session.parent_id {
+            let parent: Option<(String,)> =
+                sqlx::query_as(r"select token_hash from sessions where id = $1;")
+                    .bind(parent_id)
+                    .fetch_optional(&self.repo.pool)
+                    .await
+                    .map_err(FailedToQuerySessions)?;
+
+            let parent = match parent {
+                Some((parent_hash,)) => self.touch(&parent_hash).await?,
+                None => None,
+            };
+
+            if parent.is_none() {
+                sqlx::query(r"delete from sessions where token_hash = $1;")
+                    .bind(&token_hash)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(FailedToQuerySessions)?;
+                tx.commit().await.map_err(FailedToQuerySessions)?;
+
+                return Ok(None);
+            }
+        }
+
+        let token = self.insert_refresh_token(&mut *tx, &token_hash).await?;
+        tx.commit().await.map_err(FailedToQuerySessions)?;
+
+        Ok(Some(RotatedRefreshToken { token, session }))
+    }
+
+    async fn refresh_token_session(&self, refresh_token: &str) -> Result<Option<Session>, DBError> {
+        let session = sqlx::query_as(&format!(
+            r"select s.id, s.user_id, s.ip, s.user_agent, s.auth_method, s.kind,
+                s.created_at, s.last_seen_at, s.expires_at, s.parent_id, s.client_id
+            from refresh_tokens r
+            join sessions s on s.token_hash = r.session_token_hash
+            where r.token_hash = $1 and r.used_at is null
+                and s.idle_expires_at > now() and s.expires_at > now() and {PARENT_ALIVE};"
+        ))
+        .bind(self.hasher.hash(refresh_token))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQuerySessions)?;
+
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl IUserIDGetter for PgSessionsRepo {
+    async fn get_user(&self, token: &str) -> Result<Option<Session>, DBError> {
+        self.touch(&self.hasher.hash(token)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery_http::users_delivery::IUsersRepo;
+    use crate::model::{AuthMethod, SessionKind, User};
+    use crate::repo::session_store_conformance as conformance;
+    use crate::repo::users_repo::UsersRepo;
+    use crate::usecase::users_usecase::IUsersRepository;
+
+    async fn pool() -> PGPool {
+        PGPool::new(std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Sessions reference their user, so every check gets a real one.
+    async fn new_user(users: &UsersRepo) -> Uuid {
+        let id = Uuid::new_v4();
+        let user = User {
+            id,
+            email: format!("sessions-{}@example.com", id.simple()),
+            username: "sessions".to_string(),
+            ..Default::default()
+        };
+
+        users.create_user(user).await.unwrap().id
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn test_conformance() {
+        let users = UsersRepo::new(pool().await);
+        let store = PgSessionsRepo::new(
+            pool().await,
+            conformance::config(),
+            TokenHasher::new("test-key"),
+        );
+
+        conformance::check_all(&store, || new_user(&users)).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn test_cleanup_purges_expired_sessions() {
+        let users = UsersRepo::new(pool().await);
+        let store = Arc::new(PgSessionsRepo::new(
+            pool().await,
+            conformance::config(),
+            TokenHasher::new("test-key"),
+        ));
+        let user_id = new_user(&users).await;
+        let meta = |kind| SessionMeta {
+            ip: None,
+            user_agent: None,
+            auth_method: AuthMethod::Password,
+            kind,
+            parent_id: None,
+            client_id: None,
+        };
+
+        let transient = store
+            .create_session(user_id, meta(SessionKind::Transient))
+            .await
+            .unwrap();
+        store
+            .create_session(user_id, SessionMeta::token_family(&transient.session))
+            .await
+            .unwrap();
+        let persistent = store
+            .create_session(user_id, meta(SessionKind::Persistent))
+            .await
+            .unwrap();
+
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        store
+            .clone()
+            .spawn_cleanup(std::time::Duration::from_millis(50));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let rows: Vec<(Uuid,)> = sqlx::query_as(r"select id from sessions where user_id = $1;")
+            .bind(user_id)
+            .fetch_all(&store.repo.pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![(persistent.session.id,)]);
+
+        users.delete_user(user_id).await.unwrap();
+    }
+}
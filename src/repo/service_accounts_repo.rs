@@ -0,0 +1,71 @@
+use crate::delivery_http::tokens_delivery::IServiceAccountsRepo;
+use crate::errors::DBError;
+use crate::errors::DBError::FailedToQueryServiceAccounts;
+use crate::infra::postgres::PGPool;
+use crate::model::{IssuedServiceAccount, ServiceAccount};
+use crate::tokens::{TokenHasher, generate_client_id, generate_token};
+use async_trait::async_trait;
+use uuid::Uuid;
+
+const SERVICE_ACCOUNT_COLUMNS: &str = "id, client_id, name, scopes";
+
+/// Client secrets are random tokens, so a keyed hash is enough to store them.
+pub struct ServiceAccountsRepo {
+    pub repo: PGPool,
+    hasher: TokenHasher,
+}
+
+impl ServiceAccountsRepo {
+    pub fn new(repo: PGPool, hasher: TokenHasher) -> Self {
+        ServiceAccountsRepo { repo, hasher }
+    }
+
+    pub async fn create_service_account(
+        &self,
+        name: String,
+        scopes: Vec<String>,
+    ) -> Result<IssuedServiceAccount, DBError> {
+        let client_secret = generate_token();
+
+        let account = sqlx::query_as(&format!(
+            r"insert into service_accounts (id, client_id, secret_hash, name, scopes)
+            values ($1, $2, $3, $4, $5)
+            returning {SERVICE_ACCOUNT_COLUMNS};"
+        ))
+        .bind(Uuid::new_v4())
+        .bind(generate_client_id())
+        .bind(self.hasher.hash(&client_secret))
+        .bind(name)
+        .bind(scopes)
+        .fetch_one(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryServiceAccounts)?;
+
+        Ok(IssuedServiceAccount {
+            client_secret,
+            account,
+        })
+    }
+}
+
+#[async_trait]
+impl IServiceAccountsRepo for ServiceAccountsRepo {
+    async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<Option<ServiceAccount>, DBError> {
+        let account = sqlx::query_as(&format!(
+            r"select {SERVICE_ACCOUNT_COLUMNS}
+            from service_accounts
+            where client_id = $1 and secret_hash = $2;"
+        ))
+        .bind(client_id)
+        .bind(self.hasher.hash(client_secret))
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToQueryServiceAccounts)?;
+
+        Ok(account)
+    }
+}
@@ -0,0 +1,334 @@
+//! Behaviour every session backend has to share. Each backend runs [`check_all`]
+//! from its own tests, the ones needing a server are ignored by default.
+
+use crate::config::SessionConfig;
+use crate::delivery_grpc::users_delivery::IUserIDGetter;
+use crate::delivery_http::users_delivery::ISessionStore;
+use crate::errors::DBError;
+use crate::model::{AuthMethod, SessionKind, SessionMeta};
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Transient sessions idle out after a second, persistent ones live for three.
+pub fn config() -> SessionConfig {
+    SessionConfig {
+        idle_timeout: 1,
+        absolute_lifetime: 60,
+        remember_me_lifetime: 3,
+    }
+}
+
+fn meta(kind: SessionKind) -> SessionMeta {
+    SessionMeta {
+        ip: Some("127.0.0.1".to_string()),
+        user_agent: Some("test-agent".to_string()),
+        auth_method: AuthMethod::Password,
+        kind,
+        parent_id: None,
+        client_id: None,
+    }
+}
+
+/// `new_user` hands out a user id no other check has used.
+pub async fn check_all<S, F, Fut>(store: &S, new_user: F)
+where
+    S: ISessionStore + IUserIDGetter,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Uuid>,
+{
+    lookups(store, new_user().await).await;
+    session_rotation(store, new_user().await).await;
+    refresh_rotation(store, new_user().await).await;
+    concurrent_refresh_rotation(store, new_user().await).await;
+    refresh_reuse(store, new_user().await).await;
+    revoke_other_sessions(store, new_user().await, new_user().await).await;
+    token_families(store, new_user().await).await;
+    // Last, it has to wait for sessions to expire.
+    idle_and_absolute_expiry(store, new_user().await).await;
+}
+
+async fn lookups<S: ISessionStore + IUserIDGetter>(store: &S, user_id: Uuid) {
+    let issued = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let id = issued.session.id;
+
+    assert_eq!(
+        store.find_session(&issued.token).await.unwrap().unwrap().id,
+        id
+    );
+    assert_eq!(store.get_user(&issued.token).await.unwrap().unwrap().id, id);
+    assert!(store.get_session(user_id, id).await.unwrap().is_some());
+    assert!(
+        store
+            .get_session(Uuid::new_v4(), id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let listed = store.list_sessions(user_id).await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].ip.as_deref(), Some("127.0.0.1"));
+
+    assert!(store.remove_user_session(user_id, id).await.unwrap());
+    assert!(!store.remove_user_session(user_id, id).await.unwrap());
+    assert!(store.find_session(&issued.token).await.unwrap().is_none());
+}
+
+/// A rotated session keeps its data under a new token and id, the old token dies.
+async fn session_rotation<S: ISessionStore>(store: &S, user_id: Uuid) {
+    let issued = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let refresh_token = store.create_refresh_token(&issued.token).await.unwrap();
+
+    let rotated = store.rotate_session(&issued.token).await.unwrap().unwrap();
+
+    assert_ne!(rotated.token, issued.token);
+    assert_ne!(rotated.session.id, issued.session.id);
+    assert_eq!(rotated.session.kind, SessionKind::Persistent);
+    assert_eq!(rotated.session.ip.as_deref(), Some("127.0.0.1"));
+    assert!(store.find_session(&issued.token).await.unwrap().is_none());
+    assert_eq!(
+        store
+            .find_session(&rotated.token)
+            .await
+            .unwrap()
+            .unwrap()
+            .id,
+        rotated.session.id
+    );
+    assert_eq!(store.list_sessions(user_id).await.unwrap().len(), 1);
+    // Refresh tokens of the old token go with it.
+    assert!(
+        store
+            .refresh_token_session(&refresh_token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(store.rotate_session(&issued.token).await.unwrap().is_none());
+}
+
+async fn refresh_rotation<S: ISessionStore>(store: &S, user_id: Uuid) {
+    let issued = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let first = store.create_refresh_token(&issued.token).await.unwrap();
+
+    let session = store.refresh_token_session(&first).await.unwrap().unwrap();
+    assert_eq!(session.id, issued.session.id);
+
+    let rotated = store.rotate_refresh_token(&first).await.unwrap().unwrap();
+    assert_eq!(rotated.session.id, issued.session.id);
+    assert_ne!(rotated.token, first);
+    assert!(store.refresh_token_session(&first).await.unwrap().is_none());
+
+    assert!(
+        store
+            .rotate_refresh_token(&rotated.token)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert!(
+        store
+            .rotate_refresh_token("unknown")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+/// Of two redemptions racing each other exactly one wins, the other counts as reuse.
+async fn concurrent_refresh_rotation<S: ISessionStore>(store: &S, user_id: Uuid) {
+    let issued = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let refresh_token = store.create_refresh_token(&issued.token).await.unwrap();
+
+    let (a, b) = tokio::join!(
+        store.rotate_refresh_token(&refresh_token),
+        store.rotate_refresh_token(&refresh_token)
+    );
+
+    let (rotated, reused) = match (a, b) {
+        (Ok(Some(rotated)), reused) | (reused, Ok(Some(rotated))) => (rotated, reused),
+        (a, b) => panic!("no redemption won: {a:?}, {b:?}"),
+    };
+    assert!(matches!(reused, Err(DBError::RefreshTokenReused)));
+    assert!(
+        store
+            .rotate_refresh_token(&rotated.token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn refresh_reuse<S: ISessionStore>(store: &S, user_id: Uuid) {
+    let issued = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let first = store.create_refresh_token(&issued.token).await.unwrap();
+    let second = store.rotate_refresh_token(&first).await.unwrap().unwrap();
+
+    assert!(matches!(
+        store.rotate_refresh_token(&first).await,
+        Err(DBError::RefreshTokenReused)
+    ));
+
+    // Reuse ends the session along with every token issued on it.
+    assert!(store.find_session(&issued.token).await.unwrap().is_none());
+    assert!(
+        store
+            .rotate_refresh_token(&second.token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn revoke_other_sessions<S: ISessionStore>(store: &S, user_id: Uuid, other_user_id: Uuid) {
+    let mut issued = vec![];
+    for _ in 0..3 {
+        issued.push(
+            store
+                .create_session(user_id, meta(SessionKind::Persistent))
+                .await
+                .unwrap(),
+        );
+    }
+    let kept = &issued[0].session;
+    let kept_family = store
+        .create_session(user_id, SessionMeta::token_family(kept))
+        .await
+        .unwrap();
+    store
+        .create_session(user_id, SessionMeta::token_family(&issued[1].session))
+        .await
+        .unwrap();
+    let other_user = store
+        .create_session(other_user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        store.revoke_other_sessions(user_id, kept.id).await.unwrap(),
+        3
+    );
+
+    let listed: HashSet<Uuid> = store
+        .list_sessions(user_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|session| session.id)
+        .collect();
+    assert_eq!(listed, HashSet::from([kept.id, kept_family.session.id]));
+    assert!(
+        store
+            .find_session(&other_user.token)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    assert_eq!(store.revoke_all_sessions(user_id).await.unwrap(), 2);
+    assert!(store.list_sessions(user_id).await.unwrap().is_empty());
+}
+
+async fn token_families<S: ISessionStore>(store: &S, user_id: Uuid) {
+    let parent = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+    let family = store
+        .create_session(user_id, SessionMeta::token_family(&parent.session))
+        .await
+        .unwrap();
+    assert_eq!(family.session.parent_id, Some(parent.session.id));
+
+    let refresh_token = store.create_refresh_token(&family.token).await.unwrap();
+    let rotated = store
+        .rotate_refresh_token(&refresh_token)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rotated.session.id, family.session.id);
+
+    // Ending the family leaves the session it was issued from alone.
+    let sibling = store
+        .create_session(user_id, SessionMeta::token_family(&parent.session))
+        .await
+        .unwrap();
+    assert!(
+        store
+            .remove_user_session(user_id, sibling.session.id)
+            .await
+            .unwrap()
+    );
+    assert!(store.find_session(&parent.token).await.unwrap().is_some());
+
+    store.remove_session(&parent.token).await.unwrap();
+
+    assert!(
+        store
+            .get_session(user_id, family.session.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        store
+            .refresh_token_session(&rotated.token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        store
+            .rotate_refresh_token(&rotated.token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+/// Transient sessions die once they sit idle, persistent ones only at their absolute expiry.
+async fn idle_and_absolute_expiry<S: ISessionStore + IUserIDGetter>(store: &S, user_id: Uuid) {
+    let transient = store
+        .create_session(user_id, meta(SessionKind::Transient))
+        .await
+        .unwrap();
+    let persistent = store
+        .create_session(user_id, meta(SessionKind::Persistent))
+        .await
+        .unwrap();
+
+    assert!(store.get_user(&transient.token).await.unwrap().is_some());
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    assert!(store.get_user(&transient.token).await.unwrap().is_none());
+    assert!(
+        store
+            .find_session(&transient.token)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(store.get_user(&persistent.token).await.unwrap().is_some());
+
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+
+    assert!(store.get_user(&persistent.token).await.unwrap().is_none());
+    assert!(store.list_sessions(user_id).await.unwrap().is_empty());
+}
@@ -1,74 +1,708 @@
 use crate::errors::DBError::{
-    FailedToCreateSession, FailedToDeleteSession, FailedToGetUserFromSession, FailedToParseUUID,
-    SessionNotFound,
+    FailedToCreateSession, FailedToDeleteSession, FailedToGetUserFromSession, FailedToListSessions,
+    FailedToMigrateSessions, FailedToSerializeSession, RefreshTokenReused, SessionNotFound,
 };
+use crate::model::{
+    AuthMethod, IssuedSession, RotatedRefreshToken, Session, SessionKind, SessionMeta,
+};
+use crate::tokens::{TokenHasher, generate_token};
 use async_trait::async_trait;
-use deadpool_redis::redis::AsyncTypedCommands;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_redis::Connection;
+use deadpool_redis::redis::{
+    AsyncTypedCommands, ExistenceCheck, IntegerReplyOrNoOp, SetExpiry, SetOptions,
+};
+use std::cmp::Reverse;
 use uuid::Uuid;
 
-const DEFAULT_EXPIRATION_TIME: u64 = 86400;
+const SESSION_KEY_SEGMENT: &str = "sess";
+const USER_SESSIONS_KEY_SEGMENT: &str = "user_sess";
+const REFRESH_KEY_SEGMENT: &str = "refresh";
+const REFRESH_SESSION_FIELD: &str = "session";
+const REFRESH_USES_FIELD: &str = "uses";
+const LEGACY_USER_SESSIONS_PREFIX: &str = "user_sessions";
+const TOKEN_HASH_LEN: usize = 64;
+/// Lengths of the hyphen-separated groups of a UUID.
+const UUID_GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
 
 use crate::{
-    delivery_http::users_delivery::ISessionStore, errors::DBError, infra::redis::RedisPool,
+    config::SessionConfig, delivery_http::users_delivery::ISessionStore, errors::DBError,
+    infra::redis::RedisPool,
 };
 
 use crate::delivery_grpc::users_delivery::IUserIDGetter;
 
+/// Sessions are stored under the keyed hash of their token, the raw token never reaches Redis.
+///
+/// Key layout, `{prefix}` being e.g. `auth:v1`:
+/// - `{prefix}:sess:{token_hash}` holds the serialized session;
+/// - `{prefix}:user_sess:{user_id}` maps the public session id to its token hash;
+/// - `{prefix}:refresh:{refresh_hash}` is a hash holding the session token hash and the number
+///   of times the refresh token was redeemed, kept until the session's absolute expiry.
 pub struct SessionsRepo {
     pub repo: RedisPool,
+    config: SessionConfig,
+    hasher: TokenHasher,
+    key_prefix: String,
 }
 
 impl SessionsRepo {
-    pub fn new(repo: RedisPool) -> Self {
-        SessionsRepo { repo }
+    pub fn new(
+        repo: RedisPool,
+        config: SessionConfig,
+        hasher: TokenHasher,
+        key_prefix: String,
+    ) -> Self {
+        SessionsRepo {
+            repo,
+            config,
+            hasher,
+            key_prefix,
+        }
+    }
+
+    fn session_key(&self, token_hash: &str) -> String {
+        format!("{}:{SESSION_KEY_SEGMENT}:{token_hash}", self.key_prefix)
+    }
+
+    fn user_sessions_key(&self, user_id: Uuid) -> String {
+        format!("{}:{USER_SESSIONS_KEY_SEGMENT}:{user_id}", self.key_prefix)
     }
+
+    fn refresh_key(&self, refresh_hash: &str) -> String {
+        format!("{}:{REFRESH_KEY_SEGMENT}:{refresh_hash}", self.key_prefix)
+    }
+
+    async fn load(conn: &mut Connection, key: &str) -> Result<Option<Session>, DBError> {
+        let raw = conn.get(key).await.map_err(FailedToGetUserFromSession)?;
+
+        raw.map(|raw| serde_json::from_str(raw.as_str()).map_err(FailedToSerializeSession))
+            .transpose()
+    }
+
+    async fn index(
+        &self,
+        conn: &mut Connection,
+        token_hash: &str,
+        session: &Session,
+    ) -> Result<(), DBError> {
+        // The index outlives any session in it,
+        // stale members are dropped lazily in list_sessions.
+        let index_key = self.user_sessions_key(session.user_id);
+        let index_ttl = self
+            .config
+            .absolute_lifetime
+            .max(self.config.remember_me_lifetime);
+
+        conn.hset(&index_key, session.id.to_string(), token_hash)
+            .await
+            .map_err(FailedToCreateSession)?;
+
+        conn.expire(&index_key, index_ttl as i64)
+            .await
+            .map_err(FailedToCreateSession)?;
+
+        Ok(())
+    }
+
+    async fn store(
+        &self,
+        conn: &mut Connection,
+        token_hash: &str,
+        session: &Session,
+        ttl: u64,
+    ) -> Result<(), DBError> {
+        let raw = serde_json::to_string(session).map_err(FailedToSerializeSession)?;
+
+        conn.set_ex(self.session_key(token_hash), raw, ttl)
+            .await
+            .map_err(FailedToCreateSession)?;
+
+        self.index(conn, token_hash, session).await
+    }
+
+    async fn drop_session(
+        &self,
+        conn: &mut Connection,
+        token_hash: &str,
+        session: &Session,
+    ) -> Result<(), DBError> {
+        conn.del(self.session_key(token_hash))
+            .await
+            .map_err(FailedToDeleteSession)?;
+
+        conn.hdel(
+            self.user_sessions_key(session.user_id),
+            session.id.to_string(),
+        )
+        .await
+        .map_err(FailedToDeleteSession)?;
+
+        Ok(())
+    }
+
+    /// The token hash of a live session, looked up by its public id.
+    async fn live_token_hash(
+        &self,
+        conn: &mut Connection,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<String>, DBError> {
+        let Some(token_hash) = conn
+            .hget(self.user_sessions_key(user_id), session_id.to_string())
+            .await
+            .map_err(FailedToGetUserFromSession)?
+        else {
+            return Ok(None);
+        };
+
+        let session = Self::load(conn, &self.session_key(&token_hash)).await?;
+        let alive = session.is_some_and(|session| session.ttl(&self.config, Utc::now()) > 0);
+
+        Ok(alive.then_some(token_hash))
+    }
+
+    /// Whether the session a token family was issued from is still alive.
+    async fn parent_alive(
+        &self,
+        conn: &mut Connection,
+        session: &Session,
+    ) -> Result<bool, DBError> {
+        let Some(parent_id) = session.parent_id else {
+            return Ok(true);
+        };
+
+        Ok(self
+            .live_token_hash(conn, session.user_id, parent_id)
+            .await?
+            .is_some())
+    }
+
+    /// Loads a live session and slides its expiry.
+    async fn touch(
+        &self,
+        conn: &mut Connection,
+        token_hash: &str,
+    ) -> Result<Option<Session>, DBError> {
+        let key = self.session_key(token_hash);
+
+        let Some(mut session) = Self::load(conn, &key).await? else {
+            return Ok(None);
+        };
+
+        let now = Utc::now();
+        let ttl = session.ttl(&self.config, now);
+
+        if ttl == 0 {
+            self.drop_session(conn, token_hash, &session).await?;
+            return Ok(None);
+        }
+
+        session.last_seen_at = now;
+        let raw = serde_json::to_string(&session).map_err(FailedToSerializeSession)?;
+
+        // Sliding expiration; XX keeps a session that expired in the meantime from being resurrected.
+        conn.set_options(
+            &key,
+            raw,
+            SetOptions::default()
+                .conditional_set(ExistenceCheck::XX)
+                .with_expiration(SetExpiry::EX(ttl)),
+        )
+        .await
+        .map_err(FailedToGetUserFromSession)?;
+
+        Ok(Some(session))
+    }
+
+    async fn store_refresh_token(
+        &self,
+        conn: &mut Connection,
+        token_hash: &str,
+        session: &Session,
+    ) -> Result<String, DBError> {
+        let refresh_token = generate_token();
+        let key = self.refresh_key(&self.hasher.hash(&refresh_token));
+        let ttl = (session.expires_at - Utc::now()).num_seconds().max(1);
+
+        conn.hset(&key, REFRESH_SESSION_FIELD, token_hash)
+            .await
+            .map_err(FailedToCreateSession)?;
+
+        conn.expire(&key, ttl)
+            .await
+            .map_err(FailedToCreateSession)?;
+
+        Ok(refresh_token)
+    }
+
+    async fn scan_keys(conn: &mut Connection, pattern: &str) -> Result<Vec<String>, DBError> {
+        let mut iter = conn
+            .scan_match::<_, String>(pattern)
+            .await
+            .map_err(FailedToMigrateSessions)?;
+
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    /// Moves sessions written before the key schema was introduced to the prefixed layout.
+    ///
+    /// Only bare keys shaped like a session token are looked at: a UUID, the token the
+    /// original layout used as both cookie value and key, or the hash of one. Their value
+    /// is either the plain user id the original layout stored or a serialized session.
+    /// Sessions keep their remaining lifetime, so cookies issued before keep working.
+    /// Keys whose value is neither are left untouched.
+    pub async fn migrate_legacy_keys(&self) -> Result<usize, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let mut migrated = 0;
+
+        let uuid_pattern = UUID_GROUPS.map(hex_pattern).join("-");
+        let mut keys = Self::scan_keys(&mut conn, &uuid_pattern).await?;
+        keys.extend(Self::scan_keys(&mut conn, &hex_pattern(TOKEN_HASH_LEN)).await?);
+
+        for key in keys {
+            let token_hash = if key.len() == TOKEN_HASH_LEN {
+                key.clone()
+            } else {
+                self.hasher.hash(&key)
+            };
+
+            let Some(raw) = conn.get(&key).await.map_err(FailedToMigrateSessions)? else {
+                continue;
+            };
+
+            let remaining = match conn.ttl(&key).await.map_err(FailedToMigrateSessions)? {
+                IntegerReplyOrNoOp::IntegerReply(ttl) => ttl as u64,
+                IntegerReplyOrNoOp::ExistsButNotRelevant => self.config.absolute_lifetime,
+                IntegerReplyOrNoOp::NotExists => continue,
+            };
+
+            let now = Utc::now();
+            let Some(session) = legacy_session(&raw, remaining, now) else {
+                continue;
+            };
+
+            let ttl = session.ttl(&self.config, now);
+            if ttl > 0 {
+                self.store(&mut conn, &token_hash, &session, ttl).await?;
+                migrated += 1;
+            }
+
+            conn.del(&key).await.map_err(FailedToMigrateSessions)?;
+        }
+
+        for key in Self::scan_keys(&mut conn, &format!("{LEGACY_USER_SESSIONS_PREFIX}:*")).await? {
+            conn.del(&key).await.map_err(FailedToMigrateSessions)?;
+        }
+
+        Ok(migrated)
+    }
+}
+
+/// A `SCAN` pattern matching `len` lowercase hex digits.
+fn hex_pattern(len: usize) -> String {
+    "[0-9a-f]".repeat(len)
+}
+
+/// Reads the value of an unprefixed session key, `remaining` being the key's TTL in seconds.
+fn legacy_session(raw: &str, remaining: u64, now: DateTime<Utc>) -> Option<Session> {
+    if let Ok(session) = serde_json::from_str(raw) {
+        return Some(session);
+    }
+
+    let user_id = Uuid::parse_str(raw.trim()).ok()?;
+
+    Some(Session {
+        id: Uuid::new_v4(),
+        user_id,
+        ip: None,
+        user_agent: None,
+        auth_method: AuthMethod::Password,
+        kind: SessionKind::Transient,
+        created_at: now,
+        last_seen_at: now,
+        expires_at: now + Duration::seconds(remaining as i64),
+        parent_id: None,
+        client_id: None,
+    })
 }
 
 #[async_trait]
 impl ISessionStore for SessionsRepo {
-    async fn create_session(&self, user_id: Uuid) -> Result<Uuid, DBError> {
-        let session_id = Uuid::new_v4();
+    async fn create_session(
+        &self,
+        user_id: Uuid,
+        meta: SessionMeta,
+    ) -> Result<IssuedSession, DBError> {
+        let token = generate_token();
+        let session = Session::new(user_id, meta, &self.config);
+        let ttl = session.ttl(&self.config, session.created_at);
+
         let mut conn = self.repo.get_conn().await?;
+        self.store(&mut conn, &self.hasher.hash(&token), &session, ttl)
+            .await?;
 
-        conn.set_ex(
-            session_id.to_string(),
-            user_id.to_string(),
-            DEFAULT_EXPIRATION_TIME,
-        )
-        .await
-        .map_err(FailedToCreateSession)?;
+        Ok(IssuedSession { token, session })
+    }
+
+    async fn remove_session(&self, token: &str) -> Result<(), DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let token_hash = self.hasher.hash(token);
+
+        let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? else {
+            return Err(SessionNotFound);
+        };
+
+        self.drop_session(&mut conn, &token_hash, &session).await
+    }
+
+    async fn find_session(&self, token: &str) -> Result<Option<Session>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let session = Self::load(&mut conn, &self.session_key(&self.hasher.hash(token))).await?;
+
+        Ok(session.filter(|session| session.ttl(&self.config, Utc::now()) > 0))
+    }
+
+    async fn get_session(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<Option<Session>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+
+        let Some(token_hash) = self.live_token_hash(&mut conn, user_id, session_id).await? else {
+            return Ok(None);
+        };
 
-        Ok(session_id)
+        let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? else {
+            return Ok(None);
+        };
+
+        if !self.parent_alive(&mut conn, &session).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(session))
+    }
+
+    async fn list_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let index_key = self.user_sessions_key(user_id);
+
+        let members = conn
+            .hgetall(&index_key)
+            .await
+            .map_err(FailedToListSessions)?;
+
+        let mut sessions = Vec::with_capacity(members.len());
+
+        for (session_id, token_hash) in members {
+            let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? else {
+                conn.hdel(&index_key, &session_id)
+                    .await
+                    .map_err(FailedToListSessions)?;
+                continue;
+            };
+
+            sessions.push(session);
+        }
+
+        sessions.sort_by_key(|session| Reverse(session.last_seen_at));
+
+        Ok(sessions)
     }
 
-    async fn remove_session(&self, session_id: Uuid) -> Result<(), DBError> {
+    async fn remove_user_session(&self, user_id: Uuid, session_id: Uuid) -> Result<bool, DBError> {
         let mut conn = self.repo.get_conn().await?;
+        let index_key = self.user_sessions_key(user_id);
 
-        let rows_deleted = conn
-            .del(session_id.to_string())
+        let Some(token_hash) = conn
+            .hget(&index_key, session_id.to_string())
+            .await
+            .map_err(FailedToDeleteSession)?
+        else {
+            return Ok(false);
+        };
+
+        conn.hdel(&index_key, session_id.to_string())
             .await
             .map_err(FailedToDeleteSession)?;
 
-        if rows_deleted == 0 {
+        let removed = conn
+            .del(self.session_key(&token_hash))
+            .await
+            .map_err(FailedToDeleteSession)?;
+
+        Ok(removed > 0)
+    }
+
+    async fn rotate_session(&self, token: &str) -> Result<Option<IssuedSession>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let old_hash = self.hasher.hash(token);
+
+        let Some(session) = Self::load(&mut conn, &self.session_key(&old_hash)).await? else {
+            return Ok(None);
+        };
+
+        let ttl = session.ttl(&self.config, Utc::now());
+        if ttl == 0 {
+            return Ok(None);
+        }
+
+        self.drop_session(&mut conn, &old_hash, &session).await?;
+
+        let mut session = session;
+        session.id = Uuid::new_v4();
+
+        let token = generate_token();
+        self.store(&mut conn, &self.hasher.hash(&token), &session, ttl)
+            .await?;
+
+        Ok(Some(IssuedSession { token, session }))
+    }
+
+    async fn revoke_all_sessions(&self, user_id: Uuid) -> Result<usize, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let index_key = self.user_sessions_key(user_id);
+
+        let members = conn
+            .hgetall(&index_key)
+            .await
+            .map_err(FailedToDeleteSession)?;
+
+        let mut revoked = 0;
+
+        for token_hash in members.values() {
+            revoked += conn
+                .del(self.session_key(token_hash))
+                .await
+                .map_err(FailedToDeleteSession)?;
+        }
+
+        conn.del(&index_key).await.map_err(FailedToDeleteSession)?;
+
+        Ok(revoked)
+    }
+
+    async fn revoke_other_sessions(&self, user_id: Uuid, keep: Uuid) -> Result<usize, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let index_key = self.user_sessions_key(user_id);
+
+        let members = conn
+            .hgetall(&index_key)
+            .await
+            .map_err(FailedToDeleteSession)?;
+
+        let mut revoked = 0;
+
+        for (session_id, token_hash) in &members {
+            if *session_id == keep.to_string() {
+                continue;
+            }
+
+            let session = Self::load(&mut conn, &self.session_key(token_hash)).await?;
+            if session.is_some_and(|session| session.parent_id == Some(keep)) {
+                continue;
+            }
+
+            conn.hdel(&index_key, session_id)
+                .await
+                .map_err(FailedToDeleteSession)?;
+            revoked += conn
+                .del(self.session_key(token_hash))
+                .await
+                .map_err(FailedToDeleteSession)?;
+        }
+
+        Ok(revoked)
+    }
+
+    async fn create_refresh_token(&self, session_token: &str) -> Result<String, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let token_hash = self.hasher.hash(session_token);
+
+        let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? else {
             return Err(SessionNotFound);
+        };
+
+        self.store_refresh_token(&mut conn, &token_hash, &session)
+            .await
+    }
+
+    async fn rotate_refresh_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<Option<RotatedRefreshToken>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let key = self.refresh_key(&self.hasher.hash(refresh_token));
+
+        let Some(token_hash) = conn
+            .hget(&key, REFRESH_SESSION_FIELD)
+            .await
+            .map_err(FailedToGetUserFromSession)?
+        else {
+            return Ok(None);
+        };
+
+        // The counter makes redemption atomic: of two concurrent requests only one sees 1.
+        let uses = conn
+            .hincr(&key, REFRESH_USES_FIELD, 1)
+            .await
+            .map_err(FailedToGetUserFromSession)?;
+
+        if uses > 1.0 {
+            if let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? {
+                self.drop_session(&mut conn, &token_hash, &session).await?;
+            }
+            return Err(RefreshTokenReused);
         }
 
-        Ok(())
+        let Some(session) = self.touch(&mut conn, &token_hash).await? else {
+            return Ok(None);
+        };
+
+        // Using the tokens keeps the session they were issued from alive as well.
+        if let Some(parent_id) = session.parent_id {
+            let parent = self
+                .live_token_hash(&mut conn, session.user_id, parent_id)
+                .await?;
+
+            let parent = match parent {
+                Some(parent_hash) => self.touch(&mut conn, &parent_hash).await?,
+                None => None,
+            };
+
+            if parent.is_none() {
+                self.drop_session(&mut conn, &token_hash, &session).await?;
+                return Ok(None);
+            }
+        }
+
+        let token = self
+            .store_refresh_token(&mut conn, &token_hash, &session)
+            .await?;
+
+        Ok(Some(RotatedRefreshToken { token, session }))
+    }
+
+    async fn refresh_token_session(&self, refresh_token: &str) -> Result<Option<Session>, DBError> {
+        let mut conn = self.repo.get_conn().await?;
+        let key = self.refresh_key(&self.hasher.hash(refresh_token));
+
+        let Some(token_hash) = conn
+            .hget(&key, REFRESH_SESSION_FIELD)
+            .await
+            .map_err(FailedToGetUserFromSession)?
+        else {
+            return Ok(None);
+        };
+
+        let uses = conn
+            .hget(&key, REFRESH_USES_FIELD)
+            .await
+            .map_err(FailedToGetUserFromSession)?;
+
+        if uses.is_some_and(|uses| uses != "0") {
+            return Ok(None);
+        }
+
+        let Some(session) = Self::load(&mut conn, &self.session_key(&token_hash)).await? else {
+            return Ok(None);
+        };
+
+        if session.ttl(&self.config, Utc::now()) == 0
+            || !self.parent_alive(&mut conn, &session).await?
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(session))
     }
 }
 
 #[async_trait]
 impl IUserIDGetter for SessionsRepo {
-    async fn get_user(&self, session_id: Uuid) -> Result<Option<Uuid>, DBError> {
+    async fn get_user(&self, token: &str) -> Result<Option<Session>, DBError> {
         let mut conn = self.repo.get_conn().await?;
+        self.touch(&mut conn, &self.hasher.hash(token)).await
+    }
+}
 
-        let user_id = conn
-            .get(session_id.to_string())
-            .await
-            .map_err(FailedToGetUserFromSession)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery_http::session_cookie::{SESSION_COOKIE, SessionCookieCodec};
+    use crate::repo::session_store_conformance as conformance;
+    use axum_extra::extract::cookie::Cookie;
 
-        user_id
-            .map(|id| Uuid::parse_str(id.as_str()).map_err(FailedToParseUUID))
-            .transpose()
+    #[test]
+    fn test_legacy_session() {
+        let now = Utc::now();
+        let user_id = Uuid::new_v4();
+
+        // The original layout stored the bare user id under the session UUID.
+        let session = legacy_session(&user_id.to_string(), 3600, now).unwrap();
+        assert_eq!(session.user_id, user_id);
+        assert_eq!(session.kind, SessionKind::Transient);
+        assert_eq!(session.expires_at, now + Duration::seconds(3600));
+
+        let serialized = serde_json::to_string(&session).unwrap();
+        let restored = legacy_session(&serialized, 10, now).unwrap();
+        assert_eq!(restored.id, session.id);
+        assert_eq!(restored.expires_at, session.expires_at);
+
+        assert!(legacy_session("not a session", 3600, now).is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server in REDIS_URL"]
+    async fn test_legacy_cookies_keep_working_after_migration() {
+        let pool = RedisPool::new(std::env::var("REDIS_URL").unwrap()).unwrap();
+        let repo = SessionsRepo::new(
+            pool,
+            SessionConfig {
+                idle_timeout: 60,
+                absolute_lifetime: 3600,
+                remember_me_lifetime: 7200,
+            },
+            TokenHasher::new("test-key"),
+            format!("test:{}", Uuid::new_v4().simple()),
+        );
+        let (token, user_id) = (Uuid::new_v4().to_string(), Uuid::new_v4());
+
+        // The original layout: the cookie carried the bare key, which held the user id.
+        let mut conn = repo.repo.get_conn().await.unwrap();
+        conn.set_ex(&token, user_id.to_string(), 600).await.unwrap();
+        drop(conn);
+
+        assert!(repo.migrate_legacy_keys().await.unwrap() >= 1);
+
+        let cookie = Cookie::new(SESSION_COOKIE, token.clone());
+        let sealed_only = SessionCookieCodec::new(&[7; 64], None);
+        let migrating = SessionCookieCodec::new(&[7; 64], None).accept_legacy_values();
+
+        assert!(sealed_only.open(&cookie).is_none());
+        let token = migrating.open(&cookie).unwrap();
+        let session = repo.get_user(&token).await.unwrap().unwrap();
+        assert_eq!(session.user_id, user_id);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis server in REDIS_URL"]
+    async fn test_conformance() {
+        let pool = RedisPool::new(std::env::var("REDIS_URL").unwrap()).unwrap();
+        let store = SessionsRepo::new(
+            pool,
+            conformance::config(),
+            TokenHasher::new("test-key"),
+            format!("test:{}", Uuid::new_v4().simple()),
+        );
+
+        conformance::check_all(&store, || async { Uuid::new_v4() }).await;
     }
 }
@@ -3,12 +3,18 @@ use crate::errors::DBError;
 use crate::errors::DBError::{
     FailedToCreateUser, FailedToDeleteUser, FailedToGetUser, FailedToUpdateUser,
 };
+use crate::external_idp::ExternalIdentity;
 use crate::infra::postgres::PGPool;
-use crate::model::User;
+use crate::model::{EmailTokenPurpose, User};
+use crate::repo::identities_repo::insert_identity;
 use crate::usecase::users_usecase::IUsersRepository;
 use async_trait::async_trait;
+use sqlx::PgExecutor;
 use uuid::Uuid;
 
+const USER_COLUMNS: &str =
+    "id, email, username, password_hash, email_verified_at, created_at, updated_at";
+
 pub struct UsersRepo {
     pub repo: PGPool,
 }
@@ -22,10 +28,10 @@ impl UsersRepo {
 #[async_trait]
 impl IUsersRepo for UsersRepo {
     async fn update_user(&self, user: User) -> Result<Option<User>, DBError> {
-        let user = sqlx::query_as(
+        let user = sqlx::query_as(&format!(
             r"update users set username = $1 where id = $2
-            returning id, email, username, password_hash, created_at, updated_at;",
-        )
+            returning {USER_COLUMNS};"
+        ))
         .bind(user.username)
         .bind(user.id)
         .fetch_optional(&self.repo.pool)
@@ -36,11 +42,11 @@ impl IUsersRepo for UsersRepo {
     }
 
     async fn get_user(&self, user_id: Uuid) -> Result<Option<User>, DBError> {
-        let user = sqlx::query_as(
-            r"select id, email, username, password_hash, created_at, updated_at
+        let user = sqlx::query_as(&format!(
+            r"select {USER_COLUMNS}
             from users
-            where id = $1;",
-        )
+            where id = $1;"
+        ))
         .bind(user_id)
         .fetch_optional(&self.repo.pool)
         .await
@@ -63,46 +69,198 @@ impl IUsersRepo for UsersRepo {
 #[async_trait]
 impl IUsersRepository for UsersRepo {
     async fn create_user(&self, user: User) -> Result<User, DBError> {
+        insert_user(&self.repo.pool, user).await
+    }
+
+    async fn create_external_user(
+        &self,
+        user: User,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<User, DBError> {
+        let mut tx = self.repo.pool.begin().await.map_err(FailedToCreateUser)?;
+
+        let user = insert_user(&mut *tx, user).await?;
+        insert_identity(&mut *tx, user.id, provider, identity).await?;
+
+        tx.commit().await.map_err(FailedToCreateUser)?;
+
+        Ok(user)
+    }
+
+    async fn login(&self, email: String) -> Result<Option<User>, DBError> {
+        let user = sqlx::query_as(&format!(
+            r"select {USER_COLUMNS}
+            from users
+            where email = $1;"
+        ))
+        .bind(email)
+        .fetch_optional(&self.repo.pool)
+        .await
+        .map_err(FailedToGetUser)?;
+
+        Ok(user)
+    }
+
+    async fn mark_email_verified(&self, user_id: Uuid, email: &str) -> Result<bool, DBError> {
+        let res = sqlx::query(
+            r"update users set email_verified_at = now()
+            where id = $1 and email = $2 and email_verified_at is null;",
+        )
+        .bind(user_id)
+        .bind(email)
+        .execute(&self.repo.pool)
+        .await
+        .map_err(FailedToUpdateUser)?;
+
+        Ok(res.rows_affected() == 1)
+    }
+
+    async fn change_email(&self, user_id: Uuid, email: &str) -> Result<Option<String>, DBError> {
+        // The joined row still holds the values from before the update.
         let res = sqlx::query_as(
-            r#"insert into users (id, email, username, password_hash)
-            values ($1, $2, $3, $4)
-            returning id, email, username, password_hash, created_at, updated_at;"#,
+            r"update users set email = $2, email_verified_at = now()
+            from users previous
+            where users.id = $1 and previous.id = users.id
+            returning previous.email;",
         )
-        .bind(user.id)
-        .bind(user.email)
-        .bind(user.username)
-        .bind(user.password_hash)
-        .fetch_one(&self.repo.pool)
+        .bind(user_id)
+        .bind(email)
+        .fetch_optional(&self.repo.pool)
         .await;
 
         match res {
-            Ok(user) => Ok(user),
+            Ok(previous) => Ok(previous.map(|(email,)| email)),
             Err(e) => {
-                if let Some(db_err) = e.as_database_error() {
-                    // Unique violation check
-                    if let Some(code) = db_err.code() {
-                        if code == "23505" {
-                            return Err(DBError::UserAlreadyExists);
-                        }
-                    }
+                if let Some(db_err) = e.as_database_error()
+                    && let Some(code) = db_err.code()
+                    && code == "23505"
+                {
+                    return Err(DBError::UserAlreadyExists);
                 }
 
-                Err(FailedToCreateUser(e))
+                Err(FailedToUpdateUser(e))
             }
         }
     }
 
-    async fn login(&self, email: String) -> Result<Option<User>, DBError> {
-        let user = sqlx::query_as(
-            r"select id, email, username, password_hash, created_at, updated_at
-            from users
-            where email = $1;",
+    async fn update_password(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        password_hash: String,
+    ) -> Result<bool, DBError> {
+        let mut tx = self.repo.pool.begin().await.map_err(FailedToUpdateUser)?;
+
+        let res = sqlx::query(
+            r"update users set password_hash = $3
+            where id = $1 and email = $2;",
         )
+        .bind(user_id)
         .bind(email)
-        .fetch_optional(&self.repo.pool)
+        .bind(password_hash)
+        .execute(&mut *tx)
         .await
-        .map_err(FailedToGetUser)?;
+        .map_err(FailedToUpdateUser)?;
 
-        Ok(user)
+        if res.rows_affected() != 1 {
+            return Ok(false);
+        }
+
+        // Marked as used like any revoked email token, so they still count towards the throttle.
+        sqlx::query(
+            r"update email_tokens set used_at = now()
+            where user_id = $1 and purpose = $2 and used_at is null;",
+        )
+        .bind(user_id)
+        .bind(EmailTokenPurpose::PasswordReset.as_str())
+        .execute(&mut *tx)
+        .await
+        .map_err(FailedToUpdateUser)?;
+
+        tx.commit().await.map_err(FailedToUpdateUser)?;
+
+        Ok(true)
+    }
+}
+
+async fn insert_user(executor: impl PgExecutor<'_>, user: User) -> Result<User, DBError> {
+    let res = sqlx::query_as(&format!(
+        r#"insert into users (id, email, username, password_hash, email_verified_at)
+        values ($1, $2, $3, $4, $5)
+        returning {USER_COLUMNS};"#
+    ))
+    .bind(user.id)
+    .bind(user.email)
+    .bind(user.username)
+    .bind(user.password_hash)
+    .bind(user.email_verified_at)
+    .fetch_one(executor)
+    .await;
+
+    match res {
+        Ok(user) => Ok(user),
+        Err(e) => {
+            // Unique violation check
+            if let Some(db_err) = e.as_database_error()
+                && let Some(code) = db_err.code()
+                && code == "23505"
+            {
+                return Err(DBError::UserAlreadyExists);
+            }
+
+            Err(FailedToCreateUser(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_user(email: &str) -> User {
+        User {
+            id: Uuid::new_v4(),
+            email: email.to_string(),
+            username: "writer".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn test_change_email() {
+        let pool = PGPool::new(std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let repo = UsersRepo::new(pool);
+        let suffix = Uuid::new_v4().simple();
+        let email = |name: &str| format!("{name}-{suffix}@example.com");
+
+        let user = repo.create_user(new_user(&email("old"))).await.unwrap();
+        let taken = repo.create_user(new_user(&email("taken"))).await.unwrap();
+
+        assert_eq!(
+            repo.change_email(user.id, &email("new")).await.unwrap(),
+            Some(email("old"))
+        );
+        let changed = repo.get_user(user.id).await.unwrap().unwrap();
+        assert_eq!(changed.email, email("new"));
+        assert!(changed.email_verified_at.is_some());
+
+        assert!(matches!(
+            repo.change_email(user.id, &email("taken")).await,
+            Err(DBError::UserAlreadyExists)
+        ));
+        assert_eq!(
+            repo.change_email(Uuid::new_v4(), &email("other"))
+                .await
+                .unwrap(),
+            None
+        );
+
+        for id in [user.id, taken.id] {
+            repo.delete_user(id).await.unwrap();
+        }
     }
 }
@@ -0,0 +1,166 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+const TOKEN_BYTES: usize = 32;
+/// Length of [`generate_token`] output, 32 bytes in unpadded base64.
+const TOKEN_LEN: usize = 43;
+/// Prefixes are unique, 64 bits keep collisions out of reach for any realistic number of keys.
+const API_KEY_PREFIX_BYTES: usize = 8;
+const CLIENT_ID_BYTES: usize = 8;
+const CLIENT_ID_MARKER: &str = "svc_";
+const OAUTH_CLIENT_ID_MARKER: &str = "app_";
+const CODE_VERIFIER_LENGTH: std::ops::RangeInclusive<usize> = 43..=128;
+
+/// Marks a bearer credential as an API key rather than a session token.
+pub const API_KEY_MARKER: &str = "ak_";
+
+/// Generates an opaque URL-safe token carrying 256 bits of randomness.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Generates an API key as `ak_<16 hex>_<token>` along with its `ak_<16 hex>` display prefix.
+pub fn generate_api_key() -> (String, String) {
+    let mut bytes = [0u8; API_KEY_PREFIX_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+
+    let prefix = format!("{API_KEY_MARKER}{}", hex::encode(bytes));
+    let key = format!("{prefix}_{}", generate_token());
+
+    (prefix, key)
+}
+
+/// Tells an API key from a session token by its shape: `ak_<16 hex>_<token>` is longer
+/// than a bare token, so a session token starting with the marker is never taken for a key.
+pub fn is_api_key(token: &str) -> bool {
+    let Some((prefix, secret)) = token
+        .strip_prefix(API_KEY_MARKER)
+        .and_then(|rest| rest.split_once('_'))
+    else {
+        return false;
+    };
+
+    prefix.len() == API_KEY_PREFIX_BYTES * 2
+        && prefix.bytes().all(|b| b.is_ascii_hexdigit())
+        && secret.len() == TOKEN_LEN
+}
+
+/// Generates a public client id for a service account.
+pub fn generate_client_id() -> String {
+    client_id(CLIENT_ID_MARKER)
+}
+
+/// Generates a public client id for an application using the authorization code grant.
+pub fn generate_oauth_client_id() -> String {
+    client_id(OAUTH_CLIENT_ID_MARKER)
+}
+
+fn client_id(marker: &str) -> String {
+    let mut bytes = [0u8; CLIENT_ID_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{marker}{}", hex::encode(bytes))
+}
+
+/// Checks a PKCE code verifier against its S256 challenge (RFC 7636).
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let well_formed = CODE_VERIFIER_LENGTH.contains(&code_verifier.len())
+        && code_verifier
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+
+    well_formed && pkce_challenge(code_verifier) == code_challenge
+}
+
+/// Derives the S256 challenge sent along with an authorization request.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Keyed hash of bearer tokens, only the hash ever reaches storage.
+#[derive(Clone)]
+pub struct TokenHasher {
+    key: Vec<u8>,
+}
+
+impl TokenHasher {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        TokenHasher { key: key.into() }
+    }
+
+    pub fn hash(&self, token: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(token.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token_is_256_bit() {
+        let token = generate_token();
+        let decoded = URL_SAFE_NO_PAD.decode(&token).unwrap();
+
+        assert_eq!(decoded.len(), TOKEN_BYTES);
+        assert_eq!(token.len(), TOKEN_LEN);
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn test_api_key_starts_with_prefix() {
+        let (prefix, key) = generate_api_key();
+
+        assert!(prefix.starts_with(API_KEY_MARKER));
+        assert_eq!(
+            prefix.len(),
+            API_KEY_MARKER.len() + API_KEY_PREFIX_BYTES * 2
+        );
+        assert!(key.starts_with(&format!("{prefix}_")));
+    }
+
+    #[test]
+    fn test_is_api_key() {
+        let (_, key) = generate_api_key();
+        assert!(is_api_key(&key));
+
+        let session_token = format!(
+            "{API_KEY_MARKER}{}",
+            &generate_token()[API_KEY_MARKER.len()..]
+        );
+        assert!(!is_api_key(&session_token));
+        assert!(!is_api_key(&generate_token()));
+    }
+
+    #[test]
+    fn test_verify_pkce() {
+        // RFC 7636, appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce(&verifier[1..], challenge));
+        assert!(!verify_pkce("short", challenge));
+    }
+
+    #[test]
+    fn test_hash_depends_on_key() {
+        let token = generate_token();
+        let hasher = TokenHasher::new("first-key");
+
+        assert_eq!(hasher.hash(&token), hasher.hash(&token));
+        assert_ne!(hasher.hash(&token), token);
+        assert_ne!(
+            hasher.hash(&token),
+            TokenHasher::new("second-key").hash(&token)
+        );
+    }
+}
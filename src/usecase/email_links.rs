@@ -0,0 +1,422 @@
+use crate::errors::DBError;
+use crate::infra::mailer::{Email, IMailer};
+use crate::model::{EmailToken, EmailTokenPurpose, NewEmailToken, User};
+use crate::usecase::emails::{
+    email_change_email, email_changed_email, magic_link_email, password_reset_email,
+    verification_email,
+};
+use crate::usecase::users_usecase::{IEmailVerifier, IUsersRepository};
+use async_trait::async_trait;
+use std::sync::Arc;
+use url::Url;
+use uuid::Uuid;
+
+pub const MAGIC_LINK_TTL: u64 = 60 * 15;
+const VERIFICATION_TTL: u64 = 60 * 60 * 24;
+const PASSWORD_RESET_TTL: u64 = 60 * 60;
+/// Long enough to notice the notice after a few days away.
+const EMAIL_REVERT_TTL: u64 = 60 * 60 * 24 * 3;
+/// At most one link per user and purpose in this many seconds, so none of the
+/// endpoints that mail links can be used to flood someone's inbox.
+const EMAIL_LINK_INTERVAL: u64 = 60;
+
+#[async_trait]
+pub trait IEmailTokensRepo: Send + Sync {
+    /// Returns `None` without issuing anything when a token with the same purpose
+    /// was issued to the user less than `min_interval` seconds ago.
+    async fn issue_email_token(
+        &self,
+        new_token: &NewEmailToken,
+        min_interval: u64,
+    ) -> Result<Option<String>, DBError>;
+    /// Single use, and only together with the browser secret the token was issued for.
+    /// A wrong secret leaves the token untouched.
+    async fn redeem_email_token(
+        &self,
+        purpose: EmailTokenPurpose,
+        token: &str,
+        browser_secret: Option<&str>,
+    ) -> Result<Option<EmailToken>, DBError>;
+    /// Burns every unused token of the user with this purpose.
+    async fn revoke_email_tokens(
+        &self,
+        user_id: Uuid,
+        purpose: EmailTokenPurpose,
+    ) -> Result<(), DBError>;
+}
+
+/// Where each kind of link leads, the token is added to the query.
+#[derive(Clone)]
+pub struct EmailLinkUrls {
+    pub verification: Url,
+    pub email_change: Url,
+    pub email_revert: Url,
+    pub password_reset: Url,
+    pub magic_link: Url,
+}
+
+/// Issues single-use tokens and mails them as links.
+///
+/// Every send runs detached from the request: the response never waits for the
+/// mail server, and when the link is requested by address it takes the same time
+/// whether the address is registered or not.
+#[derive(Clone)]
+pub struct EmailLinks {
+    email_tokens: Arc<dyn IEmailTokensRepo>,
+    users: Arc<dyn IUsersRepository>,
+    mailer: Arc<dyn IMailer>,
+    urls: EmailLinkUrls,
+}
+
+impl EmailLinks {
+    pub fn new(
+        email_tokens: Arc<dyn IEmailTokensRepo>,
+        users: Arc<dyn IUsersRepository>,
+        mailer: Arc<dyn IMailer>,
+        urls: EmailLinkUrls,
+    ) -> Self {
+        EmailLinks {
+            email_tokens,
+            users,
+            mailer,
+            urls,
+        }
+    }
+
+    /// Sign-in link bound to `browser_secret`, for the user with `email` if there is one.
+    pub fn send_magic_link(&self, email: String, browser_secret: String) {
+        let links = self.clone();
+        detach(async move {
+            let Some(user) = links.users.login(email).await? else {
+                return Ok(());
+            };
+
+            links
+                .mail_link(
+                    NewEmailToken {
+                        purpose: EmailTokenPurpose::MagicLink,
+                        user_id: user.id,
+                        email: user.email,
+                        browser_secret: Some(browser_secret),
+                        ttl: MAGIC_LINK_TTL,
+                    },
+                    EMAIL_LINK_INTERVAL,
+                    &links.urls.magic_link,
+                    |to, link| magic_link_email(to, link, MAGIC_LINK_TTL / 60),
+                )
+                .await
+        });
+    }
+
+    pub fn send_password_reset(&self, email: String) {
+        let links = self.clone();
+        detach(async move {
+            let Some(user) = links.users.login(email).await? else {
+                return Ok(());
+            };
+
+            links
+                .mail_link(
+                    NewEmailToken {
+                        purpose: EmailTokenPurpose::PasswordReset,
+                        user_id: user.id,
+                        email: user.email,
+                        browser_secret: None,
+                        ttl: PASSWORD_RESET_TTL,
+                    },
+                    EMAIL_LINK_INTERVAL,
+                    &links.urls.password_reset,
+                    |to, link| password_reset_email(to, link, PASSWORD_RESET_TTL / 60),
+                )
+                .await
+        });
+    }
+
+    pub fn resend_verification(&self, email: String) {
+        let links = self.clone();
+        detach(async move {
+            let Some(user) = links.users.login(email).await? else {
+                return Ok(());
+            };
+
+            links.verification(user).await
+        });
+    }
+
+    /// Tells the previous address about the change and how to undo it.
+    pub fn send_changed_notice(&self, user_id: Uuid, previous_email: String, new_email: String) {
+        let links = self.clone();
+        detach(async move {
+            // Never throttled, every change gets its own way back.
+            links
+                .mail_link(
+                    NewEmailToken {
+                        purpose: EmailTokenPurpose::EmailRevert,
+                        user_id,
+                        email: previous_email,
+                        browser_secret: None,
+                        ttl: EMAIL_REVERT_TTL,
+                    },
+                    0,
+                    &links.urls.email_revert,
+                    |to, link| {
+                        email_changed_email(to, &new_email, link, EMAIL_REVERT_TTL / 60 / 60 / 24)
+                    },
+                )
+                .await
+        });
+    }
+
+    async fn verification(&self, user: User) -> Result<(), DBError> {
+        if user.email_verified_at.is_some() {
+            return Ok(());
+        }
+
+        self.mail_link(
+            NewEmailToken {
+                purpose: EmailTokenPurpose::EmailVerification,
+                user_id: user.id,
+                email: user.email,
+                browser_secret: None,
+                ttl: VERIFICATION_TTL,
+            },
+            EMAIL_LINK_INTERVAL,
+            &self.urls.verification,
+            |to, link| verification_email(to, link, VERIFICATION_TTL / 60 / 60),
+        )
+        .await
+    }
+
+    /// Issues the token and mails `email(to, link)`, nothing happens when the user
+    /// got a token with the same purpose less than `min_interval` seconds ago.
+    async fn mail_link(
+        &self,
+        new_token: NewEmailToken,
+        min_interval: u64,
+        base: &Url,
+        email: impl FnOnce(&str, &str) -> Email,
+    ) -> Result<(), DBError> {
+        let Some(token) = self
+            .email_tokens
+            .issue_email_token(&new_token, min_interval)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        let mut link = base.clone();
+        link.query_pairs_mut().append_pair("token", &token);
+
+        if let Err(e) = self
+            .mailer
+            .send(email(&new_token.email, link.as_str()))
+            .await
+        {
+            eprintln!("failed to send {} email: {e}", new_token.purpose.as_str());
+        }
+
+        Ok(())
+    }
+}
+
+fn detach(send: impl Future<Output = Result<(), DBError>> + Send + 'static) {
+    tokio::spawn(async move {
+        if let Err(e) = send.await {
+            eprintln!("failed to issue email token: {e}");
+        }
+    });
+}
+
+#[async_trait]
+impl IEmailVerifier for EmailLinks {
+    async fn send_verification(&self, user: &User) -> Result<(), DBError> {
+        let (links, user) = (self.clone(), user.clone());
+        detach(async move { links.verification(user).await });
+
+        Ok(())
+    }
+
+    async fn send_email_change(&self, user: &User, new_email: &str) -> Result<(), DBError> {
+        let links = self.clone();
+        let (user_id, new_email) = (user.id, new_email.to_string());
+        detach(async move {
+            links
+                .mail_link(
+                    NewEmailToken {
+                        purpose: EmailTokenPurpose::EmailChange,
+                        user_id,
+                        email: new_email,
+                        browser_secret: None,
+                        ttl: VERIFICATION_TTL,
+                    },
+                    EMAIL_LINK_INTERVAL,
+                    &links.urls.email_change,
+                    |to, link| email_change_email(to, link, VERIFICATION_TTL / 60 / 60),
+                )
+                .await
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::MailError;
+    use crate::usecase::users_usecase::MockIUsersRepository;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingMailer(Mutex<Vec<Email>>);
+
+    #[async_trait]
+    impl IMailer for RecordingMailer {
+        async fn send(&self, email: Email) -> Result<(), MailError> {
+            self.0.lock().unwrap().push(email);
+            Ok(())
+        }
+    }
+
+    /// Issues `token` every time, or nothing when it is `None` as if throttled.
+    struct FakeEmailTokens {
+        token: Option<String>,
+        issued: Mutex<Vec<(EmailTokenPurpose, u64, Option<String>)>>,
+    }
+
+    #[async_trait]
+    impl IEmailTokensRepo for FakeEmailTokens {
+        async fn issue_email_token(
+            &self,
+            new_token: &NewEmailToken,
+            min_interval: u64,
+        ) -> Result<Option<String>, DBError> {
+            self.issued.lock().unwrap().push((
+                new_token.purpose,
+                min_interval,
+                new_token.browser_secret.clone(),
+            ));
+            Ok(self.token.clone())
+        }
+
+        async fn redeem_email_token(
+            &self,
+            _: EmailTokenPurpose,
+            _: &str,
+            _: Option<&str>,
+        ) -> Result<Option<EmailToken>, DBError> {
+            Ok(None)
+        }
+
+        async fn revoke_email_tokens(&self, _: Uuid, _: EmailTokenPurpose) -> Result<(), DBError> {
+            Ok(())
+        }
+    }
+
+    fn links(
+        token: Option<&str>,
+        users: MockIUsersRepository,
+    ) -> (EmailLinks, Arc<FakeEmailTokens>, Arc<RecordingMailer>) {
+        let url = |path: &str| Url::parse(&format!("https://auth.example.com{path}")).unwrap();
+        let email_tokens = Arc::new(FakeEmailTokens {
+            token: token.map(str::to_string),
+            issued: Mutex::default(),
+        });
+        let mailer = Arc::new(RecordingMailer::default());
+
+        let links = EmailLinks::new(
+            email_tokens.clone(),
+            Arc::new(users),
+            mailer.clone(),
+            EmailLinkUrls {
+                verification: url("/verify-email"),
+                email_change: url("/confirm-email"),
+                email_revert: url("/api/v1/email/revert"),
+                password_reset: url("/reset-password"),
+                magic_link: url("/api/v1/login/magic-link/verify"),
+            },
+        );
+
+        (links, email_tokens, mailer)
+    }
+
+    fn unverified_user() -> User {
+        User {
+            email: "writer@example.com".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verification_mails_link_with_token() {
+        let (links, email_tokens, mailer) = links(Some("t0k3n"), MockIUsersRepository::new());
+
+        links.verification(unverified_user()).await.unwrap();
+
+        assert_eq!(
+            *email_tokens.issued.lock().unwrap(),
+            [(
+                EmailTokenPurpose::EmailVerification,
+                EMAIL_LINK_INTERVAL,
+                None
+            )]
+        );
+        let sent = mailer.0.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "writer@example.com");
+        assert!(
+            sent[0]
+                .body
+                .contains("https://auth.example.com/verify-email?token=t0k3n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nothing_is_mailed_when_throttled_or_verified() {
+        let (links, email_tokens, mailer) = links(None, MockIUsersRepository::new());
+
+        links.verification(unverified_user()).await.unwrap();
+
+        let verified = User {
+            email_verified_at: Some(Default::default()),
+            ..unverified_user()
+        };
+        links.verification(verified).await.unwrap();
+
+        assert_eq!(email_tokens.issued.lock().unwrap().len(), 1);
+        assert!(mailer.0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_magic_link_is_bound_to_the_browser_secret() {
+        let mut users = MockIUsersRepository::new();
+        users
+            .expect_login()
+            .times(1)
+            .returning(|_| Ok(Some(unverified_user())));
+        let (links, email_tokens, mailer) = links(Some("t0k3n"), users);
+
+        links.send_magic_link("writer@example.com".to_string(), "s3cret".to_string());
+
+        // The link is mailed in the background.
+        while mailer.0.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(
+            *email_tokens.issued.lock().unwrap(),
+            [(
+                EmailTokenPurpose::MagicLink,
+                EMAIL_LINK_INTERVAL,
+                Some("s3cret".to_string())
+            )]
+        );
+        let sent = mailer.0.lock().unwrap();
+        assert!(
+            sent[0]
+                .body
+                .contains("https://auth.example.com/api/v1/login/magic-link/verify?token=t0k3n")
+        );
+        assert!(!sent[0].body.contains("s3cret"));
+    }
+}
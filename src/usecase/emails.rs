@@ -0,0 +1,65 @@
+use crate::infra::mailer::Email;
+
+pub fn magic_link_email(to: &str, link: &str, ttl_minutes: u64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Your sign-in link".to_string(),
+        body: format!(
+            "Open this link to sign in:\n\n{link}\n\n\
+             It works once, for {ttl_minutes} minutes, and only in the browser \
+             you requested it from.\n\
+             If you did not try to sign in, you can ignore this email."
+        ),
+    }
+}
+
+pub fn password_reset_email(to: &str, link: &str, ttl_minutes: u64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Open this link to choose a new password:\n\n{link}\n\n\
+             It works once, for {ttl_minutes} minutes. Resetting the password \
+             signs you out everywhere.\n\
+             If you did not ask for this, you can ignore this email, your password \
+             stays the same."
+        ),
+    }
+}
+
+pub fn email_change_email(to: &str, link: &str, ttl_hours: u64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Confirm your new email".to_string(),
+        body: format!(
+            "Open this link to make {to} the email of your account:\n\n{link}\n\n\
+             It works once, for {ttl_hours} hours.\n\
+             If you did not ask for this, you can ignore this email."
+        ),
+    }
+}
+
+pub fn email_changed_email(to: &str, new_email: &str, revert_link: &str, ttl_days: u64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Your email was changed".to_string(),
+        body: format!(
+            "The email of your account was changed to {new_email}.\n\n\
+             If this wasn't you, open this link within {ttl_days} days to change it \
+             back, sign out everywhere and revoke your password:\n\n{revert_link}\n\n\
+             Then reset your password to sign in again."
+        ),
+    }
+}
+
+pub fn verification_email(to: &str, link: &str, ttl_hours: u64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Confirm your email".to_string(),
+        body: format!(
+            "Open this link to confirm your email address:\n\n{link}\n\n\
+             It works once, for {ttl_hours} hours.\n\
+             If you did not create an account, you can ignore this email."
+        ),
+    }
+}
@@ -1 +1,3 @@
+pub mod email_links;
+pub mod emails;
 pub mod users_usecase;
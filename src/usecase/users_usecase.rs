@@ -1,13 +1,18 @@
+use crate::config::EmailVerificationPolicy;
 use crate::delivery_http::dto::{LoginRequest, RegisterRequest};
 use crate::delivery_http::users_delivery::IUsersCreatorUsecase;
-use crate::errors::UsecaseError::{DBDerivedError, InvalidCreds, UserNotFoundError};
+use crate::errors::UsecaseError::{
+    DBDerivedError, EmailNotVerified, InvalidCreds, UserNotFoundError,
+};
 use crate::errors::{DBError, UsecaseError};
+use crate::external_idp::ExternalIdentity;
 use crate::model::User;
+use crate::tokens::generate_token;
 use argon2::password_hash::SaltString;
 use argon2::password_hash::rand_core::OsRng;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use async_trait::async_trait;
-use mockall::predicate::*;
+use chrono::Local;
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -15,40 +20,112 @@ use uuid::Uuid;
 #[async_trait]
 pub trait IUsersRepository: Send + Sync {
     async fn create_user(&self, user: User) -> Result<User, DBError>;
+    /// Creates the user and links the provider account to them in one transaction.
+    /// Fails with [`DBError::UserAlreadyExists`] or [`DBError::IdentityAlreadyLinked`].
+    async fn create_external_user(
+        &self,
+        user: User,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<User, DBError>;
     async fn login(&self, email: String) -> Result<Option<User>, DBError>;
+    /// Only while the user still has the address the token was sent to.
+    /// Returns `false` when nothing changed.
+    async fn mark_email_verified(&self, user_id: Uuid, email: &str) -> Result<bool, DBError>;
+    /// Sets a new, verified address and returns the previous one.
+    /// Fails with [`DBError::UserAlreadyExists`] when the address is taken.
+    async fn change_email(&self, user_id: Uuid, email: &str) -> Result<Option<String>, DBError>;
+    /// Only while the user still has `email`. Returns `false` when nothing changed.
+    /// Outstanding password reset links stop working along with the old password.
+    async fn update_password(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        password_hash: String,
+    ) -> Result<bool, DBError>;
+}
+
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait IEmailVerifier: Send + Sync {
+    /// Mails a verification link unless the address is verified already.
+    async fn send_verification(&self, user: &User) -> Result<(), DBError>;
+    /// Mails a confirmation link for the change to `new_email` to that address.
+    async fn send_email_change(&self, user: &User, new_email: &str) -> Result<(), DBError>;
 }
 
 pub struct UserUsecase {
     repo: Arc<dyn IUsersRepository>,
+    verifier: Arc<dyn IEmailVerifier>,
+    policy: EmailVerificationPolicy,
 }
 
 impl UserUsecase {
-    pub fn new(repo: Arc<dyn IUsersRepository>) -> Self {
-        UserUsecase { repo }
+    pub fn new(
+        repo: Arc<dyn IUsersRepository>,
+        verifier: Arc<dyn IEmailVerifier>,
+        policy: EmailVerificationPolicy,
+    ) -> Self {
+        UserUsecase {
+            repo,
+            verifier,
+            policy,
+        }
     }
 }
 
 #[async_trait]
 impl IUsersCreatorUsecase for UserUsecase {
     async fn create_user(&self, user_payload: RegisterRequest) -> Result<User, UsecaseError> {
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(user_payload.password.as_bytes(), &salt)?
-            .to_string();
-
-        let password_hash = PasswordHash::new(&password_hash)?;
-
         let user = User {
             id: Uuid::new_v4(),
             email: user_payload.email,
             username: user_payload.username,
-            password_hash: password_hash.to_string(),
+            password_hash: hash_password(&user_payload.password)?,
+            email_verified_at: None,
             created_at: Default::default(),
             updated_at: Default::default(),
         };
 
-        Ok(self.repo.create_user(user).await.map_err(DBDerivedError)?)
+        let user = self.repo.create_user(user).await.map_err(DBDerivedError)?;
+
+        // The account exists either way, the user can ask for another link.
+        if let Err(e) = self.verifier.send_verification(&user).await {
+            eprintln!("failed to send verification email: {e}");
+        }
+
+        Ok(user)
+    }
+
+    async fn create_external_user(
+        &self,
+        provider: &str,
+        identity: &ExternalIdentity,
+    ) -> Result<User, UsecaseError> {
+        // Nobody knows this password, the account is only reachable through the provider.
+        let password_hash = hash_password(&generate_token())?;
+
+        let user = User {
+            id: Uuid::new_v4(),
+            email: identity.email.clone(),
+            username: identity.username.clone(),
+            password_hash,
+            email_verified_at: identity.email_verified.then(Local::now),
+            created_at: Default::default(),
+            updated_at: Default::default(),
+        };
+
+        let user = self
+            .repo
+            .create_external_user(user, provider, identity)
+            .await
+            .map_err(DBDerivedError)?;
+
+        if let Err(e) = self.verifier.send_verification(&user).await {
+            eprintln!("failed to send verification email: {e}");
+        }
+
+        Ok(user)
     }
 
     async fn login(&self, login_payload: LoginRequest) -> Result<User, UsecaseError> {
@@ -62,25 +139,111 @@ impl IUsersCreatorUsecase for UserUsecase {
             }
         };
 
-        let argon2 = Argon2::default();
+        if verify_password(&login_payload.password, &user.password_hash)? {
+            // Checked after the password so the answer reveals nothing to a stranger.
+            if self.can_sign_in(&user) {
+                Ok(user)
+            } else {
+                Err(EmailNotVerified)
+            }
+        } else {
+            Err(InvalidCreds)
+        }
+    }
 
-        let parsed_hash = PasswordHash::new(user.password_hash.as_str())?;
+    async fn change_password(
+        &self,
+        user: &User,
+        current_password: &str,
+        new_password: &str,
+    ) -> Result<(), UsecaseError> {
+        if !verify_password(current_password, &user.password_hash)? {
+            return Err(InvalidCreds);
+        }
 
-        if argon2
-            .verify_password(login_payload.password.as_bytes(), &parsed_hash)
-            .is_ok()
+        if self
+            .repo
+            .update_password(user.id, &user.email, hash_password(new_password)?)
+            .await?
         {
-            Ok(user)
+            Ok(())
         } else {
-            Err(InvalidCreds)
+            Err(UserNotFoundError)
+        }
+    }
+
+    async fn request_email_change(
+        &self,
+        user: &User,
+        current_password: &str,
+        new_email: String,
+    ) -> Result<(), UsecaseError> {
+        if !verify_password(current_password, &user.password_hash)? {
+            return Err(InvalidCreds);
+        }
+
+        if new_email == user.email {
+            return Ok(());
+        }
+
+        // Registration tells the same, and a confirmation that can never succeed helps nobody.
+        if self.repo.login(new_email.clone()).await?.is_some() {
+            return Err(DBDerivedError(DBError::UserAlreadyExists));
         }
+
+        Ok(self.verifier.send_email_change(user, &new_email).await?)
+    }
+
+    async fn reset_password(
+        &self,
+        user_id: Uuid,
+        email: &str,
+        password: &str,
+    ) -> Result<bool, UsecaseError> {
+        Ok(self
+            .repo
+            .update_password(user_id, email, hash_password(password)?)
+            .await?)
+    }
+
+    async fn lock_password(&self, user_id: Uuid, email: &str) -> Result<bool, UsecaseError> {
+        Ok(self
+            .repo
+            .update_password(user_id, email, hash_password(&generate_token())?)
+            .await?)
+    }
+
+    fn can_sign_in(&self, user: &User) -> bool {
+        self.policy != EmailVerificationPolicy::Block || user.email_verified_at.is_some()
+    }
+
+    fn has_full_access(&self, user: &User) -> bool {
+        self.policy == EmailVerificationPolicy::Off || user.email_verified_at.is_some()
     }
 }
 
+fn verify_password(password: &str, password_hash: &str) -> Result<bool, UsecaseError> {
+    let parsed_hash = PasswordHash::new(password_hash)?;
+
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+fn hash_password(password: &str) -> Result<String, UsecaseError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string();
+
+    Ok(PasswordHash::new(&password_hash)?.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::errors::DBError;
+    use mockall::predicate::*;
     use std::sync::Arc;
     use uuid::Uuid;
 
@@ -90,11 +253,27 @@ mod tests {
             email: "test@example.com".to_string(),
             username: "testuser".to_string(),
             password_hash: "argon2_hash_placeholder".to_string(),
+            email_verified_at: None,
             created_at: Default::default(),
             updated_at: Default::default(),
         }
     }
 
+    fn usecase(repo: MockIUsersRepository, policy: EmailVerificationPolicy) -> UserUsecase {
+        let mut verifier = MockIEmailVerifier::new();
+        verifier.expect_send_verification().returning(|_| Ok(()));
+
+        UserUsecase::new(Arc::new(repo), Arc::new(verifier), policy)
+    }
+
+    fn password_hash(password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .unwrap()
+            .to_string()
+    }
+
     #[tokio::test]
     async fn test_create_user_success() {
         let mut mock_repo = MockIUsersRepository::new();
@@ -103,9 +282,20 @@ mod tests {
             .expect_create_user()
             .times(1)
             .withf(|u: &User| u.email == "new@email.com" && u.username == "NewUser")
-            .returning(|u| Ok(u));
+            .returning(Ok);
+
+        let mut verifier = MockIEmailVerifier::new();
+        verifier
+            .expect_send_verification()
+            .times(1)
+            .withf(|u: &User| u.email == "new@email.com")
+            .returning(|_| Ok(()));
 
-        let usecase = UserUsecase::new(Arc::new(mock_repo));
+        let usecase = UserUsecase::new(
+            Arc::new(mock_repo),
+            Arc::new(verifier),
+            EmailVerificationPolicy::Restrict,
+        );
 
         let req = RegisterRequest {
             email: "new@email.com".to_string(),
@@ -131,7 +321,7 @@ mod tests {
             .times(1)
             .returning(|_| Err(DBError::UserAlreadyExists));
 
-        let usecase = UserUsecase::new(Arc::new(mock_repo));
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
 
         let req = RegisterRequest {
             email: "exists@email.com".to_string(),
@@ -142,12 +332,192 @@ mod tests {
         let result = usecase.create_user(req).await;
 
         assert!(result.is_err());
-        match result.unwrap_err() {
-            DBDerivedError(_) => assert!(true),
-            _ => assert!(false, "Wrong error type"),
+        assert!(
+            matches!(result.unwrap_err(), DBDerivedError(_)),
+            "Wrong error type"
+        );
+    }
+
+    fn octocat(email_verified: bool) -> ExternalIdentity {
+        ExternalIdentity {
+            subject: "583231".to_string(),
+            email: "octocat@example.com".to_string(),
+            username: "octocat".to_string(),
+            email_verified,
         }
     }
 
+    #[tokio::test]
+    async fn test_create_external_user_success() {
+        let mut mock_repo = MockIUsersRepository::new();
+
+        mock_repo
+            .expect_create_external_user()
+            .times(1)
+            .withf(|u: &User, provider, identity| {
+                u.email == "octocat@example.com"
+                    && u.username == "octocat"
+                    && provider == "github"
+                    && identity.subject == "583231"
+            })
+            .returning(|u, _, _| Ok(u));
+
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
+
+        let user = usecase
+            .create_external_user("github", &octocat(true))
+            .await
+            .unwrap();
+
+        assert!(PasswordHash::new(&user.password_hash).is_ok());
+        assert!(user.email_verified_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_create_external_user_with_unverified_email() {
+        let mut mock_repo = MockIUsersRepository::new();
+        mock_repo
+            .expect_create_external_user()
+            .times(1)
+            .returning(|u, _, _| Ok(u));
+
+        let mut verifier = MockIEmailVerifier::new();
+        verifier
+            .expect_send_verification()
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let usecase = UserUsecase::new(
+            Arc::new(mock_repo),
+            Arc::new(verifier),
+            EmailVerificationPolicy::Restrict,
+        );
+
+        let user = usecase
+            .create_external_user("github", &octocat(false))
+            .await
+            .unwrap();
+
+        assert!(user.email_verified_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_change_password() {
+        let mut user = mock_user();
+        user.password_hash = password_hash("old-password");
+
+        let mut mock_repo = MockIUsersRepository::new();
+        mock_repo
+            .expect_update_password()
+            .times(1)
+            .withf(|_, _, hash| verify_password("new-password", hash).unwrap())
+            .returning(|_, _, _| Ok(true));
+
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
+
+        assert!(matches!(
+            usecase
+                .change_password(&user, "wrong-password", "new-password")
+                .await,
+            Err(InvalidCreds)
+        ));
+        assert!(
+            usecase
+                .change_password(&user, "old-password", "new-password")
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lock_password() {
+        let user = mock_user();
+        let user_id = user.id;
+
+        let mut mock_repo = MockIUsersRepository::new();
+        mock_repo
+            .expect_update_password()
+            .times(1)
+            .withf(move |id, email, hash| {
+                *id == user_id
+                    && email == "test@example.com"
+                    && !verify_password("old-password", hash).unwrap()
+            })
+            .returning(|_, _, _| Ok(true));
+
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
+
+        assert!(usecase.lock_password(user.id, &user.email).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_request_email_change() {
+        let mut user = mock_user();
+        user.password_hash = password_hash("password");
+
+        let mut mock_repo = MockIUsersRepository::new();
+        mock_repo
+            .expect_login()
+            .returning(|email| Ok((email == "taken@example.com").then(mock_user)));
+
+        let mut verifier = MockIEmailVerifier::new();
+        verifier
+            .expect_send_email_change()
+            .times(1)
+            .withf(|_, new_email| new_email == "new@example.com")
+            .returning(|_, _| Ok(()));
+
+        let usecase = UserUsecase::new(
+            Arc::new(mock_repo),
+            Arc::new(verifier),
+            EmailVerificationPolicy::Restrict,
+        );
+
+        assert!(matches!(
+            usecase
+                .request_email_change(&user, "wrong", "new@example.com".to_string())
+                .await,
+            Err(InvalidCreds)
+        ));
+        assert!(matches!(
+            usecase
+                .request_email_change(&user, "password", "taken@example.com".to_string())
+                .await,
+            Err(DBDerivedError(DBError::UserAlreadyExists))
+        ));
+        assert!(
+            usecase
+                .request_email_change(&user, "password", "new@example.com".to_string())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reset_password() {
+        let mut mock_repo = MockIUsersRepository::new();
+        let user_id = Uuid::new_v4();
+
+        mock_repo
+            .expect_update_password()
+            .times(1)
+            .withf(move |id, email, hash| {
+                *id == user_id
+                    && email == "test@example.com"
+                    && verify_password("new-password", hash).unwrap()
+            })
+            .returning(|_, _, _| Ok(true));
+
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
+
+        assert!(
+            usecase
+                .reset_password(user_id, "test@example.com", "new-password")
+                .await
+                .unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_login_success() {
         let mut mock_repo = MockIUsersRepository::new();
@@ -170,11 +540,14 @@ mod tests {
             .with(eq("login@test.com".to_string()))
             .return_once(move |_| Ok(Some(db_user)));
 
-        let usecase = UserUsecase::new(Arc::new(mock_repo));
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
 
         let req = LoginRequest {
             email: "login@test.com".to_string(),
             password: "mysecretpassword".to_string(),
+            remember_me: false,
+            issue_access_token: false,
+            cookieless: false,
         };
 
         let result = usecase.login(req).await;
@@ -183,6 +556,43 @@ mod tests {
         assert_eq!(result.unwrap().email, "login@test.com");
     }
 
+    #[tokio::test]
+    async fn test_login_unverified_email() {
+        let mut db_user = mock_user();
+        db_user.password_hash = password_hash("mysecretpassword");
+
+        let req = || LoginRequest {
+            email: "test@example.com".to_string(),
+            password: "mysecretpassword".to_string(),
+            remember_me: false,
+            issue_access_token: false,
+            cookieless: false,
+        };
+
+        let mut mock_repo = MockIUsersRepository::new();
+        let user = db_user.clone();
+        mock_repo
+            .expect_login()
+            .returning(move |_| Ok(Some(user.clone())));
+        let restricted = usecase(mock_repo, EmailVerificationPolicy::Restrict);
+
+        assert!(restricted.login(req()).await.is_ok());
+        assert!(!restricted.has_full_access(&db_user));
+
+        let mut mock_repo = MockIUsersRepository::new();
+        let user = db_user.clone();
+        mock_repo
+            .expect_login()
+            .returning(move |_| Ok(Some(user.clone())));
+        let blocked = usecase(mock_repo, EmailVerificationPolicy::Block);
+
+        assert!(matches!(blocked.login(req()).await, Err(EmailNotVerified)));
+
+        db_user.email_verified_at = Some(Local::now());
+        assert!(blocked.can_sign_in(&db_user));
+        assert!(blocked.has_full_access(&db_user));
+    }
+
     #[tokio::test]
     async fn test_login_wrong_password() {
         let mut mock_repo = MockIUsersRepository::new();
@@ -201,11 +611,14 @@ mod tests {
             .times(1)
             .returning(move |_| Ok(Some(db_user.clone())));
 
-        let usecase = UserUsecase::new(Arc::new(mock_repo));
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
 
         let req = LoginRequest {
             email: "test@test.com".to_string(),
             password: "WRONG_PASSWORD".to_string(),
+            remember_me: false,
+            issue_access_token: false,
+            cookieless: false,
         };
 
         let result = usecase.login(req).await;
@@ -219,11 +632,14 @@ mod tests {
 
         mock_repo.expect_login().times(1).returning(|_| Ok(None));
 
-        let usecase = UserUsecase::new(Arc::new(mock_repo));
+        let usecase = usecase(mock_repo, EmailVerificationPolicy::Restrict);
 
         let req = LoginRequest {
             email: "unknown@test.com".to_string(),
             password: "123".to_string(),
+            remember_me: false,
+            issue_access_token: false,
+            cookieless: false,
         };
 
         let result = usecase.login(req).await;